@@ -5,9 +5,21 @@ use crate::{
 };
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use prpr::{config::Config, info::ChartInfo};
+use prpr::{
+    config::{ChallengeModeColor, Config},
+    info::ChartInfo,
+    judge::{JudgePreset, PlayResult},
+    scene::PracticeSection,
+};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, ops::DerefMut, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::DerefMut,
+    path::Path,
+};
+
+const HISTORY_LIMIT: usize = 50;
+const RKS_BEST_NUM: usize = 19;
 
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -63,6 +75,95 @@ pub struct LocalChart {
     #[serde(flatten)]
     pub info: BriefChartInfo,
     pub path: String,
+    /// Content hash of the chart and its music, see [prpr::fs::chart_fingerprint]
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+}
+
+/// Computes the fingerprint of the chart file at `path`.
+pub async fn fingerprint_at(path: &Path) -> Result<String> {
+    let mut fs = prpr::fs::fs_from_file(path)?;
+    let info = prpr::fs::load_info(fs.deref_mut()).await?;
+    prpr::fs::fingerprint(&mut fs, &info).await
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayRecord {
+    pub score: u32,
+    pub accuracy: f64,
+    pub max_combo: u32,
+    pub counts: [u32; 4],
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartRecord {
+    pub best_score: u32,
+    pub best_accuracy: f64,
+    pub full_combo: bool,
+    pub all_perfect: bool,
+    pub play_count: u32,
+    /// The latest plays, oldest first
+    pub history: Vec<PlayRecord>,
+}
+
+impl ChartRecord {
+    pub fn push(&mut self, result: &PlayResult) {
+        self.best_score = self.best_score.max(result.score);
+        self.best_accuracy = self.best_accuracy.max(result.accuracy);
+        self.full_combo |= result.counts[2] + result.counts[3] == 0;
+        self.all_perfect |= result.counts[0] == result.num_of_notes;
+        self.play_count += 1;
+        self.history.push(PlayRecord {
+            score: result.score,
+            accuracy: result.accuracy,
+            max_combo: result.max_combo,
+            counts: result.counts,
+            time: Utc::now(),
+        });
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.drain(..self.history.len() - HISTORY_LIMIT);
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengeRecord {
+    /// Record keys of the charts, in the order they were played
+    pub charts: Vec<String>,
+    pub rank: u32,
+    pub scores: Vec<u32>,
+    /// The tier earned, none if the challenge failed or was aborted
+    pub color: Option<ChallengeModeColor>,
+    pub time: DateTime<Utc>,
+}
+
+/// Ranking score of a single chart, 0 under 70% accuracy.
+pub fn chart_rks(accuracy: f64, difficulty: f32) -> f32 {
+    if accuracy < 0.7 {
+        return 0.;
+    }
+    let p = ((accuracy * 100. - 55.) / 45.) as f32;
+    p * p * difficulty
+}
+
+pub struct RksEntry {
+    pub name: String,
+    pub level: String,
+    pub difficulty: f32,
+    pub accuracy: f64,
+    pub rks: f32,
+}
+
+pub struct Rks {
+    pub value: f32,
+    /// The all-perfect chart with the highest difficulty, counted once more
+    pub phi: Option<RksEntry>,
+    /// The best charts, highest first
+    pub best: Vec<RksEntry>,
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -71,12 +172,28 @@ pub struct Data {
     pub charts: Vec<LocalChart>,
     pub config: Config,
     pub message_check_time: Option<DateTime<Utc>>,
+    /// Play records by chart (see [Data::record_key]) and ruleset
+    #[serde(default)]
+    pub records: HashMap<String, HashMap<JudgePreset, ChartRecord>>,
+    #[serde(default)]
+    pub challenges: Vec<ChallengeRecord>,
+    /// Practice sections by chart (see [Data::record_key])
+    #[serde(default)]
+    pub sections: HashMap<String, Vec<PracticeSection>>,
+    /// Fingerprints of local charts by path, none for charts that failed to load. Charts missing here are yet to be fingerprinted.
+    #[serde(skip)]
+    fingerprints: HashMap<String, Option<String>>,
 }
 
 impl Data {
     pub async fn init(&mut self) -> Result<()> {
         let charts = dir::charts()?;
         self.charts.retain(|it| Path::new(&format!("{}/{}", charts, it.path)).exists());
+        self.fingerprints = self
+            .charts
+            .iter()
+            .filter_map(|it| Some((it.path.clone(), Some(it.fingerprint.clone()?))))
+            .collect();
         let occurred: HashSet<_> = self.charts.iter().map(|it| it.path.clone()).collect();
         for entry in std::fs::read_dir(dir::custom_charts()?)? {
             let entry = entry?;
@@ -92,12 +209,15 @@ impl Data {
             };
             let result = prpr::fs::load_info(fs.deref_mut()).await;
             if let Ok(info) = result {
+                // fingerprinted later on, see Data::set_fingerprint
                 self.charts.push(LocalChart {
                     info: BriefChartInfo { id: None, ..info.into() },
                     path: filename,
+                    fingerprint: None,
                 });
             }
         }
+        self.config.player_rks = self.rks().value;
         if let Some(res_pack_path) = &mut self.config.res_pack_path {
             if res_pack_path.starts_with('/') {
                 // for compatibility
@@ -110,4 +230,183 @@ impl Data {
     pub fn find_chart(&self, chart: &ChartItem) -> Option<usize> {
         self.charts.iter().position(|it| it.path == chart.path)
     }
+
+    pub fn add_chart(&mut self, chart: LocalChart) {
+        if let Some(fingerprint) = &chart.fingerprint {
+            self.fingerprints.insert(chart.path.clone(), Some(fingerprint.clone()));
+        }
+        self.charts.push(chart);
+    }
+
+    pub fn remove_chart(&mut self, index: usize) -> LocalChart {
+        let chart = self.charts.remove(index);
+        self.fingerprints.remove(&chart.path);
+        chart
+    }
+
+    /// The path of a local chart whose fingerprint is yet to be computed, which is done lazily so as not to load every chart at startup.
+    pub fn unfingerprinted(&self) -> Option<&str> {
+        self.charts
+            .iter()
+            .find(|it| !self.fingerprints.contains_key(&it.path))
+            .map(|it| it.path.as_str())
+    }
+
+    /// Stores the fingerprint of the chart at `path` (none if it failed to load), moving the records kept under its path to it. Returns
+    /// false if the chart turned out to be a copy of another one, in which case its file is deleted and its records go to the other one
+    /// unless that has its own.
+    pub fn set_fingerprint(&mut self, path: &str, fingerprint: Option<String>) -> Result<bool> {
+        let Some(index) = self.charts.iter().position(|it| it.path == path) else {
+            return Ok(true);
+        };
+        let old = self.record_key(&self.charts[index].info, path);
+        let duplicate = fingerprint.is_some() && self.charts.iter().any(|it| it.fingerprint == fingerprint);
+        let new = if duplicate {
+            // the same chart under another name
+            let other = self.charts.iter().find(|it| it.fingerprint == fingerprint).unwrap();
+            let new = self.record_key(&other.info, &other.path);
+            self.remove_chart(index);
+            let file = format!("{}/{}", dir::charts()?, path);
+            let file = Path::new(&file);
+            if file.is_dir() {
+                std::fs::remove_dir_all(file)?;
+            } else {
+                std::fs::remove_file(file)?;
+            }
+            new
+        } else {
+            self.charts[index].fingerprint = fingerprint.clone();
+            self.fingerprints.insert(path.to_owned(), fingerprint);
+            self.record_key(&self.charts[index].info, path)
+        };
+        if old != new {
+            if let Some(records) = self.records.remove(&old) {
+                self.records.entry(new.clone()).or_insert(records);
+            }
+            if let Some(sections) = self.sections.remove(&old) {
+                self.sections.entry(new).or_insert(sections);
+            }
+        }
+        Ok(!duplicate)
+    }
+
+    /// Uploaded charts are keyed by their id, so that remote and downloaded copies share records. Other charts are keyed by their
+    /// fingerprint if known, so that records stay with them when they move.
+    pub fn record_key(&self, info: &BriefChartInfo, path: &str) -> String {
+        info.id
+            .clone()
+            .or_else(|| self.fingerprints.get(path).cloned().flatten())
+            .unwrap_or_else(|| path.to_owned())
+    }
+
+    /// The record of `chart` under the current ruleset.
+    pub fn record(&self, chart: &ChartItem) -> Option<&ChartRecord> {
+        self.records
+            .get(&self.record_key(&chart.info, &chart.path))?
+            .get(&self.config.judge_rules)
+    }
+
+    pub fn add_record(&mut self, key: String, result: &PlayResult) {
+        self.records.entry(key).or_default().entry(result.rules).or_default().push(result);
+        self.config.player_rks = self.rks().value;
+    }
+
+    /// Ranking score from the official records of local charts: the mean of the best 19 charts and the best all-perfect one.
+    pub fn rks(&self) -> Rks {
+        let mut entries: Vec<_> = self
+            .charts
+            .iter()
+            .filter_map(|chart| {
+                let record = self
+                    .records
+                    .get(&self.record_key(&chart.info, &chart.path))?
+                    .get(&JudgePreset::Official)?;
+                Some((
+                    record.all_perfect,
+                    RksEntry {
+                        name: chart.info.name.clone(),
+                        level: chart.info.level.clone(),
+                        difficulty: chart.info.difficulty,
+                        accuracy: record.best_accuracy,
+                        rks: chart_rks(record.best_accuracy, chart.info.difficulty),
+                    },
+                ))
+            })
+            .collect();
+        entries.sort_by(|x, y| y.1.rks.total_cmp(&x.1.rks));
+        let phi = entries
+            .iter()
+            .filter(|it| it.0)
+            .max_by(|x, y| x.1.difficulty.total_cmp(&y.1.difficulty))
+            .map(|it| RksEntry {
+                name: it.1.name.clone(),
+                level: it.1.level.clone(),
+                difficulty: it.1.difficulty,
+                accuracy: 1.,
+                rks: it.1.difficulty,
+            });
+        let best: Vec<_> = entries.into_iter().take(RKS_BEST_NUM).map(|it| it.1).collect();
+        let sum = best.iter().map(|it| it.rks).sum::<f32>() + phi.as_ref().map_or(0., |it| it.rks);
+        Rks {
+            value: sum / (RKS_BEST_NUM + 1) as f32,
+            phi,
+            best,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() <= 1e-5 * a.abs().max(1.), "{a} != {b}");
+    }
+
+    fn chart(data: &mut Data, difficulty: f32, preset: JudgePreset, accuracy: f64, all_perfect: bool) {
+        let path = format!("custom/{}", data.charts.len());
+        data.charts.push(LocalChart {
+            info: ChartInfo {
+                name: format!("Chart {difficulty}"),
+                difficulty,
+                ..Default::default()
+            }
+            .into(),
+            path: path.clone(),
+            fingerprint: None,
+        });
+        let record = ChartRecord {
+            best_accuracy: accuracy,
+            all_perfect,
+            ..Default::default()
+        };
+        data.records.entry(path).or_default().insert(preset, record);
+    }
+
+    #[test]
+    fn single_chart() {
+        assert_eq!(chart_rks(0.699, 12.), 0.);
+        assert_close(chart_rks(0.7, 12.), 12. / 9.);
+        assert_close(chart_rks(0.85, 12.), 12. * 4. / 9.);
+        assert_close(chart_rks(1., 12.), 12.);
+    }
+
+    #[test]
+    fn best_charts_and_phi() {
+        let mut data = Data::default();
+        for difficulty in 1..=21 {
+            chart(&mut data, difficulty as f32, JudgePreset::Official, 1., matches!(difficulty, 5 | 8));
+        }
+        // neither counts: one is under 70%, the other was only played with other rules
+        chart(&mut data, 30., JudgePreset::Official, 0.69, false);
+        chart(&mut data, 40., JudgePreset::Strict, 1., true);
+
+        let rks = data.rks();
+        let best: Vec<_> = rks.best.iter().map(|it| it.difficulty).collect();
+        assert_eq!(best, (3..=21).rev().map(|it| it as f32).collect::<Vec<_>>());
+        // the hardest all-perfect chart is counted once more
+        let phi = rks.phi.unwrap();
+        assert_eq!((phi.difficulty, phi.accuracy, phi.rks), (8., 1., 8.));
+        assert_close(rks.value, ((3..=21).sum::<i32>() + 8) as f32 / 20.);
+    }
 }
@@ -251,7 +251,7 @@ pub unsafe extern "C" fn Java_quad_1native_QuadNative_setInputText(_: *mut std::
 #[cfg(target_os = "android")]
 #[no_mangle]
 pub unsafe extern "C" fn Java_quad_1native_QuadNative_setFfmpegPath(_: *mut std::ffi::c_void, _: *const std::ffi::c_void, path: ndk_sys::jstring) {
-    use prpr::scene::FFMPEG_PATH;
+    use prpr::parse::FFMPEG_PATH;
 
     let env = crate::miniquad::native::attach_jni_env();
     *FFMPEG_PATH.lock().unwrap() = Some(string_from_java(env, path).into());
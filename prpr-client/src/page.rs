@@ -13,6 +13,9 @@ pub use message::MessagePage;
 mod remote;
 pub use remote::RemotePage;
 
+mod rks;
+pub use rks::RksPage;
+
 mod settings;
 pub use settings::SettingsPage;
 
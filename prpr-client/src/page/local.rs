@@ -1,14 +1,14 @@
 use super::{get_touched, load_local, trigger_grid, Page, SharedState, CARD_HEIGHT, ROW_NUM, SHOULD_UPDATE};
 use crate::{
-    data::{BriefChartInfo, LocalChart},
-    dir, get_data_mut, save_data,
+    data::{fingerprint_at, BriefChartInfo, LocalChart},
+    dir, get_data, get_data_mut, save_data,
     scene::{ChartOrderBox, CHARTS_BAR_HEIGHT},
     task::Task,
 };
 use anyhow::{Context, Result};
 use macroquad::prelude::*;
 use prpr::{
-    ext::SafeTexture,
+    ext::{poll_future, LocalTask, SafeTexture},
     fs,
     scene::{request_file, return_file, show_error, show_message, take_file},
     ui::{RectButton, Scroll, Ui},
@@ -23,6 +23,10 @@ pub struct LocalPage {
 
     import_button: RectButton,
     import_task: Task<Result<LocalChart>>,
+    // loading the chart to fingerprint it can't be done off the main thread
+    fingerprint_task: LocalTask<(LocalChart, Result<String>)>,
+    // fingerprints the charts that are not yet, one at a time
+    background_task: LocalTask<(String, Option<String>)>,
 }
 
 impl LocalPage {
@@ -35,6 +39,8 @@ impl LocalPage {
 
             import_button: RectButton::new(),
             import_task: Task::pending(),
+            fingerprint_task: None,
+            background_task: None,
         })
     }
 }
@@ -64,6 +70,7 @@ impl Page for LocalPage {
                             ..info.into()
                         },
                         path: format!("custom/{name}"),
+                        fingerprint: None,
                     })
                 }
                 self.import_task = Task::new(import(file));
@@ -77,13 +84,50 @@ impl Page for LocalPage {
                     show_error(err.context("Import failure"));
                 }
                 Ok(chart) => {
-                    get_data_mut().charts.push(chart);
+                    let path = format!("{}/{}", dir::charts()?, chart.path);
+                    self.fingerprint_task = Some(Box::pin(async move {
+                        let fingerprint = fingerprint_at(Path::new(&path)).await;
+                        (chart, fingerprint)
+                    }));
+                }
+            }
+        }
+        if let Some(task) = &mut self.fingerprint_task {
+            if let Some((mut chart, fingerprint)) = poll_future(task.as_mut()) {
+                self.fingerprint_task = None;
+                // charts that fail to load are still imported, as before
+                let fingerprint = fingerprint.ok();
+                if let Some(other) = get_data().charts.iter().find(|it| fingerprint.is_some() && it.fingerprint == fingerprint) {
+                    let _ = std::fs::remove_file(format!("{}/{}", dir::charts()?, chart.path));
+                    show_message(format!("This chart has already been imported as {}", other.info.name));
+                } else {
+                    chart.fingerprint = fingerprint;
+                    get_data_mut().add_chart(chart);
                     save_data()?;
                     state.charts_local = load_local(&state.tex, self.order_box.to_order());
                     show_message("Imported successfully");
                 }
             }
         }
+        if self.background_task.is_none() && self.fingerprint_task.is_none() {
+            if let Some(path) = get_data().unfingerprinted() {
+                let path = path.to_owned();
+                let file = format!("{}/{}", dir::charts()?, path);
+                self.background_task = Some(Box::pin(async move {
+                    let fingerprint = fingerprint_at(Path::new(&file)).await.ok();
+                    (path, fingerprint)
+                }));
+            }
+        }
+        if let Some(task) = &mut self.background_task {
+            if let Some((path, fingerprint)) = poll_future(task.as_mut()) {
+                self.background_task = None;
+                if !get_data_mut().set_fingerprint(&path, fingerprint)? {
+                    state.charts_local = load_local(&state.tex, self.order_box.to_order());
+                }
+                save_data()?;
+            }
+        }
         Ok(())
     }
 
@@ -0,0 +1,98 @@
+use super::{Page, SharedState, SIDE_PADDING};
+use crate::{
+    data::{Rks, RksEntry},
+    get_data,
+};
+use anyhow::Result;
+use macroquad::prelude::*;
+use prpr::ui::{Scroll, Ui};
+
+pub struct RksPage {
+    scroll: Scroll,
+    rks: Option<Rks>,
+}
+
+impl RksPage {
+    pub fn new() -> Self {
+        Self {
+            scroll: Scroll::new(),
+            rks: None,
+        }
+    }
+
+    fn row(ui: &mut Ui, width: f32, rank: &str, entry: &RksEntry) -> f32 {
+        let h = 0.07;
+        let r = Rect::new(0., 0., width, h);
+        ui.fill_rect(r, Color::new(1., 1., 1., 0.1));
+        let text = |ui: &mut Ui, x: f32, anchor: f32, s: &str| {
+            ui.text(s)
+                .pos(x, h / 2.)
+                .anchor(anchor, 0.5)
+                .no_baseline()
+                .size(0.45)
+                .max_width(width * 0.5)
+                .draw();
+        };
+        text(ui, 0.02, 0., rank);
+        text(ui, 0.12, 0., &entry.name);
+        text(ui, width - 0.42, 1., &format!("{} {:.1}", entry.level, entry.difficulty));
+        text(ui, width - 0.2, 1., &format!("{:.2}%", entry.accuracy * 100.));
+        text(ui, width - 0.02, 1., &format!("{:.2}", entry.rks));
+        h
+    }
+}
+
+impl Page for RksPage {
+    fn label(&self) -> &'static str {
+        "RKS"
+    }
+
+    fn update(&mut self, focus: bool, state: &mut SharedState) -> Result<()> {
+        if !focus {
+            self.rks = None;
+        } else if self.rks.is_none() {
+            self.rks = Some(get_data().rks());
+        }
+        self.scroll.update(state.t);
+        Ok(())
+    }
+
+    fn touch(&mut self, touch: &Touch, state: &mut SharedState) -> Result<bool> {
+        if self.scroll.touch(touch, state.t) {
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn render(&mut self, ui: &mut Ui, state: &mut SharedState) -> Result<()> {
+        let Some(rks) = &self.rks else {
+            return Ok(());
+        };
+        let width = (1. - SIDE_PADDING) * 2. - 0.04;
+        ui.dx(0.02);
+        ui.dy(0.01);
+        self.scroll.size(state.content_size);
+        self.scroll.render(ui, |ui| {
+            let r = ui
+                .text(format!("RKS {:.2}\nThe best 19 charts plus the best all-perfect one, averaged over 20, from official records", rks.value))
+                .multiline()
+                .max_width(width)
+                .size(0.55)
+                .draw();
+            let mut h = r.h + 0.03;
+            ui.dy(r.h + 0.03);
+            let rows = rks
+                .phi
+                .iter()
+                .map(|it| ("φ".to_owned(), it))
+                .chain(rks.best.iter().enumerate().map(|(i, it)| (format!("#{}", i + 1), it)));
+            for (rank, entry) in rows {
+                let dh = Self::row(ui, width, &rank, entry) + 0.01;
+                ui.dy(dh);
+                h += dh;
+            }
+            (width, h)
+        });
+        Ok(())
+    }
+}
@@ -5,13 +5,15 @@ use macroquad::prelude::*;
 use prpr::{
     core::{ParticleEmitter, ResourcePack, JUDGE_LINE_PERFECT_COLOR, NOTE_WIDTH_RATIO_BASE},
     ext::{create_audio_manger, poll_future, LocalTask, RectExt, SafeTexture},
+    judge::robust_median,
     scene::{request_file, return_file, show_error, show_message, take_file},
     time::TimeManager,
-    ui::{RectButton, Ui},
+    ui::{Dialog, RectButton, Ui},
 };
 use sasa::{AudioClip, AudioManager, Music, MusicParams, PlaySfxParams, Sfx};
 
 const RESET_WAIT: f32 = 0.8;
+const CALI_TAPS: usize = 10;
 
 pub struct SettingsPage {
     focus: bool,
@@ -21,6 +23,9 @@ pub struct SettingsPage {
     cali_hit: Sfx,
     cali_tm: TimeManager,
     cali_last: bool,
+    cali_button: RectButton,
+    // tap times collected by auto calibration, if running
+    cali_taps: Option<Vec<f32>>,
     click_texture: SafeTexture,
     emitter: ParticleEmitter,
     res_pack: ResourcePack, // prevent resource pack textures from being destroyed (ParticleEmitter holds a `weak` reference)
@@ -56,6 +61,8 @@ impl SettingsPage {
             cali_hit,
             cali_tm,
             cali_last: false,
+            cali_button: RectButton::new(),
+            cali_taps: None,
             click_texture: res_pack.note_style.click.clone(),
             emitter,
             res_pack,
@@ -100,6 +107,7 @@ impl Page for SettingsPage {
         if self.focus && !focus {
             save_data()?;
             self.cali.pause()?;
+            self.cali_taps = None;
         }
         self.focus = focus;
 
@@ -147,6 +155,37 @@ impl Page for SettingsPage {
     }
 
     fn touch(&mut self, touch: &Touch, _state: &mut SharedState) -> Result<bool> {
+        if self.cali_button.touch(touch) {
+            self.cali_taps = if self.cali_taps.is_some() { None } else { Some(Vec::new()) };
+            return Ok(true);
+        }
+        if let Some(taps) = &mut self.cali_taps {
+            if touch.phase == TouchPhase::Started {
+                // the beat of the calibration music is at 1s of its 2s loop
+                let mut diff = (self.cali_tm.now() as f32 - 1.).rem_euclid(2.);
+                if diff >= 1. {
+                    diff -= 2.;
+                }
+                taps.push(diff);
+                if taps.len() >= CALI_TAPS {
+                    let offset = robust_median(taps).unwrap();
+                    let offset = ((offset / 0.005).round() * 0.005).clamp(-0.5, 0.5);
+                    self.cali_taps = None;
+                    Dialog::plain("Auto calibration", format!("Suggested offset: {:.0}ms", offset * 1000.))
+                        .buttons(vec!["Cancel".to_owned(), "Apply".to_owned()])
+                        .listener(move |pos| {
+                            if pos == 1 {
+                                get_data_mut().config.offset = offset;
+                                if let Err(err) = save_data() {
+                                    show_error(err.context("Failed to save"));
+                                }
+                            }
+                        })
+                        .show();
+                }
+            }
+            return Ok(true);
+        }
         for (id, button) in self.chal_buttons.iter_mut().enumerate() {
             if button.touch(touch) {
                 use prpr::config::ChallengeModeColor::*;
@@ -183,8 +222,6 @@ impl Page for SettingsPage {
                 let r = ui.checkbox("Low Performance Mode", &mut low);
                 config.sample_count = if low { 1 } else { 4 };
                 ui.dy(r.h + s);
-                let r = ui.slider("Player RKS", 1.0..17.0, 0.01, &mut config.player_rks, Some(0.45));
-                ui.dy(r.h + s);
             });
             ui.dx(0.62);
 
@@ -286,6 +323,20 @@ impl Page for SettingsPage {
             let ct = (0.9, ui.top * 1.3);
             let len = 0.25;
             ui.fill_rect(Rect::new(ct.0 - len, ct.1 - 0.005, len * 2., 0.01), WHITE);
+            ui.scope(|ui| {
+                ui.dx(ct.0);
+                ui.dy(ct.1 + 0.05);
+                let text = if let Some(taps) = &self.cali_taps {
+                    format!("Tap to the beat ({}/{CALI_TAPS})", taps.len())
+                } else {
+                    "Auto calibrate".to_owned()
+                };
+                let r = ui.text(&text).size(0.4).anchor(0.5, 0.).measure().feather(0.01);
+                self.cali_button.set(ui, r);
+                ui.fill_rect(r, if self.cali_taps.is_some() { ui.accent() } else { WHITE });
+                let color = if self.cali_taps.is_some() { WHITE } else { ui.accent() };
+                ui.text(text).size(0.4).anchor(0.5, 0.).color(color).draw();
+            });
             let mut cali_t = self.cali_tm.now() as f32 - config.offset;
             if cali_t < 0. {
                 cali_t += 2.;
@@ -4,5 +4,8 @@ pub use main::{MainScene, CHARTS_BAR_HEIGHT};
 mod song;
 pub use song::SongScene;
 
+mod challenge;
+pub use challenge::ChallengeScene;
+
 mod chart_order;
 pub use chart_order::{ChartOrder, ChartOrderBox};
@@ -0,0 +1,249 @@
+use super::song::{fs_from_path, game_config};
+use crate::{
+    cloud::UserManager,
+    data::{BriefChartInfo, ChallengeRecord},
+    get_data, get_data_mut, save_data,
+};
+use anyhow::Result;
+use chrono::Utc;
+use macroquad::prelude::*;
+use prpr::{
+    config::{ChallengeModeColor, Config},
+    ext::{poll_future, screen_aspect, LocalTask},
+    info::ChartInfo,
+    judge::PlayResult,
+    scene::{show_error, show_message, GameMode, LoadingScene, NextScene, Scene, CARRIED_COMBO, RESULT_LISTENER},
+    time::TimeManager,
+    ui::Ui,
+};
+use std::sync::{atomic::Ordering, Arc, Mutex};
+
+pub const CHALLENGE_NUM: usize = 3;
+/// Charts picked for the next challenge: (path, brief info, info).
+pub static CHALLENGE_CHARTS: Mutex<Vec<(String, BriefChartInfo, ChartInfo)>> = Mutex::new(Vec::new());
+
+const PASS_SCORE: u32 = 700_000;
+
+/// The tier earned by passing a challenge of `rank`, i.e. the summed difficulties of its charts.
+pub fn challenge_color(rank: u32) -> ChallengeModeColor {
+    use ChallengeModeColor::*;
+    match rank {
+        0..=20 => Green,
+        21..=30 => Blue,
+        31..=40 => Red,
+        41..=45 => Golden,
+        _ => Rainbow,
+    }
+}
+
+/// Plays the picked charts back to back, carrying the combo over from one to the next. Only the first finished run of each chart counts,
+/// and leaving one aborts the challenge. Every chart has to score [PASS_SCORE] for the challenge to pass.
+pub struct ChallengeScene {
+    charts: Vec<(String, BriefChartInfo, ChartInfo)>,
+    rank: u32,
+    results: Vec<PlayResult>,
+    // the combo carried into the next chart
+    combo: u32,
+    // the result of the chart being played, filled in by the result listener
+    current: Arc<Mutex<Option<PlayResult>>>,
+    playing: bool,
+    color: Option<Option<ChallengeModeColor>>,
+
+    scene_task: LocalTask<Result<LoadingScene>>,
+    target: Option<RenderTarget>,
+    next_scene: Option<NextScene>,
+}
+
+impl ChallengeScene {
+    pub fn new(charts: Vec<(String, BriefChartInfo, ChartInfo)>) -> Self {
+        Self {
+            rank: charts.iter().map(|it| it.2.difficulty as u32).sum(),
+            charts,
+            results: Vec::new(),
+            combo: 0,
+            current: Arc::default(),
+            playing: false,
+            color: None,
+
+            scene_task: None,
+            target: None,
+            next_scene: None,
+        }
+    }
+
+    fn start_next(&mut self) -> Result<()> {
+        let (path, _, info) = &self.charts[self.results.len()];
+        let fs = fs_from_path(path)?;
+        let info = info.clone();
+        let config = Config {
+            autoplay: false,
+            challenge_rank: self.rank,
+            ..game_config()
+        };
+        *self.current.lock().unwrap() = None;
+        CARRIED_COMBO.store(self.combo, Ordering::SeqCst);
+        let current = Arc::clone(&self.current);
+        *RESULT_LISTENER.lock().unwrap() = Some(Box::new(move |result: &PlayResult| {
+            current.lock().unwrap().get_or_insert_with(|| result.clone());
+        }));
+        self.scene_task = Some(Box::pin(async move {
+            LoadingScene::new(GameMode::Normal, info, config, fs, get_data().me.as_ref().and_then(|it| UserManager::get_avatar(&it.id)), None).await
+        }));
+        self.playing = true;
+        Ok(())
+    }
+
+    fn stop() {
+        *RESULT_LISTENER.lock().unwrap() = None;
+        CARRIED_COMBO.store(0, Ordering::SeqCst);
+    }
+
+    // record keys of the charts played so far
+    fn played_keys(&self) -> Vec<String> {
+        let data = get_data();
+        self.charts[..self.results.len()]
+            .iter()
+            .map(|(path, brief, _)| data.record_key(brief, path))
+            .collect()
+    }
+
+    /// Records a run of the challenge, failed (and cut short if aborted) if `color` is none.
+    fn record(&self, color: Option<ChallengeModeColor>) -> Result<()> {
+        let keys = self.played_keys();
+        let data = get_data_mut();
+        data.challenges.push(ChallengeRecord {
+            charts: keys,
+            rank: self.rank,
+            scores: self.results.iter().map(|it| it.score).collect(),
+            color: color.clone(),
+            time: Utc::now(),
+        });
+        if let Some(color) = &color {
+            // keep the best badge: a higher tier first, then a higher rank
+            let config = &mut data.config;
+            if (color.clone() as usize, self.rank) > (config.challenge_color.clone() as usize, config.challenge_rank) {
+                config.challenge_color = color.clone();
+                config.challenge_rank = self.rank;
+            }
+        }
+        save_data()
+    }
+
+    fn abort(&mut self) -> Result<()> {
+        Self::stop();
+        self.next_scene = Some(NextScene::Pop);
+        self.record(None)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Self::stop();
+        let color = self.results.iter().all(|it| it.score >= PASS_SCORE).then(|| challenge_color(self.rank));
+        self.record(color.clone())?;
+        self.color = Some(color);
+        Ok(())
+    }
+}
+
+impl Scene for ChallengeScene {
+    fn enter(&mut self, _tm: &mut TimeManager, target: Option<RenderTarget>) -> Result<()> {
+        self.target = target;
+        if self.playing {
+            self.playing = false;
+            match self.current.lock().unwrap().take() {
+                Some(result) => {
+                    // a bad or a miss breaks the carried combo too
+                    self.combo = if result.counts[2] + result.counts[3] == 0 {
+                        self.combo + result.combo
+                    } else {
+                        result.combo
+                    };
+                    // recorded right away, as a normal run would be
+                    let (path, brief, _) = &self.charts[self.results.len()];
+                    get_data_mut().add_record(get_data().record_key(brief, path), &result);
+                    self.results.push(result);
+                    save_data()?;
+                }
+                None => {
+                    show_message("Challenge aborted");
+                    return self.abort();
+                }
+            }
+        }
+        if self.results.len() == self.charts.len() {
+            if self.color.is_none() {
+                self.finish()?;
+            }
+        } else if let Err(err) = self.start_next() {
+            show_error(err.context("Failed to load the beatmap"));
+            self.abort()?;
+        }
+        Ok(())
+    }
+
+    fn on_result(&mut self, _tm: &mut TimeManager, result: Box<dyn std::any::Any>) -> Result<()> {
+        if let Ok(error) = result.downcast::<anyhow::Error>() {
+            show_error(error.context("Failed to load the beatmap"));
+        }
+        Ok(())
+    }
+
+    fn touch(&mut self, _tm: &mut TimeManager, touch: &Touch) -> Result<bool> {
+        if self.color.is_some() && touch.phase == TouchPhase::Started {
+            self.next_scene = Some(NextScene::Pop);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn update(&mut self, _tm: &mut TimeManager) -> Result<()> {
+        if let Some(future) = &mut self.scene_task {
+            if let Some(scene) = poll_future(future.as_mut()) {
+                self.scene_task = None;
+                self.next_scene = Some(NextScene::Overlay(Box::new(scene?)));
+            }
+        }
+        Ok(())
+    }
+
+    fn render(&mut self, _tm: &mut TimeManager, ui: &mut Ui) -> Result<()> {
+        set_camera(&Camera2D {
+            zoom: vec2(1., -screen_aspect()),
+            render_target: self.target,
+            ..Default::default()
+        });
+        clear_background(BLACK);
+        let top = ui.top;
+        let mut y = -top + 0.1;
+        let r = ui.text(format!("Challenge mode · Rank {}", self.rank)).pos(-0.9, y).size(0.9).draw();
+        ui.text(format!("Combo {}", self.combo)).pos(0.9, y).anchor(1., 0.).size(0.6).draw();
+        y += r.h + 0.06;
+        for (index, (_, _, info)) in self.charts.iter().enumerate() {
+            let status = match self.results.get(index) {
+                Some(result) => format!("{:07} ({:.2}%)", result.score, result.accuracy * 100.),
+                None => "-".to_owned(),
+            };
+            ui.text(format!("{}. {} ({})", index + 1, info.name, info.level))
+                .pos(-0.9, y)
+                .size(0.6)
+                .draw();
+            let r = ui.text(status).pos(0.9, y).anchor(1., 0.).size(0.6).draw();
+            y += r.h + 0.04;
+        }
+        if let Some(color) = &self.color {
+            let text = match color {
+                Some(color) => {
+                    let tier = ["White", "Green", "Blue", "Red", "Golden", "Rainbow"][color.clone() as usize];
+                    format!("Challenge passed: {tier} {}", self.rank)
+                }
+                None => format!("Challenge failed: every chart needs {PASS_SCORE} points"),
+            };
+            ui.text(text).pos(-0.9, y + 0.06).size(0.7).draw();
+            ui.text("Tap to return").pos(0., top - 0.08).anchor(0.5, 1.).size(0.5).draw();
+        }
+        Ok(())
+    }
+
+    fn next_scene(&mut self, _tm: &mut TimeManager) -> NextScene {
+        self.next_scene.take().unwrap_or_default()
+    }
+}
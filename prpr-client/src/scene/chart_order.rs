@@ -1,4 +1,4 @@
-use crate::page::ChartItem;
+use crate::{get_data, page::ChartItem};
 use macroquad::prelude::*;
 use prpr::{
     ext::{RectExt, SafeTexture},
@@ -10,6 +10,8 @@ use super::main::CHARTS_BAR_HEIGHT;
 pub enum ChartOrder {
     Default,
     Name,
+    /// Best score under the current ruleset, highest first
+    Score,
 }
 
 impl ChartOrder {
@@ -25,17 +27,30 @@ impl ChartOrder {
             Self::Name => {
                 charts.sort_by(|x, y| f(x).info.name.cmp(&f(y).info.name));
             }
+            Self::Score => {
+                let data = get_data();
+                charts.sort_by_cached_key(|it| std::cmp::Reverse(data.record(f(it)).map_or(0, |it| it.best_score)));
+            }
         }
     }
 }
 
-const ORDER_NUM: usize = 4;
-const ORDER_LABELS: [&str; ORDER_NUM] = ["From new to old", "From old to new", "Alphabetical order", "Reverse alphabetical order"];
+const ORDER_NUM: usize = 6;
+const ORDER_LABELS: [&str; ORDER_NUM] = [
+    "From new to old",
+    "From old to new",
+    "Alphabetical order",
+    "Reverse alphabetical order",
+    "Highest score first",
+    "Lowest score first",
+];
 static ORDERS: [(ChartOrder, bool); ORDER_NUM] = [
     (ChartOrder::Default, false),
     (ChartOrder::Default, true),
     (ChartOrder::Name, false),
     (ChartOrder::Name, true),
+    (ChartOrder::Score, false),
+    (ChartOrder::Score, true),
 ];
 
 pub struct ChartOrderBox {
@@ -23,7 +23,7 @@ use std::sync::{
     Mutex,
 };
 
-const PAGE_NUM: usize = 6;
+const PAGE_NUM: usize = 7;
 const SIDE_PADDING: f32 = 0.02;
 const CARD_PADDING: f32 = 0.02;
 pub const CHARTS_BAR_HEIGHT: f32 = 0.06;
@@ -90,6 +90,7 @@ impl MainScene {
             pages: [
                 Box::new(page::LocalPage::new(icon_play.clone()).await?),
                 Box::new(page::RemotePage::new(icon_play)),
+                Box::new(page::RksPage::new()),
                 Box::new(page::AccountPage::new()),
                 Box::new(page::MessagePage::new()),
                 Box::new(page::SettingsPage::new().await?),
@@ -311,7 +312,7 @@ impl Scene for MainScene {
                         } else {
                             std::fs::remove_dir_all(path)?;
                         }
-                        get_data_mut().charts.remove(get_data().find_chart(chart).unwrap());
+                        get_data_mut().remove_chart(get_data().find_chart(chart).unwrap());
                         save_data()?;
                         self.shared_state.charts_local.remove(id);
                         Ok(())
@@ -1,4 +1,8 @@
-use super::main::{UPDATE_INFO, UPDATE_REMOTE_TEXTURE, UPDATE_TEXTURE};
+use super::{
+    challenge::{CHALLENGE_CHARTS, CHALLENGE_NUM},
+    main::{UPDATE_INFO, UPDATE_REMOTE_TEXTURE, UPDATE_TEXTURE},
+    ChallengeScene,
+};
 use crate::{
     cloud::{Client, Images, LCChartItem, LCFile, Pointer, UserManager},
     data::{BriefChartInfo, LocalChart},
@@ -16,9 +20,12 @@ use prpr::{
     config::Config,
     core::Tweenable,
     ext::{poll_future, screen_aspect, JoinToString, LocalTask, RectExt, SafeTexture, ScaleType, BLACK_TEXTURE},
-    fs::{self, update_zip, FileSystem, ZipFileSystem},
+    fs::{self, FileSystem, ZipFileSystem},
     info::ChartInfo,
-    scene::{show_error, show_message, GameMode, LoadingScene, NextScene, Scene},
+    judge::PlayResult,
+    parse::load_chart,
+    scene::{show_error, show_message, GameMode, LoadingScene, NextScene, PracticeSection, Scene, PRACTICE_SECTIONS, RESULT_LISTENER},
+    stats::{chart_stats, ChartStats},
     time::TimeManager,
     ui::{render_chart_info, ChartInfoEdit, Dialog, RectButton, Scroll, Ui},
 };
@@ -40,7 +47,7 @@ const EDIT_CHART_INFO_WIDTH: f32 = 0.7;
 static CONFIRM_UPLOAD: AtomicBool = AtomicBool::new(false);
 static UPLOAD_STATUS: Mutex<Option<String>> = Mutex::new(None);
 
-fn fs_from_path(path: &str) -> Result<Box<dyn FileSystem>> {
+pub(super) fn fs_from_path(path: &str) -> Result<Box<dyn FileSystem>> {
     if let Some(name) = path.strip_prefix(':') {
         fs::fs_from_assets(format!("charts/{name}/"))
     } else {
@@ -48,6 +55,19 @@ fn fs_from_path(path: &str) -> Result<Box<dyn FileSystem>> {
     }
 }
 
+/// The config to play with, filled in with the player.
+pub(super) fn game_config() -> Config {
+    Config {
+        player_name: get_data().me.as_ref().map(|it| it.name.clone()).unwrap_or_else(|| "Guest".to_string()),
+        res_pack_path: get_data()
+            .config
+            .res_pack_path
+            .as_ref()
+            .map(|it| format!("{}/{it}", dir::root().unwrap())),
+        ..get_data().config.clone()
+    }
+}
+
 pub struct TrashBin {
     icon_delete: SafeTexture,
     icon_question: SafeTexture,
@@ -139,6 +159,8 @@ pub struct SongScene {
     illustration_task: Option<Task<Result<(DynamicImage, Option<DynamicImage>)>>>,
     remote_illustration_task: Option<Task<Result<DynamicImage>>>,
     chart_info: Option<ChartInfo>,
+    stats_task: LocalTask<Result<ChartStats>>,
+    stats: Option<ChartStats>,
     scene_task: LocalTask<Result<LoadingScene>>,
 
     target: Option<RenderTarget>,
@@ -220,6 +242,8 @@ impl SongScene {
             remote_illustration_task: lc_file.map(|file| Task::new(async move { Images::load_lc(&file).await })),
 
             chart_info: None,
+            stats_task: None,
+            stats: None,
             scene_task: None,
 
             target: None,
@@ -336,6 +360,33 @@ impl SongScene {
                     .draw();
                 ui.dy(r.h + 0.02);
                 sy += r.h + 0.02;
+                if let Some(stats) = &self.stats {
+                    let h = Self::render_stats(ui, stats);
+                    ui.dy(h + 0.02);
+                    sy += h + 0.02;
+                }
+                if let Some(record) = get_data().record(&self.chart) {
+                    let flag = if record.all_perfect {
+                        " AP"
+                    } else if record.full_combo {
+                        " FC"
+                    } else {
+                        ""
+                    };
+                    let r = ui
+                        .text(format!(
+                            "Best：{:07} ({:.2}%){flag}\nPlayed {} times",
+                            record.best_score,
+                            record.best_accuracy * 100.,
+                            record.play_count
+                        ))
+                        .multiline()
+                        .size(0.5)
+                        .color(Color::new(1., 1., 1., 0.77))
+                        .draw();
+                    ui.dy(r.h + 0.02);
+                    sy += r.h + 0.02;
+                }
                 (2., sy + 0.06)
             });
         });
@@ -362,6 +413,44 @@ impl SongScene {
         }
     }
 
+    /// Draws the note statistics and the NPS graph, returning the height taken.
+    fn render_stats(ui: &mut Ui, stats: &ChartStats) -> f32 {
+        let color = Color::new(1., 1., 1., 0.77);
+        let notes = &stats.notes;
+        let r = ui
+            .text(format!(
+                "Notes：{} (Tap {} · Drag {} · Hold {} · Flick {}) · Fake {}\nNPS：{:.2} average · {:.0} peak · {} chords · {:.0}% held\nEstimated difficulty：{:.1}",
+                notes.total(),
+                notes.click,
+                notes.drag,
+                notes.hold,
+                notes.flick,
+                stats.fake,
+                stats.average_nps,
+                stats.peak_nps,
+                stats.chords,
+                stats.hold_coverage * 100.,
+                stats.difficulty
+            ))
+            .multiline()
+            .max_width(2. - 0.06 * 2.)
+            .size(0.5)
+            .color(color)
+            .draw();
+        let max = stats.nps.iter().copied().fold(0., f32::max);
+        if max <= 0. {
+            return r.h;
+        }
+        let (width, height) = (2. - 0.06 * 2., 0.12);
+        let top = r.h + 0.02;
+        let w = width / stats.nps.len() as f32;
+        for (i, nps) in stats.nps.iter().enumerate() {
+            let h = nps / max * height;
+            ui.fill_rect(Rect::new(i as f32 * w, top + height - h, w, h), color);
+        }
+        top + height
+    }
+
     fn side_tools(&mut self, ui: &mut Ui) {
         let pad = 0.03;
         let width = EDIT_CHART_INFO_WIDTH - pad;
@@ -373,9 +462,42 @@ impl SongScene {
             self.play_chart(GameMode::TweakOffset).unwrap();
         }
         ui.dy(r.h + 0.01);
-        if ui.button("exercise", r, "Segmented exercises") {
+        if ui.button("calibrate", r, "Calibrate offset by playing") {
+            self.play_chart(GameMode::Calibrate).unwrap();
+        }
+        ui.dy(r.h + 0.01);
+        if ui.button("exercise", r, "Practice sections") {
             self.play_chart(GameMode::Exercise).unwrap();
         }
+        ui.dy(r.h + 0.01);
+        let num = CHALLENGE_CHARTS.lock().unwrap().len();
+        if ui.button("challenge", r, format!("Add to challenge ({num}/{CHALLENGE_NUM})")) {
+            self.add_to_challenge();
+        }
+        if num != 0 {
+            ui.dy(r.h + 0.01);
+            if ui.button("clear_challenge", r, "Clear challenge") {
+                CHALLENGE_CHARTS.lock().unwrap().clear();
+            }
+        }
+    }
+
+    fn add_to_challenge(&mut self) {
+        let Some(info) = self.chart_info.clone() else {
+            show_message("Not yet finished loading");
+            return;
+        };
+        let mut charts = CHALLENGE_CHARTS.lock().unwrap();
+        if charts.iter().any(|it| it.0 == self.chart.path) {
+            show_message("This chart is already in the challenge");
+            return;
+        }
+        charts.push((self.chart.path.clone(), self.chart.info.clone(), info));
+        if charts.len() == CHALLENGE_NUM {
+            self.next_scene = Some(NextScene::Overlay(Box::new(ChallengeScene::new(std::mem::take(&mut *charts)))));
+        } else {
+            show_message(format!("Added to challenge ({}/{CHALLENGE_NUM})", charts.len()));
+        }
     }
 
     fn side_chart_info(&mut self, ui: &mut Ui, t: f32) {
@@ -457,11 +579,9 @@ impl SongScene {
             self.save_task = Some(Task::new(async move {
                 let mut fs = fs_from_path(&path)?;
                 let patches = edit.to_patches().await.context("Failed to load file")?;
+                fs.write_files(patches).await.context("Failed to save file")?;
                 if let Some(zip) = fs.as_any().downcast_mut::<ZipFileSystem>() {
-                    let bytes = update_zip(&mut zip.0.lock().unwrap(), patches).context("Failed to write to configuration file")?;
-                    std::fs::write(format!("{}/{}", dir::charts()?, path), bytes).context("Failed to save file")?;
-                } else {
-                    unreachable!();
+                    zip.save(format!("{}/{}", dir::charts()?, path)).context("Failed to save file")?;
                 }
                 Ok(())
             }));
@@ -491,6 +611,7 @@ impl SongScene {
         let chart = LocalChart {
             info: self.chart.info.clone(),
             path,
+            fingerprint: None,
         };
         let progress = Arc::new(Mutex::new(0.));
         let prog_cl = Arc::clone(&progress);
@@ -526,24 +647,24 @@ impl SongScene {
         }
         let fs = fs_from_path(&self.chart.path)?;
         let info = self.chart_info.clone().unwrap();
+        if mode == GameMode::Exercise {
+            *PRACTICE_SECTIONS.lock().unwrap() = get_data()
+                .sections
+                .get(&get_data().record_key(&self.chart.info, &self.chart.path))
+                .cloned()
+                .unwrap_or_default();
+        }
+        if mode == GameMode::Normal {
+            let key = get_data().record_key(&self.chart.info, &self.chart.path);
+            *RESULT_LISTENER.lock().unwrap() = Some(Box::new(move |result: &PlayResult| {
+                get_data_mut().add_record(key.clone(), result);
+                if let Err(err) = save_data() {
+                    show_error(err.context("Failed to save the record"));
+                }
+            }));
+        }
         self.scene_task = Some(Box::pin(async move {
-            LoadingScene::new(
-                mode,
-                info,
-                Config {
-                    player_name: get_data().me.as_ref().map(|it| it.name.clone()).unwrap_or_else(|| "Guest".to_string()),
-                    res_pack_path: get_data()
-                        .config
-                        .res_pack_path
-                        .as_ref()
-                        .map(|it| format!("{}/{it}", dir::root().unwrap())),
-                    ..get_data().config.clone()
-                },
-                fs,
-                get_data().me.as_ref().and_then(|it| UserManager::get_avatar(&it.id)),
-                None,
-            )
-            .await
+            LoadingScene::new(mode, info, game_config(), fs, get_data().me.as_ref().and_then(|it| UserManager::get_avatar(&it.id)), None).await
         }));
         Ok(())
     }
@@ -558,7 +679,7 @@ impl Scene for SongScene {
             }
             Err(res) => res,
         };
-        let _result = match result.downcast::<Option<f32>>() {
+        let result = match result.downcast::<Option<f32>>() {
             Ok(offset) => {
                 if let Some(offset) = *offset {
                     self.chart_info.as_mut().unwrap().offset = offset;
@@ -569,11 +690,22 @@ impl Scene for SongScene {
             }
             Err(res) => res,
         };
+        let _result = match result.downcast::<Vec<PracticeSection>>() {
+            Ok(sections) => {
+                let key = get_data().record_key(&self.chart.info, &self.chart.path);
+                get_data_mut().sections.insert(key, *sections);
+                save_data()?;
+                return Ok(());
+            }
+            Err(res) => res,
+        };
         Ok(())
     }
 
     fn enter(&mut self, tm: &mut TimeManager, target: Option<RenderTarget>) -> Result<()> {
         self.target = target;
+        // back from the game, whose records must not go to whatever is played next
+        *RESULT_LISTENER.lock().unwrap() = None;
         if self.first_in {
             self.first_in = false;
             tm.seek_to(-FADEIN_TIME as _);
@@ -664,10 +796,26 @@ impl Scene for SongScene {
         if let Some(task) = &mut self.info_task {
             if let Some(info) = task.take() {
                 self.update_chart_info(info.clone().into());
+                let path = self.chart.path.clone();
+                let chart_info = info.clone();
+                self.stats_task = Some(Box::pin(async move {
+                    let mut fs = fs_from_path(&path)?;
+                    let chart = load_chart(&mut fs, &chart_info).await?;
+                    Ok(chart_stats(&chart))
+                }));
                 self.chart_info = Some(info);
                 self.info_task = None;
             }
         }
+        if let Some(task) = &mut self.stats_task {
+            if let Some(result) = poll_future(task.as_mut()) {
+                match result {
+                    Err(err) => show_error(err.context("Failed to compute chart statistics")),
+                    Ok(stats) => self.stats = Some(stats),
+                }
+                self.stats_task = None;
+            }
+        }
         if let Some(task) = &mut self.save_task {
             if let Some(result) = task.take() {
                 if let Err(err) = result {
@@ -774,7 +922,7 @@ impl Scene for SongScene {
                     self.chart.info = chart.info.clone();
                     self.chart.path = chart.path.clone();
                     self.info_task = Some(create_info_task(chart.path.clone(), chart.info.clone()));
-                    get_data_mut().charts.push(chart);
+                    get_data_mut().add_chart(chart);
                     save_data()?;
                     SHOULD_UPDATE.store(true, Ordering::SeqCst);
                     self.remote = false;
@@ -0,0 +1,158 @@
+use anyhow::{bail, Context, Result};
+use macroquad::prelude::*;
+use prpr::{
+    build_conf,
+    core::{AnimFloat, Chart, JudgeLineKind},
+    fs,
+    info::ChartFormat,
+    parse::{export_pec, export_phigros, export_rpe, load_chart},
+};
+use std::ops::DerefMut;
+
+#[cfg(target_arch = "wasm32")]
+compile_error!("WASM target is not supported");
+
+fn negative(anim: &AnimFloat) -> bool {
+    anim.keyframes.iter().any(|it| it.value < 0.) || anim.next.as_ref().map_or(false, |it| negative(it))
+}
+
+fn animated(anim: &AnimFloat) -> bool {
+    anim.keyframes.len() > 1 || anim.next.is_some()
+}
+
+fn eased(anim: &AnimFloat) -> bool {
+    anim.keyframes
+        .iter()
+        .any(|it| it.tween.as_any().downcast_ref::<prpr::core::StaticTween>().map_or(true, |it| it.0 > 2))
+        || anim.next.as_ref().map_or(false, |it| eased(it))
+}
+
+/// Lists the features used by `chart` that cannot be expressed in `format`.
+fn unsupported(chart: &Chart, format: &ChartFormat) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let rpe = matches!(format, ChartFormat::Rpe);
+    let pgr = matches!(format, ChartFormat::Pgr);
+    for (id, line) in chart.lines.iter().enumerate() {
+        let mut warn = |what: &str| warnings.push(format!("Judge line #{id}: {what}"));
+        match &line.kind {
+            JudgeLineKind::Normal => {}
+            JudgeLineKind::Texture(_) => warn("texture lines are exported as normal lines"),
+            JudgeLineKind::Text(_) if !rpe => warn("text lines are exported as normal lines"),
+            JudgeLineKind::Paint(..) if !rpe => warn("paint lines are exported as normal lines"),
+            _ => {}
+        }
+        if rpe {
+            if chart.settings.pe_alpha_extension && negative(&line.object.alpha) {
+                warn("negative alpha values (PE alpha extension) are not supported");
+            }
+            continue;
+        }
+        if line.attach_ui.is_some() {
+            warn("UI attachment is dropped");
+        }
+        if line.parent.is_some() {
+            warn("parent line is dropped");
+        }
+        if line.z_index != 0 {
+            warn("z-order is dropped");
+        }
+        if !line.color.is_default() {
+            warn("color events are dropped");
+        }
+        if !line.incline.is_default() {
+            warn("incline events are dropped");
+        }
+        let ctrl = line.ctrl_obj.borrow();
+        if [&ctrl.alpha, &ctrl.size, &ctrl.pos, &ctrl.y].into_iter().any(|it| !it.is_default()) {
+            warn("control events are dropped");
+        }
+        if animated(&line.object.scale.0) || animated(&line.object.scale.1) {
+            warn("scale events are dropped");
+        }
+        if pgr {
+            if chart.settings.pe_alpha_extension && negative(&line.object.alpha) {
+                warn("negative alpha values (PE alpha extension) are not supported");
+            }
+            if [
+                &line.object.alpha,
+                &line.object.rotation,
+                &line.object.translation.0,
+                &line.object.translation.1,
+            ]
+            .into_iter()
+            .any(eased)
+            {
+                warn("eased events are approximated linearly");
+            }
+        }
+        if line.notes.iter().any(|it| {
+            !it.object.alpha.is_default()
+                || !it.object.translation.1.is_default()
+                || !it.object.rotation.is_default()
+                || !it.object.scale.1.is_default()
+        }) {
+            warn("note alpha, offset and rotation are dropped");
+        }
+        if pgr && line.notes.iter().any(|it| it.fake) {
+            warn("fake notes are dropped");
+        }
+        if pgr && line.notes.iter().any(|it| !it.object.scale.0.is_default()) {
+            warn("note sizes are dropped");
+        }
+        if !rpe && line.notes.iter().any(|it| it.visible_time.is_finite()) {
+            warn("note visible times are dropped");
+        }
+    }
+    if !rpe && !chart.extra.effects.is_empty() {
+        warnings.push("Effects in extra.json are kept but may not line up with the converted chart".to_owned());
+    }
+    warnings
+}
+
+#[macroquad::main(build_conf)]
+async fn main() -> Result<()> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap();
+    let (Some(path), Some(format)) = (args.next(), args.next()) else {
+        bail!("Usage: {program} <chart> <rpe|pec|pgr> [output]");
+    };
+    let format: ChartFormat = match format.as_str() {
+        "rpe" => ChartFormat::Rpe,
+        "pec" => ChartFormat::Pec,
+        "pgr" => ChartFormat::Pgr,
+        _ => bail!("Unknown format: {format}"),
+    };
+    let output = args.next().unwrap_or_else(|| {
+        if matches!(format, ChartFormat::Pec) {
+            "out.pec".to_owned()
+        } else {
+            "out.json".to_owned()
+        }
+    });
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap();
+    let _guard = rt.enter();
+
+    let mut fs = fs::fs_from_file(std::path::Path::new(&path)).context("Failed to load chart")?;
+    let info = fs::load_info(fs.deref_mut()).await.context("Failed to load chart info")?;
+    let chart = load_chart(&mut fs, &info).await.context("Failed to parse chart")?;
+
+    for warning in unsupported(&chart, &format) {
+        warn!("{warning}");
+    }
+
+    let bpm_list = chart.bpm_list.borrow().clone();
+    let text = match format {
+        ChartFormat::Rpe => export_rpe(&chart, &bpm_list),
+        ChartFormat::Pec => export_pec(&chart, &bpm_list),
+        ChartFormat::Pgr => export_phigros(&chart, &bpm_list),
+        ChartFormat::Mania => unreachable!("not a target format"),
+    };
+    std::fs::write(&output, text).with_context(|| format!("Failed to write to {output}"))?;
+    info!("Converted chart written to {output}");
+    Ok(())
+}
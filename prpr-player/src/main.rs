@@ -3,8 +3,11 @@ use macroquad::prelude::*;
 use prpr::{
     build_conf,
     core::init_assets,
-    fs,
+    fs, lint,
+    parse::load_chart,
+    replay::Replay,
     scene::{show_error, GameMode, LoadingScene, NextScene, Scene},
+    stats,
     time::TimeManager,
     ui::{FontArc, TextPainter, Ui},
     Main,
@@ -40,7 +43,7 @@ async fn main() -> Result<()> {
     init_assets();
 
     #[cfg(target_arch = "wasm32")]
-    let (mut fs, config) = {
+    let (mut fs, config, check, stats, replay) = {
         fn js_err(err: wasm_bindgen::JsValue) -> anyhow::Error {
             anyhow::Error::msg(format!("{err:?}"))
         }
@@ -52,22 +55,41 @@ async fn main() -> Result<()> {
                 autoplay: false,
                 ..Default::default()
             }),
+            false,
+            false,
+            None,
         )
     };
     #[cfg(any(target_os = "android", target_os = "ios"))]
-    let (mut fs, config) = (fs::fs_from_assets("charts/moment/")?, None);
+    let (mut fs, config, check, stats, replay) = (fs::fs_from_assets("charts/moment/")?, None, false, false, None::<Replay>);
     #[cfg(all(not(target_arch = "wasm32"), not(target_os = "android"), not(target_os = "ios")))]
-    let (mut fs, config) = {
-        let mut args = std::env::args();
+    let (mut fs, config, check, stats, replay) = {
+        let mut args = std::env::args().peekable();
         let program = args.next().unwrap();
+        let usage = || format!("Usage: {program} [--check] [--stats] [--debug] [--record <replay>] [--replay <replay>] <chart> [config]");
+        let check = args.next_if_eq("--check").is_some();
+        let stats = args.next_if_eq("--stats").is_some();
+        if args.next_if_eq("--debug").is_some() {
+            prpr::scene::DEBUG_VIEW.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+        if args.next_if_eq("--record").is_some() {
+            *prpr::scene::REPLAY_PATH.lock().unwrap() = Some(args.next().with_context(usage)?.into());
+        }
+        let replay = if args.next_if_eq("--replay").is_some() {
+            Some(Replay::load(args.next().with_context(usage)?)?)
+        } else {
+            None
+        };
         let Some(path) = args.next() else {
-            anyhow::bail!("Usage: {program} <chart>");
+            anyhow::bail!(usage());
         };
+        // charts opened from a directory are reloaded when they are saved
+        prpr::scene::HOT_RELOAD.store(true, std::sync::atomic::Ordering::SeqCst);
         let mut config = None;
         if let Some(config_path) = args.next() {
             config = Some(serde_yaml::from_str(&std::fs::read_to_string(config_path).context("Cannot read from config file")?)?);
         }
-        (fs::fs_from_file(std::path::Path::new(&path))?, config)
+        (fs::fs_from_file(std::path::Path::new(&path))?, config, check, stats, replay)
     };
 
     let _guard = {
@@ -87,6 +109,32 @@ async fn main() -> Result<()> {
         }
     };
 
+    if check {
+        let diagnostics = lint::check(&mut fs).await?;
+        for diagnostic in &diagnostics {
+            println!("{diagnostic}");
+        }
+        println!("{} problem(s) found", diagnostics.len());
+        return Ok(());
+    }
+
+    if stats {
+        println!("{}", serde_json::to_string_pretty(&stats::load_stats(&mut fs).await?)?);
+        return Ok(());
+    }
+
+    if let Some(replay) = replay {
+        let info = fs::load_info(fs.deref_mut()).await?;
+        let chart = load_chart(&mut fs, &info).await?;
+        let result = replay.simulate(chart, config.unwrap_or_default(), &info);
+        println!("Score: {:07}", result.score);
+        println!("Accuracy: {:.2}%", result.accuracy * 100.);
+        println!("Max combo: {}", result.max_combo);
+        println!("Perfect / Good / Bad / Miss: {:?}", result.counts);
+        println!("Early / Late: {} / {}", result.early, result.late);
+        return Ok(());
+    }
+
     let font = FontArc::try_from_vec(load_file("font.ttf").await?)?;
     let mut painter = TextPainter::new(font);
 
@@ -8,7 +8,9 @@ use prpr::{
     config::Config,
     core::{init_assets, MSRenderTarget, NoteKind},
     fs::{self, PatchedFileSystem},
-    scene::{GameMode, GameScene, LoadingScene, BILLBOARD},
+    parse::load_chart,
+    replay::Replay,
+    scene::{GameMode, GameScene, LoadingScene, BILLBOARD, PLAYBACK},
     time::TimeManager,
     ui::{ChartInfoEdit, FontArc, TextPainter, Ui},
     Main,
@@ -82,11 +84,12 @@ async fn the_main() -> Result<()> {
     let font = FontArc::try_from_vec(load_file("font.ttf").await?)?;
     let mut painter = TextPainter::new(font);
 
-    let (path, config) = {
+    let (path, config, replay) = {
         let mut args = std::env::args().skip(1);
         let Some(path) = args.next() else {
             bail!("请将谱面文件或文件夹拖动到该软件上！");
         };
+        let replay = args.next().map(Replay::load).transpose().context("加载回放失败")?;
         let config =
             match (|| -> Result<Config> { Ok(serde_yaml::from_str(&std::fs::read_to_string("conf.yml").context("无法加载配置文件")?)?) })() {
                 Err(err) => {
@@ -95,13 +98,13 @@ async fn the_main() -> Result<()> {
                 }
                 Ok(config) => config,
             };
-        (path, config)
+        (path, config, replay)
     };
 
     let mut fs = fs::fs_from_file(std::path::Path::new(&path)).context("加载谱面失败")?;
     let info = fs::load_info(fs.deref_mut()).await.context("加载谱面信息失败")?;
 
-    let chart = GameScene::load_chart(&mut fs, &info).await.context("加载谱面内容失败")?;
+    let chart = load_chart(&mut fs, &info).await.context("加载谱面内容失败")?;
     macro_rules! ld {
         ($path:literal) => {
             AudioClip::new(load_file($path).await?).with_context(|| format!("加载音效 `{}` 失败", $path))?
@@ -115,6 +118,20 @@ async fn the_main() -> Result<()> {
     let sfx_drag = ld!("drag.ogg");
     let sfx_flick = ld!("flick.ogg");
 
+    // hit sounds are placed where the judge plays them
+    let hits: Vec<(f32, NoteKind)> = if let Some(replay) = &replay {
+        let chart = load_chart(&mut fs, &info).await.context("加载谱面内容失败")?;
+        replay.simulation(chart, config.clone(), &info).res.played_sfx
+    } else {
+        chart
+            .lines
+            .iter()
+            .flat_map(|it| it.notes.iter())
+            .filter(|it| !it.fake)
+            .map(|it| (it.time, it.kind.clone()))
+            .collect()
+    };
+
     let mut gl = unsafe { get_internal_gl() };
 
     let texture = miniquad::Texture::new_render_texture(
@@ -188,7 +205,7 @@ async fn the_main() -> Result<()> {
     let volume_music = config.volume_music;
     let volume_sfx = config.volume_sfx;
     let config = Config {
-        autoplay: true,
+        autoplay: replay.is_none(),
         volume_music: 0.,
         volume_sfx: 0.,
         ..config
@@ -235,10 +252,10 @@ async fn the_main() -> Result<()> {
             *dst += frame.1 * volume;
         }
     };
-    for note in chart.lines.iter().flat_map(|it| it.notes.iter()).filter(|it| !it.fake) {
+    for (time, kind) in hits {
         place(
-            O + note.time as f64 + offset as f64,
-            match note.kind {
+            O + time as f64 + offset as f64,
+            match kind {
                 NoteKind::Click | NoteKind::Hold { .. } => &sfx_click,
                 NoteKind::Drag => &sfx_drag,
                 NoteKind::Flick => &sfx_flick,
@@ -268,8 +285,9 @@ async fn the_main() -> Result<()> {
         let my_time = Rc::clone(&my_time);
         move || *(*my_time).borrow()
     }));
-    let fs = Box::new(PatchedFileSystem(fs, edit.to_patches().await?));
+    let fs = Box::new(PatchedFileSystem::new(fs, edit.to_patches().await?));
     static MSAA: AtomicBool = AtomicBool::new(false);
+    *PLAYBACK.lock().unwrap() = replay;
     let mut main =
         Main::new(Box::new(LoadingScene::new(GameMode::Normal, edit.info, config, fs, None, Some(Rc::new(move || (vw, vh)))).await?), tm, {
             let mut cnt = 0;
@@ -84,7 +84,8 @@ impl Scene for MainScene {
                 let fs = self.fs.clone_box();
                 let edit = self.edit.clone();
                 self.loading_scene_task = Some(Box::pin(async move {
-                    LoadingScene::new(GameMode::Normal, info, config, Box::new(PatchedFileSystem(fs, edit.to_patches().await?)), None, None).await
+                    LoadingScene::new(GameMode::Normal, info, config, Box::new(PatchedFileSystem::new(fs, edit.to_patches().await?)), None, None)
+                        .await
                 }));
             }
             r.x += dx;
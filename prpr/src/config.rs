@@ -1,3 +1,4 @@
+use crate::judge::{JudgePreset, KeyboardConfig, MidiConfig};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
@@ -29,6 +30,11 @@ pub struct Config {
     pub fix_aspect_ratio: bool,
     pub fxaa: bool,
     pub interactive: bool,
+    pub judge_rules: JudgePreset,
+    /// Enables the keyboard mode, where keys are bound to lanes
+    pub keyboard: Option<KeyboardConfig>,
+    /// Reads touches from the pads of a MIDI device (needs the `midi` feature)
+    pub midi: Option<MidiConfig>,
     pub multiple_hint: bool,
     pub note_scale: f32,
     pub offset: f32,
@@ -56,6 +62,9 @@ impl Default for Config {
             fix_aspect_ratio: false,
             fxaa: false,
             interactive: true,
+            judge_rules: JudgePreset::Official,
+            keyboard: None,
+            midi: None,
             multiple_hint: true,
             note_scale: 1.0,
             offset: 0.,
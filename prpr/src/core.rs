@@ -35,7 +35,7 @@ mod render;
 pub use render::{copy_fbo, MSRenderTarget};
 
 mod resource;
-pub use resource::{ParticleEmitter, Resource, ResourcePack, DPI_VALUE};
+pub use resource::{HeadlessResource, ParticleEmitter, Resource, ResourcePack, Stage, DPI_VALUE};
 
 mod tween;
 pub use tween::{easing_from, BezierTween, ClampedTween, StaticTween, TweenFunction, TweenId, TweenMajor, TweenMinor, Tweenable, TWEEN_FUNCTIONS};
@@ -55,7 +55,7 @@ pub fn init_assets() {
     set_pc_assets_folder("assets");
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct Triple(i32, u32, u32);
 impl Default for Triple {
     fn default() -> Self {
@@ -67,9 +67,25 @@ impl Triple {
     pub fn beats(&self) -> f32 {
         self.0 as f32 + self.1 as f32 / self.2 as f32
     }
+
+    /// Finds the fraction with the smallest denominator that represents `beats` closely enough.
+    pub fn from_beats(beats: f32) -> Self {
+        const MAX_DENOMINATOR: u32 = 960;
+        let int = beats.floor();
+        let frac = beats - int;
+        let den = (1..=MAX_DENOMINATOR)
+            .find(|den| ((frac * *den as f32).round() / *den as f32 - frac).abs() < 1e-4)
+            .unwrap_or(MAX_DENOMINATOR);
+        let num = (frac * den as f32).round() as u32;
+        if num == den {
+            Self(int as i32 + 1, 0, 1)
+        } else {
+            Self(int as i32, num, den)
+        }
+    }
 }
 
-#[derive(Default)] // the default is a dummy
+#[derive(Clone, Default)] // the default is a dummy
 pub struct BpmList {
     elements: Vec<(f32, f32, f32)>, // (beats, time, bpm)
     cursor: usize,
@@ -92,6 +108,16 @@ impl BpmList {
         BpmList { elements, cursor: 0 }
     }
 
+    /// The list of a judge line whose tempo is scaled by `factor`, as RPE's `bpmfactor` does: each beat of the line lasts `factor` times as long.
+    pub fn with_factor(&self, factor: f32) -> Self {
+        Self::new(self.ranges().into_iter().map(|(beats, bpm)| (beats, bpm / factor)).collect())
+    }
+
+    /// Returns the `(beat, bpm)` pairs this list was built from.
+    pub fn ranges(&self) -> Vec<(f32, f32)> {
+        self.elements.iter().map(|(beats, _, bpm)| (*beats, *bpm)).collect()
+    }
+
     pub fn time_beats(&mut self, beats: f32) -> f32 {
         while let Some(kf) = self.elements.get(self.cursor + 1) {
             if kf.0 > beats {
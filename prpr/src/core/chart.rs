@@ -1,4 +1,4 @@
-use super::{BpmList, Effect, JudgeLine, Matrix, Resource, UIElement, Vector, Video};
+use super::{BpmList, Effect, JudgeLine, Matrix, Resource, Stage, UIElement, Vector, Video};
 use crate::{judge::JudgeStatus, ui::Ui};
 use macroquad::prelude::*;
 use std::cell::RefCell;
@@ -78,14 +78,7 @@ impl Chart {
     }
 
     pub fn update(&mut self, res: &mut Resource) {
-        for line in &mut self.lines {
-            line.object.set_time(res.time);
-        }
-        // TODO optimize
-        let trs = self.lines.iter().map(|it| it.now_transform(res, &self.lines)).collect::<Vec<_>>();
-        for (line, tr) in self.lines.iter_mut().zip(trs) {
-            line.update(res, tr);
-        }
+        self.update_lines(res);
         for effect in &mut self.extra.effects {
             effect.update(res);
         }
@@ -96,6 +89,18 @@ impl Chart {
         }
     }
 
+    /// Steps the judge lines and notes, leaving effects and videos alone.
+    pub fn update_lines(&mut self, res: &mut impl Stage) {
+        for line in &mut self.lines {
+            line.object.set_time(res.time());
+        }
+        // TODO optimize
+        let trs = self.lines.iter().map(|it| it.now_transform(res, &self.lines)).collect::<Vec<_>>();
+        for (line, tr) in self.lines.iter_mut().zip(trs) {
+            line.update(res, tr);
+        }
+    }
+
     pub fn render(&self, ui: &mut Ui, res: &mut Resource) {
         for video in &self.extra.videos {
             video.render(res);
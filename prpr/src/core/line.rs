@@ -1,7 +1,4 @@
-use super::{
-    chart::ChartSettings, object::CtrlObject, Anim, AnimFloat, BpmList, Matrix, Note, Object, Point, RenderConfig,
-    Resource, Vector,
-};
+use super::{chart::ChartSettings, object::CtrlObject, Anim, AnimFloat, BpmList, Matrix, Note, Object, Point, RenderConfig, Resource, Stage, Vector};
 use crate::{
     ext::{draw_text_aligned, get_viewport, NotNanExt, SafeTexture},
     judge::JudgeStatus,
@@ -10,10 +7,10 @@ use crate::{
 use macroquad::prelude::*;
 use miniquad::{RenderPass, Texture, TextureParams, TextureWrap};
 use nalgebra::Rotation2;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 
-#[derive(Clone, Copy, Deserialize)]
+#[derive(Clone, Copy, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 #[repr(usize)]
 pub enum UIElement {
@@ -84,6 +81,13 @@ impl JudgeLineCache {
     }
 }
 
+/// The distance within which notes are shown on a line whose PhiEdit alpha is `-w`, in units of half the screen height. PhiEdit gives it
+/// as `w - 1000` units of its canvas, the same 2048x1400 canvas `parse_pec` maps line positions from, so 700 units make half the screen
+/// height.
+fn pe_visible_distance(w: u32) -> f32 {
+    (w as f32 - 1000.) / 700.
+}
+
 pub struct JudgeLine {
     pub object: Object,
     pub ctrl_obj: RefCell<CtrlObject>,
@@ -96,15 +100,17 @@ pub struct JudgeLine {
     pub z_index: i32,
     pub show_below: bool,
     pub attach_ui: Option<UIElement>,
+    /// Name of the group the line is filed under in the editor (RPE only)
+    pub group: Option<String>,
 
     pub cache: JudgeLineCache,
 }
 
 impl JudgeLine {
-    pub fn update(&mut self, res: &mut Resource, tr: Matrix) {
+    pub fn update(&mut self, res: &mut impl Stage, tr: Matrix) {
         // self.object.set_time(res.time); // this is done by chart, chart has to calculate transform for us
         let rot = self.object.rotation.now();
-        self.height.set_time(res.time);
+        self.height.set_time(res.time());
         let line_height = self.height.now();
         let mut ctrl_obj = self.ctrl_obj.borrow_mut();
         self.cache.update_order.retain(|id| {
@@ -115,14 +121,14 @@ impl JudgeLine {
         drop(ctrl_obj);
         match &mut self.kind {
             JudgeLineKind::Text(anim) => {
-                anim.set_time(res.time);
+                anim.set_time(res.time());
             }
             JudgeLineKind::Paint(anim, ..) => {
-                anim.set_time(res.time);
+                anim.set_time(res.time());
             }
             _ => {}
         }
-        self.color.set_time(res.time);
+        self.color.set_time(res.time());
         self.cache.above_indices.retain_mut(|index| {
             while matches!(self.notes[*index].judge, JudgeStatus::Judged) {
                 if self
@@ -149,7 +155,7 @@ impl JudgeLine {
         });
     }
 
-    pub fn now_transform(&self, res: &Resource, lines: &[JudgeLine]) -> Matrix {
+    pub fn now_transform(&self, res: &(impl Stage + ?Sized), lines: &[JudgeLine]) -> Matrix {
         if let Some(parent) = self.parent {
             let po = &lines[parent].object;
             let mut tr = Rotation2::new(po.rotation.now().to_radians()) * self.object.now_translation(res);
@@ -259,6 +265,7 @@ impl JudgeLine {
                 ctrl_obj: &mut self.ctrl_obj.borrow_mut(),
                 line_height: self.height.now(),
                 appear_before: f32::INFINITY,
+                visible_distance: f32::INFINITY,
                 draw_below: self.show_below,
                 incline_sin: self.incline.now_opt().map(|it| it.to_radians().sin()).unwrap_or_default(),
             };
@@ -278,7 +285,7 @@ impl JudgeLine {
                         config.appear_before = (w as f32 - 100.) / 10.;
                     }
                     w if (1000..2000).contains(&w) => {
-                        // TODO unsupported
+                        config.visible_distance = pe_visible_distance(w);
                     }
                     _ => {}
                 }
@@ -328,3 +335,52 @@ impl JudgeLine {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::ChartExtra, parse::parse_pec, testing::fixture_str};
+
+    // times of the notes of `line` that are within its visible distance at `time`
+    fn visible(line: &mut JudgeLine, time: f32) -> Vec<f32> {
+        line.object.alpha.set_time(time);
+        let distance = pe_visible_distance((-line.object.alpha.now()).floor() as u32);
+        line.height.set_time(time);
+        let line_height = line.height.now();
+        let mut res: Vec<_> = line
+            .notes
+            .iter()
+            .filter(|it| it.within_distance(line_height, it.speed, distance))
+            .map(|it| it.time)
+            .collect();
+        res.sort_by(f32::total_cmp);
+        res
+    }
+
+    #[test]
+    fn pe_visible_distance_units() {
+        let mut chart = parse_pec(&fixture_str("pe_visible_distance.pec"), ChartExtra::default()).unwrap();
+        // line #2 sits 500 canvas units above the center, which is where the visible range of -1500 ends
+        let line = &mut chart.lines[2];
+        line.object.translation.1.set_time(0.);
+        assert!((line.object.translation.1.now() - pe_visible_distance(1500)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pe_visible_distance_culling() {
+        let mut chart = parse_pec(&fixture_str("pe_visible_distance.pec"), ChartExtra::default()).unwrap();
+        // notes move half the screen height per second, and show within 500 / 700 of it
+        let above = &mut chart.lines[0];
+        assert_eq!(visible(above, 0.), [0.5]);
+        assert_eq!(visible(above, 1.), [0.5, 1., 1.5]);
+        // the head of the hold is long gone, but its end is still close
+        assert_eq!(visible(above, 2.4), [1.5, 2.]);
+        assert_eq!(visible(above, 3.), [1.5]);
+        assert_eq!(visible(above, 4.), Vec::<f32>::new());
+
+        // with a negative speed, notes come from below the line
+        let below = &mut chart.lines[1];
+        assert_eq!(visible(below, 0.), [0.5]);
+        assert_eq!(visible(below, 0.5), [0.5, 1.]);
+    }
+}
@@ -1,4 +1,6 @@
-use super::{chart::ChartSettings, BpmList, CtrlObject, JudgeLine, Matrix, Object, Point, Resource, JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR};
+use super::{
+    chart::ChartSettings, BpmList, CtrlObject, JudgeLine, Matrix, Object, Point, Resource, Stage, JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR,
+};
 use crate::{judge::JudgeStatus, parse::RPE_HEIGHT};
 use macroquad::prelude::*;
 
@@ -31,6 +33,8 @@ pub struct Note {
     pub time: f32,
     pub height: f32,
     pub speed: f32,
+    /// The note only shows up this many seconds before it's hit
+    pub visible_time: f32,
 
     pub above: bool,
     pub multiple_hint: bool,
@@ -43,6 +47,8 @@ pub struct RenderConfig<'a> {
     pub ctrl_obj: &'a mut CtrlObject,
     pub line_height: f32,
     pub appear_before: f32,
+    /// Notes further than this from the line are hidden, in units of half the screen height
+    pub visible_distance: f32,
     pub draw_below: bool,
     pub incline_sin: f32,
 }
@@ -128,11 +134,11 @@ impl Note {
         // && self.ctrl_obj.is_default()
     }
 
-    pub fn update(&mut self, res: &mut Resource, parent_rot: f32, parent_tr: &Matrix, ctrl_obj: &mut CtrlObject, line_height: f32) {
-        self.object.set_time(res.time);
+    pub fn update(&mut self, res: &mut impl Stage, parent_rot: f32, parent_tr: &Matrix, ctrl_obj: &mut CtrlObject, line_height: f32) {
+        self.object.set_time(res.time());
         if let Some(color) = if let JudgeStatus::Hold(perfect, at, ..) = &mut self.judge {
-            if res.time > *at {
-                *at += HOLD_PARTICLE_INTERVAL / res.config.speed;
+            if res.time() > *at {
+                *at += HOLD_PARTICLE_INTERVAL / res.config().speed;
                 Some(if *perfect { JUDGE_LINE_PERFECT_COLOR } else { JUDGE_LINE_GOOD_COLOR })
             } else {
                 None
@@ -141,9 +147,8 @@ impl Note {
             None
         } {
             self.init_ctrl_obj(ctrl_obj, line_height);
-            res.with_model(parent_tr * self.now_transform(res, ctrl_obj, 0., 0.), |res| {
-                res.emit_at_origin(parent_rot + if self.above { 0. } else { 180. }, color)
-            });
+            let model = parent_tr * self.now_transform(res, ctrl_obj, 0., 0.);
+            res.emit(model, parent_rot + if self.above { 0. } else { 180. }, color);
         }
     }
 
@@ -156,8 +161,8 @@ impl Note {
         ctrl_obj.set_height((self.height - line_height + self.object.translation.1.now() / self.speed) * RPE_HEIGHT / 2.);
     }
 
-    pub fn now_transform(&self, res: &Resource, ctrl_obj: &CtrlObject, base: f32, incline_sin: f32) -> Matrix {
-        let incline_val = 1. - incline_sin * (base * res.aspect_ratio + self.object.translation.1.now()) * RPE_HEIGHT / 2. / 360.;
+    pub fn now_transform(&self, res: &impl Stage, ctrl_obj: &CtrlObject, base: f32, incline_sin: f32) -> Matrix {
+        let incline_val = 1. - incline_sin * (base * res.aspect_ratio() + self.object.translation.1.now()) * RPE_HEIGHT / 2. / 360.;
         let mut tr = self.object.now_translation(res);
         tr.x *= incline_val * ctrl_obj.pos.now_opt().unwrap_or(1.);
         tr.y += base;
@@ -166,10 +171,25 @@ impl Note {
         self.object.now_rotation().append_translation(&tr).append_nonuniform_scaling(&scale)
     }
 
+    /// Whether any part of the note, from its head to the end of a hold, is within `distance` (see [RenderConfig::visible_distance]) of
+    /// the line at `line_height`, on either side. `speed` includes the control of the line.
+    pub(crate) fn within_distance(&self, line_height: f32, speed: f32, distance: f32) -> bool {
+        let head = (self.height - line_height) * speed;
+        let tail = match self.kind {
+            NoteKind::Hold { end_height, .. } => (end_height - line_height) * speed,
+            _ => head,
+        };
+        let nearest = if head * tail <= 0. { 0. } else { head.abs().min(tail.abs()) };
+        nearest <= distance
+    }
+
     pub fn render(&self, res: &mut Resource, config: &mut RenderConfig, bpm_list: &mut BpmList) {
         if matches!(self.judge, JudgeStatus::Judged) && !matches!(self.kind, NoteKind::Hold { .. }) {
             return;
         }
+        if self.time - res.time > self.visible_time {
+            return;
+        }
         if config.appear_before.is_finite() {
             // TODO optimize
             let beat = bpm_list.beat(self.time);
@@ -193,6 +213,9 @@ impl Note {
         let height = self.height / res.aspect_ratio * spd;
 
         let base = height - line_height;
+        if !self.within_distance(config.line_height, spd, config.visible_distance) {
+            return;
+        }
         if !config.draw_below
             && ((res.time - FADEOUT_TIME >= self.time) || (self.fake && res.time >= self.time) || (self.time > res.time && base <= -1e-5))
             && !matches!(self.kind, NoteKind::Hold { .. })
@@ -1,4 +1,4 @@
-use super::{AnimFloat, AnimVector, Color, Matrix, Resource, Vector};
+use super::{AnimFloat, AnimVector, Color, Matrix, Stage, Vector};
 use macroquad::prelude::*;
 use nalgebra::Rotation2;
 
@@ -38,7 +38,7 @@ impl Object {
             && self.translation.1.dead()
     }
 
-    pub fn now(&self, res: &Resource) -> Matrix {
+    pub fn now(&self, res: &(impl Stage + ?Sized)) -> Matrix {
         self.now_rotation().append_translation(&self.now_translation(res))
     }
 
@@ -48,9 +48,9 @@ impl Object {
     }
 
     #[inline]
-    pub fn now_translation(&self, res: &Resource) -> Vector {
+    pub fn now_translation(&self, res: &(impl Stage + ?Sized)) -> Vector {
         let mut tr = self.translation.now();
-        tr.y /= res.aspect_ratio;
+        tr.y /= res.aspect_ratio();
         tr
     }
 
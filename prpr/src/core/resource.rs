@@ -1,4 +1,4 @@
-use super::{MSRenderTarget, Matrix, Point, JUDGE_LINE_PERFECT_COLOR, NOTE_WIDTH_RATIO_BASE};
+use super::{MSRenderTarget, Matrix, NoteKind, Point, JUDGE_LINE_PERFECT_COLOR, NOTE_WIDTH_RATIO_BASE};
 use crate::{
     config::Config,
     ext::{create_audio_manger, nalgebra_to_glm, SafeTexture},
@@ -533,3 +533,102 @@ impl Resource {
         unsafe { get_internal_gl() }.quad_gl.pop_model_matrix();
     }
 }
+
+/// The part of [Resource] that stepping a chart and judging it depends on, so that both can run without a window or an audio device.
+pub trait Stage {
+    fn config(&self) -> &Config;
+    fn time(&self) -> f32;
+    fn aspect_ratio(&self) -> f32;
+    fn dpi(&self) -> u32;
+    fn note_width(&self) -> f32;
+
+    fn play_sfx(&mut self, kind: &NoteKind);
+    fn emit(&mut self, model: Matrix, rotation: f32, color: Color);
+}
+
+impl Stage for Resource {
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn time(&self) -> f32 {
+        self.time
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
+    fn dpi(&self) -> u32 {
+        self.dpi
+    }
+
+    fn note_width(&self) -> f32 {
+        self.note_width
+    }
+
+    fn play_sfx(&mut self, kind: &NoteKind) {
+        let sfx = match kind {
+            NoteKind::Click | NoteKind::Hold { .. } => &mut self.sfx_click,
+            NoteKind::Drag => &mut self.sfx_drag,
+            NoteKind::Flick => &mut self.sfx_flick,
+        };
+        crate::judge::play_sfx(sfx, &self.config);
+    }
+
+    fn emit(&mut self, model: Matrix, rotation: f32, color: Color) {
+        self.with_model(model, |res| res.emit_at_origin(rotation, color));
+    }
+}
+
+/// A [Stage] without textures, audio or particles.
+pub struct HeadlessResource {
+    pub config: Config,
+    pub time: f32,
+    pub aspect_ratio: f32,
+    pub dpi: u32,
+    pub note_width: f32,
+    /// Hit sounds that would have been played, along with the time
+    pub played_sfx: Vec<(f32, NoteKind)>,
+}
+
+impl HeadlessResource {
+    pub fn new(config: Config, info: &ChartInfo) -> Self {
+        Self {
+            aspect_ratio: config.aspect_ratio.unwrap_or(info.aspect_ratio),
+            dpi: DPI_VALUE.load(std::sync::atomic::Ordering::SeqCst),
+            note_width: config.note_scale * NOTE_WIDTH_RATIO_BASE,
+            time: 0.,
+            config,
+            played_sfx: Vec::new(),
+        }
+    }
+}
+
+impl Stage for HeadlessResource {
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn time(&self) -> f32 {
+        self.time
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
+    fn dpi(&self) -> u32 {
+        self.dpi
+    }
+
+    fn note_width(&self) -> f32 {
+        self.note_width
+    }
+
+    fn play_sfx(&mut self, kind: &NoteKind) {
+        self.played_sfx.push((self.time, kind.clone()));
+    }
+
+    fn emit(&mut self, _model: Matrix, _rotation: f32, _color: Color) {}
+}
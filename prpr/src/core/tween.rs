@@ -1,6 +1,6 @@
 use macroquad::prelude::{vec2, Color, Vec2};
 use once_cell::sync::Lazy;
-use std::{ops::Range, rc::Rc};
+use std::{any::Any, ops::Range, rc::Rc};
 
 pub type TweenId = u8;
 
@@ -121,6 +121,7 @@ thread_local! {
 
 pub trait TweenFunction {
     fn y(&self, x: f32) -> f32;
+    fn as_any(&self) -> &dyn Any;
 }
 
 pub struct StaticTween(pub TweenId);
@@ -128,6 +129,10 @@ impl TweenFunction for StaticTween {
     fn y(&self, x: f32) -> f32 {
         TWEEN_FUNCTIONS[self.0 as usize](x)
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
 impl StaticTween {
@@ -142,6 +147,10 @@ impl TweenFunction for ClampedTween {
     fn y(&self, x: f32) -> f32 {
         (TWEEN_FUNCTIONS[self.0 as usize](f32::tween(&self.1.start, &self.1.end, x)) - self.2.start) / (self.2.end - self.2.start)
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
 impl ClampedTween {
@@ -150,6 +159,14 @@ impl ClampedTween {
         let y_range = f(range.start)..f(range.end);
         Self(tween, range, y_range)
     }
+
+    pub fn tween(&self) -> TweenId {
+        self.0
+    }
+
+    pub fn range(&self) -> Range<f32> {
+        self.1.clone()
+    }
 }
 
 // https://github.com/gre/bezier-easing
@@ -172,6 +189,10 @@ impl TweenFunction for BezierTween {
     fn y(&self, x: f32) -> f32 {
         Self::sample(self.p1.1, self.p2.1, self.t_for_x(x))
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
 impl BezierTween {
@@ -242,6 +263,10 @@ impl BezierTween {
             p2,
         }
     }
+
+    pub fn points(&self) -> ((f32, f32), (f32, f32)) {
+        (self.p1, self.p2)
+    }
 }
 
 #[repr(u8)]
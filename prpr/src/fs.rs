@@ -1,4 +1,8 @@
-use crate::{ext::spawn_task, info::ChartInfo};
+use crate::{
+    ext::spawn_task,
+    info::ChartInfo,
+    lint::{self, Diagnostic, DiagnosticKind, Severity},
+};
 use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
 use chardetng::EncodingDetector;
@@ -12,19 +16,30 @@ use std::{
     collections::HashMap,
     fs,
     io::{Cursor, Read, Seek, Write},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     sync::{Arc, Mutex},
 };
 use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
 
+mod fingerprint;
+pub use fingerprint::{chart_fingerprint, fingerprint};
+
+mod package;
+pub use package::package_chart;
+
 pub fn update_zip<R: Read + Seek>(zip: &mut ZipArchive<R>, patches: HashMap<String, Vec<u8>>) -> Result<Vec<u8>> {
+    rebuild_zip(zip, patches.into_iter().map(|(path, data)| (path, Some(data))).collect())
+}
+
+/// Copies `zip` with `patches` applied, where a patch of none removes the file. Untouched entries are copied without recompressing them.
+fn rebuild_zip<R: Read + Seek>(zip: &mut ZipArchive<R>, patches: HashMap<String, Option<Vec<u8>>>) -> Result<Vec<u8>> {
     let mut buffer = Vec::new();
     let mut w = ZipWriter::new(Cursor::new(&mut buffer));
     let options = FileOptions::default()
         .compression_method(CompressionMethod::Deflated)
         .unix_permissions(0o755);
     for i in 0..zip.len() {
-        let mut entry = zip.by_index(i).unwrap();
+        let entry = zip.by_index_raw(i).unwrap();
         let path = match entry.enclosed_name() {
             Some(path) => path.to_owned(),
             None => continue,
@@ -33,13 +48,14 @@ pub fn update_zip<R: Read + Seek>(zip: &mut ZipArchive<R>, patches: HashMap<Stri
         if entry.is_dir() {
             w.add_directory(path, options)?;
         } else if !patches.contains_key(&path) {
-            w.start_file(&path, options)?;
-            std::io::copy(&mut entry, &mut w)?;
+            w.raw_copy_file_rename(entry, &path)?;
         }
     }
     for (path, data) in patches.into_iter() {
-        w.start_file(path, options)?;
-        w.write_all(&data)?;
+        if let Some(data) = data {
+            w.start_file(path, options)?;
+            w.write_all(&data)?;
+        }
     }
     w.finish()?;
     drop(w);
@@ -50,9 +66,22 @@ pub fn update_zip<R: Read + Seek>(zip: &mut ZipArchive<R>, patches: HashMap<Stri
 pub trait FileSystem: Send {
     async fn load_file(&mut self, path: &str) -> Result<Vec<u8>>;
     async fn exists(&mut self, path: &str) -> Result<bool>;
+    /// Writes `data` to `path`, replacing the file if there is one.
+    async fn write_file(&mut self, path: &str, data: Vec<u8>) -> Result<()>;
+    async fn remove_file(&mut self, path: &str) -> Result<()>;
     fn list_root(&self) -> Result<Vec<String>>;
+    /// Lists every file (but no directory) recursively, relative to the root.
+    fn list_all(&self) -> Result<Vec<String>>;
     fn clone_box(&self) -> Box<dyn FileSystem>;
     fn as_any(&mut self) -> &mut dyn Any;
+
+    /// Writes several files at once, which some file systems do far cheaper than one by one.
+    async fn write_files(&mut self, files: HashMap<String, Vec<u8>>) -> Result<()> {
+        for (path, data) in files {
+            self.write_file(&path, data).await?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -69,10 +98,22 @@ impl FileSystem for AssetsFileSystem {
         Ok(load_file(&concat_string!(self.0, path)).await.is_ok())
     }
 
+    async fn write_file(&mut self, _path: &str, _data: Vec<u8>) -> Result<()> {
+        bail!("Assets are read-only")
+    }
+
+    async fn remove_file(&mut self, _path: &str) -> Result<()> {
+        bail!("Assets are read-only")
+    }
+
     fn list_root(&self) -> Result<Vec<String>> {
         Ok(Vec::new())
     }
 
+    fn list_all(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
     fn clone_box(&self) -> Box<dyn FileSystem> {
         Box::new(self.clone())
     }
@@ -83,7 +124,20 @@ impl FileSystem for AssetsFileSystem {
 }
 
 #[derive(Clone)]
-pub struct ExternalFileSystem(PathBuf);
+pub struct ExternalFileSystem(pub PathBuf);
+
+impl ExternalFileSystem {
+    /// Joins `path` to the root, refusing anything that could point outside of it (`..`, absolute paths).
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        if !Path::new(path)
+            .components()
+            .all(|it| matches!(it, Component::Normal(_) | Component::CurDir))
+        {
+            bail!("{path} is not inside the chart folder");
+        }
+        Ok(self.0.join(path))
+    }
+}
 
 #[async_trait]
 impl FileSystem for ExternalFileSystem {
@@ -94,13 +148,43 @@ impl FileSystem for ExternalFileSystem {
         }
         #[cfg(not(target_arch = "wasm32"))]
         {
-            let path = self.0.join(path);
+            let path = self.resolve(path)?;
             Ok(tokio::spawn(async move { tokio::fs::read(path).await }).await??)
         }
     }
 
     async fn exists(&mut self, path: &str) -> Result<bool> {
-        Ok(self.0.join(path).exists())
+        Ok(self.resolve(path)?.exists())
+    }
+
+    async fn write_file(&mut self, path: &str, data: Vec<u8>) -> Result<()> {
+        #[cfg(target_arch = "wasm32")]
+        {
+            bail!("Cannot write to external file system on wasm32")
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = self.resolve(path)?;
+            Ok(tokio::spawn(async move {
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(path, data).await
+            })
+            .await??)
+        }
+    }
+
+    async fn remove_file(&mut self, path: &str) -> Result<()> {
+        #[cfg(target_arch = "wasm32")]
+        {
+            bail!("Cannot remove from external file system on wasm32")
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = self.resolve(path)?;
+            Ok(tokio::spawn(async move { tokio::fs::remove_file(path).await }).await??)
+        }
     }
 
     fn list_root(&self) -> Result<Vec<String>> {
@@ -109,6 +193,27 @@ impl FileSystem for ExternalFileSystem {
             .collect())
     }
 
+    fn list_all(&self) -> Result<Vec<String>> {
+        fn walk(dir: &Path, prefix: &str, res: &mut Vec<String>) -> Result<()> {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                let name = concat_string!(prefix, name);
+                if entry.file_type()?.is_dir() {
+                    walk(&entry.path(), &concat_string!(name, "/"), res)?;
+                } else {
+                    res.push(name);
+                }
+            }
+            Ok(())
+        }
+        let mut res = Vec::new();
+        walk(&self.0, "", &mut res)?;
+        Ok(res)
+    }
+
     fn clone_box(&self) -> Box<dyn FileSystem> {
         Box::new(self.clone())
     }
@@ -118,8 +223,10 @@ impl FileSystem for ExternalFileSystem {
     }
 }
 
+/// Changes are kept in memory until [ZipFileSystem::save] or [ZipFileSystem::to_bytes], so that writing a file does not rebuild the
+/// whole archive.
 #[derive(Clone)]
-pub struct ZipFileSystem(pub Arc<Mutex<ZipArchive<Cursor<Vec<u8>>>>>, String);
+pub struct ZipFileSystem(pub Arc<Mutex<ZipArchive<Cursor<Vec<u8>>>>>, String, Arc<Mutex<HashMap<String, Option<Vec<u8>>>>>);
 
 impl ZipFileSystem {
     pub fn new(bytes: Vec<u8>) -> Result<Self> {
@@ -129,13 +236,53 @@ impl ZipFileSystem {
             .filter(|it| it.ends_with('/') && it.find('/') == Some(it.len() - 1))
             .collect::<Vec<_>>();
         let root = if root_dirs.len() == 1 { root_dirs[0].to_owned() } else { String::new() };
-        Ok(Self(Arc::new(Mutex::new(zip)), root))
+        Ok(Self(Arc::new(Mutex::new(zip)), root, Arc::default()))
+    }
+
+    /// The bytes of the archive, with every change made so far.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let pending = self.2.lock().unwrap();
+        let mut zip = self.0.lock().unwrap();
+        if pending.is_empty() {
+            return Ok(zip.clone().into_inner().into_inner());
+        }
+        rebuild_zip(&mut zip, pending.iter().map(|(path, data)| (concat_string!(self.1, path), data.clone())).collect())
+    }
+
+    /// Writes the archive with every change made so far to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let bytes = self.to_bytes()?;
+        fs::write(path, &bytes).with_context(|| format!("Failed to write to {}", path.display()))?;
+        *self.0.lock().unwrap() = ZipArchive::new(Cursor::new(bytes))?;
+        self.2.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        let pending = self.2.lock().unwrap();
+        let mut res: Vec<_> = self
+            .0
+            .lock()
+            .unwrap()
+            .file_names()
+            .filter(|it| !it.ends_with('/'))
+            .filter_map(|it| it.strip_prefix(self.1.as_str()))
+            .filter(|it| !pending.contains_key(*it))
+            .map(str::to_owned)
+            .collect();
+        res.extend(pending.iter().filter(|(_, data)| data.is_some()).map(|(path, _)| path.clone()));
+        res.sort();
+        res
     }
 }
 
 #[async_trait]
 impl FileSystem for ZipFileSystem {
     async fn load_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        if let Some(data) = self.2.lock().unwrap().get(path) {
+            return data.clone().with_context(|| format!("{path} has been removed"));
+        }
         let arc = Arc::clone(&self.0);
         let path = concat_string!(self.1, path);
         spawn_task(async move {
@@ -149,18 +296,31 @@ impl FileSystem for ZipFileSystem {
     }
 
     async fn exists(&mut self, path: &str) -> Result<bool> {
-        Ok(self.0.lock().unwrap().by_name(path).is_ok())
+        if let Some(data) = self.2.lock().unwrap().get(path) {
+            return Ok(data.is_some());
+        }
+        Ok(self.0.lock().unwrap().by_name(&concat_string!(self.1, path)).is_ok())
+    }
+
+    async fn write_file(&mut self, path: &str, data: Vec<u8>) -> Result<()> {
+        self.2.lock().unwrap().insert(path.to_owned(), Some(data));
+        Ok(())
+    }
+
+    async fn remove_file(&mut self, path: &str) -> Result<()> {
+        if !self.exists(path).await? {
+            bail!("{path} does not exist");
+        }
+        self.2.lock().unwrap().insert(path.to_owned(), None);
+        Ok(())
     }
 
     fn list_root(&self) -> Result<Vec<String>> {
-        Ok(self
-            .0
-            .lock()
-            .unwrap()
-            .file_names()
-            .filter(|it| !it.contains('/'))
-            .map(str::to_owned)
-            .collect())
+        Ok(self.file_names().into_iter().filter(|it| !it.contains('/')).collect())
+    }
+
+    fn list_all(&self) -> Result<Vec<String>> {
+        Ok(self.file_names())
     }
 
     fn clone_box(&self) -> Box<dyn FileSystem> {
@@ -172,31 +332,58 @@ impl FileSystem for ZipFileSystem {
     }
 }
 
-pub struct PatchedFileSystem(pub Box<dyn FileSystem>, pub HashMap<String, Vec<u8>>);
+/// Changes are kept in memory (a removed file as none), the underlying file system is never changed.
+pub struct PatchedFileSystem(pub Box<dyn FileSystem>, pub HashMap<String, Option<Vec<u8>>>);
+
+impl PatchedFileSystem {
+    pub fn new(fs: Box<dyn FileSystem>, patches: HashMap<String, Vec<u8>>) -> Self {
+        Self(fs, patches.into_iter().map(|(path, data)| (path, Some(data))).collect())
+    }
+}
 
 #[async_trait]
 impl FileSystem for PatchedFileSystem {
     async fn load_file(&mut self, path: &str) -> Result<Vec<u8>> {
-        if let Some(data) = self.1.get(path) {
-            Ok(data.clone())
-        } else {
-            self.0.load_file(path).await
+        match self.1.get(path) {
+            Some(Some(data)) => Ok(data.clone()),
+            Some(None) => bail!("{path} has been removed"),
+            None => self.0.load_file(path).await,
         }
     }
 
     async fn exists(&mut self, path: &str) -> Result<bool> {
-        Ok(self.0.exists(path).await? || self.1.contains_key(path))
+        match self.1.get(path) {
+            Some(data) => Ok(data.is_some()),
+            None => self.0.exists(path).await,
+        }
+    }
+
+    async fn write_file(&mut self, path: &str, data: Vec<u8>) -> Result<()> {
+        self.1.insert(path.to_owned(), Some(data));
+        Ok(())
+    }
+
+    async fn remove_file(&mut self, path: &str) -> Result<()> {
+        if !self.exists(path).await? {
+            bail!("{path} does not exist");
+        }
+        self.1.insert(path.to_owned(), None);
+        Ok(())
     }
 
     fn list_root(&self) -> Result<Vec<String>> {
-        let mut res = self.0.list_root()?;
-        res.extend(self.1.keys().cloned());
-        res.dedup();
+        Ok(self.list_all()?.into_iter().filter(|it| !it.contains('/')).collect())
+    }
+
+    fn list_all(&self) -> Result<Vec<String>> {
+        let mut res: Vec<_> = self.0.list_all()?.into_iter().filter(|it| !self.1.contains_key(it)).collect();
+        res.extend(self.1.iter().filter(|(_, data)| data.is_some()).map(|(path, _)| path.clone()));
+        res.sort();
         Ok(res)
     }
 
     fn clone_box(&self) -> Box<dyn FileSystem> {
-        unimplemented!()
+        Box::new(Self(self.0.clone_box(), self.1.clone()))
     }
 
     fn as_any(&mut self) -> &mut dyn Any {
@@ -241,10 +428,12 @@ fn info_from_kv<'a>(it: impl Iterator<Item = (&'a str, String)>, csv: bool) -> R
         }
         if key == "NoteScale" || key == "ScaleRatio" {
             warn!("Note scale is ignored");
+            lint::report(Diagnostic::new(Severity::Info, DiagnosticKind::IgnoredKey { key: key.to_owned() }));
             continue;
         }
         if key == "GlobalAlpha" {
             warn!("Global alpha is ignored");
+            lint::report(Diagnostic::new(Severity::Info, DiagnosticKind::IgnoredKey { key: key.to_owned() }));
             continue;
         }
         *match key {
@@ -318,7 +507,7 @@ pub async fn fix_info(fs: &mut dyn FileSystem, info: &mut ChartInfo) -> Result<(
     for file in fs.list_root().context("Cannot list files")? {
         if let Some((_, ext)) = file.rsplit_once('.') {
             match ext.to_ascii_lowercase().as_str() {
-                "json" | "pec" => {
+                "json" | "pec" | "osu" | "mc" => {
                     put("charts", &mut chart, file);
                 }
                 _ => {}
@@ -328,7 +517,22 @@ pub async fn fix_info(fs: &mut dyn FileSystem, info: &mut ChartInfo) -> Result<(
     if let Some(chart) = &chart {
         info.chart = chart.to_owned();
         if let Ok(s) = String::from_utf8(fs.load_file(&info.chart).await?) {
-            if let Ok(mut value) = serde_json::from_str::<Value>(&s) {
+            if crate::parse::is_mania(&s) {
+                let mut mania = crate::parse::mania_info(&s)?;
+                info.name = mania.name;
+                infer_diff(info, &mania.level);
+                info.level = mania.level;
+                info.charter = mania.charter;
+                info.composer = mania.composer;
+                info.preview_time = mania.preview_time;
+                info.format = mania.format;
+                if illustration.is_none() {
+                    illustration = get(fs, &mut mania.illustration).await?;
+                }
+                if music.is_none() {
+                    music = get(fs, &mut mania.music).await?;
+                }
+            } else if let Ok(mut value) = serde_json::from_str::<Value>(&s) {
                 #[derive(Deserialize)]
                 struct RPEMeta {
                     name: String,
@@ -421,3 +625,94 @@ pub fn fs_from_file(path: &Path) -> Result<Box<dyn FileSystem>> {
 pub fn fs_from_assets(name: impl Into<String>) -> Result<Box<dyn FileSystem>> {
     Ok(Box::new(AssetsFileSystem(name.into())))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block_on, zip_fs};
+
+    #[test]
+    fn zip_changes_are_kept_until_saved() {
+        let mut fs = zip_fs(&[("a.txt", b"a".to_vec()), ("dir/b.txt", b"b".to_vec())]);
+        let bytes = fs.as_any().downcast_mut::<ZipFileSystem>().unwrap().to_bytes().unwrap();
+        block_on(async {
+            fs.write_file("c.txt", b"c".to_vec()).await.unwrap();
+            fs.write_file("a.txt", b"new".to_vec()).await.unwrap();
+            fs.remove_file("dir/b.txt").await.unwrap();
+            assert!(fs.remove_file("missing.txt").await.is_err());
+
+            assert_eq!(fs.load_file("a.txt").await.unwrap(), b"new");
+            assert_eq!(fs.load_file("c.txt").await.unwrap(), b"c");
+            assert!(!fs.exists("dir/b.txt").await.unwrap());
+            assert!(fs.load_file("dir/b.txt").await.is_err());
+        });
+        assert_eq!(fs.list_all().unwrap(), ["a.txt", "c.txt"]);
+
+        let zip = fs.as_any().downcast_mut::<ZipFileSystem>().unwrap();
+        // nothing is rebuilt before the archive is asked for
+        assert_eq!(zip.0.lock().unwrap().clone().into_inner().into_inner(), bytes);
+        let mut saved = ZipFileSystem::new(zip.to_bytes().unwrap()).unwrap();
+        assert_eq!(saved.list_all().unwrap(), ["a.txt", "c.txt"]);
+        assert_eq!(block_on(saved.load_file("a.txt")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn zip_with_root_folder() {
+        let mut buffer = Vec::new();
+        let mut w = ZipWriter::new(Cursor::new(&mut buffer));
+        w.add_directory("chart/", FileOptions::default()).unwrap();
+        w.start_file("chart/a.png", FileOptions::default()).unwrap();
+        w.write_all(b"a").unwrap();
+        w.finish().unwrap();
+        drop(w);
+
+        let mut fs = ZipFileSystem::new(buffer).unwrap();
+        block_on(async {
+            assert!(fs.exists("a.png").await.unwrap());
+            assert_eq!(fs.load_file("a.png").await.unwrap(), b"a");
+            fs.remove_file("a.png").await.unwrap();
+            assert!(!fs.exists("a.png").await.unwrap());
+        });
+        assert!(fs.list_all().unwrap().is_empty());
+        let saved = ZipFileSystem::new(fs.to_bytes().unwrap()).unwrap();
+        assert!(saved.list_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn patched_removal_keeps_tombstones() {
+        let mut fs = PatchedFileSystem::new(zip_fs(&[("a.txt", b"a".to_vec()), ("b.txt", b"b".to_vec())]), HashMap::new());
+        block_on(async {
+            fs.remove_file("a.txt").await.unwrap();
+            assert!(!fs.exists("a.txt").await.unwrap());
+            assert!(fs.load_file("a.txt").await.is_err());
+            assert!(fs.remove_file("a.txt").await.is_err());
+            // the underlying file system is left alone
+            assert!(fs.0.exists("a.txt").await.unwrap());
+
+            fs.write_file("a.txt", b"new".to_vec()).await.unwrap();
+            assert_eq!(fs.load_file("a.txt").await.unwrap(), b"new");
+            fs.remove_file("b.txt").await.unwrap();
+        });
+        assert_eq!(fs.list_all().unwrap(), ["a.txt"]);
+        assert_eq!(fs.list_root().unwrap(), ["a.txt"]);
+    }
+
+    #[test]
+    fn external_paths_stay_enclosed() {
+        let root = std::env::temp_dir().join(format!("prpr-fs-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut fs = ExternalFileSystem(root.clone());
+        block_on(async {
+            fs.write_file("dir/a.txt", b"a".to_vec()).await.unwrap();
+            assert_eq!(fs.load_file("./dir/a.txt").await.unwrap(), b"a");
+            assert!(fs.write_file("../escaped.txt", b"x".to_vec()).await.is_err());
+            assert!(fs.write_file("dir/../../escaped.txt", b"x".to_vec()).await.is_err());
+            let absolute = root.join("dir/a.txt").display().to_string();
+            assert!(fs.load_file(&absolute).await.is_err());
+            assert!(fs.exists("../").await.is_err());
+            assert!(fs.remove_file("../escaped.txt").await.is_err());
+        });
+        assert!(!root.parent().unwrap().join("escaped.txt").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
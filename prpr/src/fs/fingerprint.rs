@@ -0,0 +1,179 @@
+use super::FileSystem;
+use crate::{
+    core::{Anim, AnimFloat, AnimVector, Chart, JudgeLineKind, NoteKind, Object, Tweenable},
+    info::ChartInfo,
+    parse::load_chart,
+};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Values are rounded to this precision before being hashed, so that float noise doesn't change the fingerprint.
+const PRECISION: f64 = 1e4;
+
+struct Canonical(Sha256);
+
+impl Canonical {
+    fn u64(&mut self, value: u64) {
+        self.0.update(value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        // NaN becomes 0 and infinities saturate
+        self.0.update(((value as f64 * PRECISION).round() as i64).to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u64(value.len() as u64);
+        self.0.update(value.as_bytes());
+    }
+
+    fn anim<T: Tweenable>(&mut self, anim: &Anim<T>, value: &impl Fn(&mut Self, &T)) {
+        self.u64(anim.keyframes.len() as u64);
+        for kf in anim.keyframes.iter() {
+            self.f32(kf.time);
+            value(self, &kf.value);
+            // a few samples tell the easings apart
+            for x in [0.25, 0.5, 0.75] {
+                self.f32(kf.tween.y(x));
+            }
+        }
+        match &anim.next {
+            Some(next) => {
+                self.u64(1);
+                self.anim(next, value);
+            }
+            None => self.u64(0),
+        }
+    }
+
+    fn float(&mut self, anim: &AnimFloat) {
+        self.anim(anim, &|this, value| this.f32(*value));
+    }
+
+    fn vector(&mut self, anim: &AnimVector) {
+        self.float(&anim.0);
+        self.float(&anim.1);
+    }
+
+    fn object(&mut self, object: &Object) {
+        self.float(&object.alpha);
+        self.vector(&object.scale);
+        self.float(&object.rotation);
+        self.vector(&object.translation);
+    }
+
+    fn chart(&mut self, chart: &Chart) {
+        self.f32(chart.offset);
+        let ranges = chart.bpm_list.borrow().ranges();
+        self.u64(ranges.len() as u64);
+        for (beats, bpm) in ranges {
+            self.f32(beats);
+            self.f32(bpm);
+        }
+        self.u64(chart.lines.len() as u64);
+        for line in &chart.lines {
+            self.object(&line.object);
+            let ctrl = line.ctrl_obj.borrow();
+            for anim in [&ctrl.alpha, &ctrl.size, &ctrl.pos, &ctrl.y] {
+                self.float(anim);
+            }
+            match &line.kind {
+                JudgeLineKind::Normal => self.u64(0),
+                JudgeLineKind::Texture(texture) => {
+                    self.u64(1);
+                    self.f32(texture.width());
+                    self.f32(texture.height());
+                }
+                JudgeLineKind::Text(text) => {
+                    self.u64(2);
+                    self.anim(text, &|this, value| this.str(value));
+                }
+                JudgeLineKind::Paint(paint, _) => {
+                    self.u64(3);
+                    self.float(paint);
+                }
+            }
+            self.float(&line.height);
+            self.float(&line.incline);
+            self.anim(&line.color, &|this, value| {
+                for c in [value.r, value.g, value.b, value.a] {
+                    this.f32(c);
+                }
+            });
+            self.u64(line.parent.map_or(0, |it| it as u64 + 1));
+            self.u64(line.z_index as i64 as u64);
+            self.u64(line.show_below as u64);
+            self.u64(line.attach_ui.map_or(0, |it| it as u64 + 1));
+
+            self.u64(line.notes.len() as u64);
+            for note in &line.notes {
+                self.object(&note.object);
+                match note.kind {
+                    NoteKind::Click => self.u64(0),
+                    NoteKind::Hold { end_time, end_height } => {
+                        self.u64(1);
+                        self.f32(end_time);
+                        self.f32(end_height);
+                    }
+                    NoteKind::Flick => self.u64(2),
+                    NoteKind::Drag => self.u64(3),
+                }
+                self.f32(note.time);
+                self.f32(note.height);
+                self.f32(note.speed);
+                self.f32(note.visible_time);
+                self.u64(note.above as u64);
+                self.u64(note.fake as u64);
+            }
+        }
+    }
+}
+
+/// A stable hash of the content of `chart` (notes, events and BPM) and the music, as a hex string. Files of the chart may be renamed or
+/// repackaged without changing it.
+pub fn chart_fingerprint(chart: &Chart, music: &[u8]) -> String {
+    let mut canonical = Canonical(Sha256::new());
+    canonical.chart(chart);
+    canonical.u64(music.len() as u64);
+    canonical.0.update(music);
+    format!("{:x}", canonical.0.finalize())
+}
+
+/// Loads the chart and its music from `fs` and computes their fingerprint. See [chart_fingerprint].
+pub async fn fingerprint(fs: &mut Box<dyn FileSystem>, info: &ChartInfo) -> Result<String> {
+    let music = fs.load_file(&info.music).await.context("Failed to load music")?;
+    let chart = load_chart(fs, info).await?;
+    Ok(chart_fingerprint(&chart, &music))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{block_on, fixture, zip_fs};
+
+    fn info(chart: &str, music: &str) -> ChartInfo {
+        ChartInfo {
+            chart: chart.to_owned(),
+            music: music.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn independent_of_packaging() {
+        let music = b"music".to_vec();
+        let mut first = zip_fs(&[("chart.pec", fixture("simple.pec")), ("music.ogg", music.clone())]);
+        let mut second = zip_fs(&[("song.mp3", music), ("notes.txt", Vec::new()), ("charts/hard.pec", fixture("simple.pec"))]);
+        let first = block_on(fingerprint(&mut first, &info("chart.pec", "music.ogg"))).unwrap();
+        let second = block_on(fingerprint(&mut second, &info("charts/hard.pec", "song.mp3"))).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn depends_on_music() {
+        let mut first = zip_fs(&[("chart.pec", fixture("simple.pec")), ("music.ogg", b"music".to_vec())]);
+        let mut second = zip_fs(&[("chart.pec", fixture("simple.pec")), ("music.ogg", b"other music".to_vec())]);
+        let info = info("chart.pec", "music.ogg");
+        assert_ne!(block_on(fingerprint(&mut first, &info)).unwrap(), block_on(fingerprint(&mut second, &info)).unwrap());
+    }
+}
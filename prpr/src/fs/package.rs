@@ -0,0 +1,117 @@
+use super::FileSystem;
+use crate::{info::ChartInfo, parse::load_chart};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    io::{Cursor, Write},
+    sync::{Arc, Mutex},
+};
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipWriter};
+
+// keeps every file loaded through it, which are exactly the files the chart refers to
+struct RecordingFileSystem(Box<dyn FileSystem>, Arc<Mutex<BTreeMap<String, Vec<u8>>>>);
+
+#[async_trait]
+impl FileSystem for RecordingFileSystem {
+    async fn load_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        let data = self.0.load_file(path).await?;
+        self.1.lock().unwrap().insert(path.to_owned(), data.clone());
+        Ok(data)
+    }
+
+    async fn exists(&mut self, path: &str) -> Result<bool> {
+        self.0.exists(path).await
+    }
+
+    async fn write_file(&mut self, _path: &str, _data: Vec<u8>) -> Result<()> {
+        bail!("Cannot write while packaging")
+    }
+
+    async fn remove_file(&mut self, _path: &str) -> Result<()> {
+        bail!("Cannot remove while packaging")
+    }
+
+    fn list_root(&self) -> Result<Vec<String>> {
+        self.0.list_root()
+    }
+
+    fn list_all(&self) -> Result<Vec<String>> {
+        self.0.list_all()
+    }
+
+    fn clone_box(&self) -> Box<dyn FileSystem> {
+        Box::new(Self(self.0.clone_box(), Arc::clone(&self.1)))
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Packs the chart in `fs` into a zip with only the files it uses: `info.yml` written from `info`, the chart, the music, the illustration and
+/// whatever the chart and `extra.json` refer to (textures, shaders, videos...). Files keep the paths they are referred to by, and are
+/// written in sorted order and without timestamps, so that the same chart always gives the same archive.
+pub async fn package_chart(fs: &dyn FileSystem, info: &ChartInfo) -> Result<Vec<u8>> {
+    let files = Arc::new(Mutex::new(BTreeMap::new()));
+    let mut recorder: Box<dyn FileSystem> = Box::new(RecordingFileSystem(fs.clone_box(), Arc::clone(&files)));
+    load_chart(&mut recorder, info).await.context("Failed to load chart")?;
+    for path in [&info.music, &info.illustration] {
+        recorder.load_file(path).await.with_context(|| format!("Failed to load {path}"))?;
+    }
+    drop(recorder);
+    let mut files = std::mem::take(&mut *files.lock().unwrap());
+    files.insert("info.yml".to_owned(), serde_yaml::to_string(info)?.into_bytes());
+
+    let mut buffer = Vec::new();
+    let mut w = ZipWriter::new(Cursor::new(&mut buffer));
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(DateTime::default())
+        .unix_permissions(0o644);
+    for (path, data) in files {
+        w.start_file(path, options)?;
+        w.write_all(&data)?;
+    }
+    w.finish()?;
+    drop(w);
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fs::ZipFileSystem,
+        testing::{block_on, fixture, zip_fs},
+    };
+
+    #[test]
+    fn only_referenced_files() {
+        let fs = zip_fs(&[
+            ("simple.pec", fixture("simple.pec")),
+            ("music.ogg", b"music".to_vec()),
+            ("assets/bg.png", b"illustration".to_vec()),
+            ("unused.png", b"unused".to_vec()),
+            ("info.yml", b"name: stale".to_vec()),
+        ]);
+        let info = ChartInfo {
+            chart: "simple.pec".to_owned(),
+            music: "music.ogg".to_owned(),
+            illustration: "assets/bg.png".to_owned(),
+            ..Default::default()
+        };
+        let bytes = block_on(package_chart(fs.as_ref(), &info)).unwrap();
+        assert_eq!(bytes, block_on(package_chart(fs.as_ref(), &info)).unwrap());
+
+        let mut packaged = ZipFileSystem::new(bytes).unwrap();
+        assert_eq!(packaged.list_all().unwrap(), ["assets/bg.png", "info.yml", "music.ogg", "simple.pec"]);
+        block_on(async {
+            assert_eq!(packaged.load_file("simple.pec").await.unwrap(), fixture("simple.pec"));
+            assert_eq!(packaged.load_file("assets/bg.png").await.unwrap(), b"illustration");
+            let info: ChartInfo = serde_yaml::from_slice(&packaged.load_file("info.yml").await.unwrap()).unwrap();
+            assert_eq!(info.illustration, "assets/bg.png");
+        });
+    }
+}
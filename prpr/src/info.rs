@@ -6,6 +6,8 @@ pub enum ChartFormat {
     Rpe,
     Pec,
     Pgr,
+    /// osu!mania or Malody
+    Mania,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -1,6 +1,6 @@
 use crate::{
     config::Config,
-    core::{BadNote, Chart, NoteKind, Point, Resource, Vector, JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR},
+    core::{BadNote, Chart, NoteKind, Point, Resource, Stage, Vector, JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR},
     ext::{get_viewport, NotNanExt},
 };
 use macroquad::prelude::{
@@ -10,12 +10,24 @@ use macroquad::prelude::{
 use miniquad::{EventHandler, MouseButton};
 use once_cell::sync::Lazy;
 use sasa::{PlaySfxParams, Sfx};
+use serde::{Deserialize, Serialize};
 use std::{
     cell::RefCell,
     collections::{HashMap, VecDeque},
     num::FpCategory,
 };
 
+mod input;
+pub use input::InputSource;
+
+mod keyboard;
+pub use keyboard::{KeyBinding, KeyboardConfig, KeyboardInput, LaneTarget};
+
+mod midi;
+#[cfg(all(feature = "midi", not(any(target_os = "android", target_os = "ios"))))]
+pub use midi::MidiInput;
+pub use midi::{MidiConfig, PadBinding};
+
 pub const FLICK_SPEED_THRESHOLD: f32 = 1.8;
 pub const LIMIT_PERFECT: f32 = 0.08;
 pub const LIMIT_GOOD: f32 = 0.16;
@@ -23,6 +35,75 @@ pub const LIMIT_BAD: f32 = 0.22;
 pub const UP_TOLERANCE: f32 = 0.01;
 pub const DIST_FACTOR: f32 = 0.2;
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JudgePreset {
+    #[default]
+    Official,
+    Strict,
+    Lenient,
+}
+
+impl JudgePreset {
+    pub fn rules(self) -> JudgeRules {
+        match self {
+            Self::Official => JudgeRules::OFFICIAL,
+            Self::Strict => JudgeRules::STRICT,
+            Self::Lenient => JudgeRules::LENIENT,
+        }
+    }
+}
+
+/// Judge windows (in seconds) and scoring weights.
+#[derive(Clone, Copy, Debug)]
+pub struct JudgeRules {
+    pub preset: JudgePreset,
+    pub perfect: f32,
+    pub good: f32,
+    pub bad: f32,
+    pub flick_speed_threshold: f32,
+    /// Share of the score given by accuracy; the rest is given by max combo
+    pub accuracy_weight: f64,
+    /// Accuracy of a good relative to a perfect
+    pub good_accuracy: f64,
+}
+
+impl JudgeRules {
+    pub const OFFICIAL: Self = Self {
+        preset: JudgePreset::Official,
+        perfect: LIMIT_PERFECT,
+        good: LIMIT_GOOD,
+        bad: LIMIT_BAD,
+        flick_speed_threshold: FLICK_SPEED_THRESHOLD,
+        accuracy_weight: 0.9,
+        good_accuracy: 0.65,
+    };
+    pub const STRICT: Self = Self {
+        preset: JudgePreset::Strict,
+        perfect: 0.04,
+        good: 0.08,
+        bad: 0.14,
+        flick_speed_threshold: FLICK_SPEED_THRESHOLD,
+        accuracy_weight: 1.,
+        good_accuracy: 0.5,
+    };
+    pub const LENIENT: Self = Self {
+        preset: JudgePreset::Lenient,
+        perfect: 0.12,
+        good: 0.2,
+        bad: 0.26,
+        flick_speed_threshold: 1.4,
+        accuracy_weight: 0.9,
+        good_accuracy: 0.65,
+    };
+}
+
+impl Default for JudgeRules {
+    fn default() -> Self {
+        Self::OFFICIAL
+    }
+}
+
 pub fn play_sfx(sfx: &mut Sfx, config: &Config) {
     if config.volume_sfx <= 1e-2 {
         return;
@@ -112,10 +193,10 @@ impl VelocityTracker {
         b
     }
 
-    pub fn has_flick(&mut self, res: &Resource) -> bool {
+    pub fn has_flick(&mut self, res: &impl Stage, threshold: f32) -> bool {
         let spd = self.speed();
         let norm = spd.norm();
-        let threshold = FLICK_SPEED_THRESHOLD * (res.dpi as f32 / 275.);
+        let threshold = threshold * (res.dpi() as f32 / 275.);
         if self.wait && (norm <= threshold * (1.2 / 1.8) || (self.last_dir.dot(&spd.unscale(norm)) - 1.).abs() > 0.4) {
             self.wait = false;
         }
@@ -137,7 +218,7 @@ pub enum JudgeStatus {
 }
 
 #[repr(u8)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Judgement {
     Perfect,
     Good,
@@ -145,6 +226,32 @@ pub enum Judgement {
     Miss,
 }
 
+/// Timing of a single hit note.
+#[derive(Clone, Debug)]
+pub struct NoteOffset {
+    pub line: usize,
+    pub kind: NoteKind,
+    pub judgement: Judgement,
+    /// Time of the note in the chart
+    pub time: f32,
+    /// Positive if the note was hit late, in seconds (scaled by the play speed)
+    pub offset: f32,
+}
+
+/// Input of a single frame. `touches` are in chart coordinates, while `events` (all touch events since the last frame) are normalized to
+/// `[-1, 1]` in screen space.
+#[derive(Clone, Default)]
+pub struct FrameInput {
+    pub touches: Vec<Touch>,
+    pub events: Vec<Touch>,
+    /// Number of keys pressed in this frame
+    pub keys_down: u32,
+    /// Number of keys being held
+    pub key_down_count: u32,
+    /// Ids of touches that flick in this frame regardless of their speed
+    pub flicks: Vec<u64>,
+}
+
 #[repr(C)]
 pub struct Judge {
     // notes of each line in order
@@ -153,6 +260,8 @@ pub struct Judge {
     trackers: HashMap<u64, VelocityTracker>,
     last_time: f32,
     diffs: Vec<f32>,
+    offsets: Vec<NoteOffset>,
+    pub rules: JudgeRules,
 
     pub combo: u32,
     pub max_combo: u32,
@@ -166,7 +275,7 @@ thread_local! {
 }
 
 impl Judge {
-    pub fn new(chart: &Chart) -> Self {
+    pub fn new(chart: &Chart, rules: JudgeRules) -> Self {
         let notes = chart
             .lines
             .iter()
@@ -181,6 +290,8 @@ impl Judge {
             trackers: HashMap::new(),
             last_time: 0.,
             diffs: Vec::new(),
+            offsets: Vec::new(),
+            rules,
 
             combo: 0,
             max_combo: 0,
@@ -196,6 +307,7 @@ impl Judge {
         self.max_combo = 0;
         self.counts = [0; 4];
         self.diffs.clear();
+        self.offsets.clear();
     }
 
     pub fn commit(&mut self, what: Judgement, diff: Option<f32>) {
@@ -218,7 +330,7 @@ impl Judge {
     }
 
     pub fn accuracy(&self) -> f64 {
-        (self.counts[0] as f64 + self.counts[1] as f64 * 0.65) / self.num_of_notes as f64
+        (self.counts[0] as f64 + self.counts[1] as f64 * self.rules.good_accuracy) / self.num_of_notes as f64
     }
 
     pub fn score(&self) -> u32 {
@@ -226,7 +338,8 @@ impl Judge {
         if self.counts[0] == self.num_of_notes {
             TOTAL
         } else {
-            let score = (0.9 * self.accuracy() + self.max_combo as f64 / self.num_of_notes as f64 * 0.1) * TOTAL as f64;
+            let weight = self.rules.accuracy_weight;
+            let score = (weight * self.accuracy() + self.max_combo as f64 / self.num_of_notes as f64 * (1. - weight)) * TOTAL as f64;
             score.round() as u32
         }
     }
@@ -265,66 +378,96 @@ impl Judge {
         })
     }
 
+    /// Collects the input of this frame from the window.
+    pub fn frame_input() -> FrameInput {
+        let mut touches = touches();
+        let btn = MouseButton::Left;
+        let id = button_to_id(btn);
+        if is_mouse_button_pressed(btn) {
+            let p = mouse_position();
+            touches.push(Touch {
+                id,
+                phase: TouchPhase::Started,
+                position: vec2(p.0, p.1),
+            });
+        } else if is_mouse_button_down(btn) {
+            let p = mouse_position();
+            touches.push(Touch {
+                id,
+                phase: TouchPhase::Moved,
+                position: vec2(p.0, p.1),
+            });
+        } else if is_mouse_button_released(btn) {
+            let p = mouse_position();
+            touches.push(Touch {
+                id,
+                phase: TouchPhase::Ended,
+                position: vec2(p.0, p.1),
+            });
+        }
+        let tr = Self::touch_transform();
+        for touch in &mut touches {
+            tr(touch);
+        }
+        let (mut events, keys_down, key_down_count) = TOUCHES.with(|it| {
+            let guard = it.borrow();
+            (guard.0.clone(), guard.2, guard.1)
+        });
+        for event in &mut events {
+            let p = event.position;
+            event.position = vec2(p.x / screen_width() * 2. - 1., p.y / screen_height() * 2. - 1.);
+        }
+        FrameInput {
+            touches,
+            events,
+            keys_down,
+            key_down_count,
+            flicks: Vec::new(),
+        }
+    }
+
     pub fn update(&mut self, res: &mut Resource, chart: &mut Chart, bad_notes: &mut Vec<BadNote>) {
-        if res.config.autoplay {
+        let input = if res.config.autoplay {
+            FrameInput::default()
+        } else {
+            Self::frame_input()
+        };
+        self.update_with(res, chart, bad_notes, input);
+    }
+
+    /// Judges a frame at `res.time()` with the given input. Unlike [Judge::update], this does not access the window.
+    pub fn update_with(&mut self, res: &mut impl Stage, chart: &mut Chart, bad_notes: &mut Vec<BadNote>, input: FrameInput) {
+        if res.config().autoplay {
             self.auto_play_update(res, chart);
             return;
         }
         const X_DIFF_MAX: f32 = 0.21 / (16. / 9.) * 2.;
-        let spd = res.config.speed;
+        let spd = res.config().speed;
+        let JudgeRules {
+            perfect: limit_perfect,
+            good: limit_good,
+            bad: limit_bad,
+            flick_speed_threshold,
+            ..
+        } = self.rules;
 
-        let t = res.time;
+        let t = res.time();
         // TODO optimize
-        let mut touches: HashMap<u64, Touch> = {
-            let mut touches = touches();
-            let btn = MouseButton::Left;
-            let id = button_to_id(btn);
-            if is_mouse_button_pressed(btn) {
-                let p = mouse_position();
-                touches.push(Touch {
-                    id,
-                    phase: TouchPhase::Started,
-                    position: vec2(p.0, p.1),
-                });
-            } else if is_mouse_button_down(btn) {
-                let p = mouse_position();
-                touches.push(Touch {
-                    id,
-                    phase: TouchPhase::Moved,
-                    position: vec2(p.0, p.1),
-                });
-            } else if is_mouse_button_released(btn) {
-                let p = mouse_position();
-                touches.push(Touch {
-                    id,
-                    phase: TouchPhase::Ended,
-                    position: vec2(p.0, p.1),
-                });
-            }
-            let tr = Self::touch_transform();
-            touches
-                .into_iter()
-                .map(|mut it| {
-                    tr(&mut it);
-                    (it.id, it)
-                })
-                .collect()
-        };
-        let (events, keys_down) = TOUCHES.with(|it| {
-            let guard = it.borrow();
-            (guard.0.clone(), guard.2)
-        });
-        let key_down_count = TOUCHES.with(|it| it.borrow().1);
+        let mut touches: HashMap<u64, Touch> = input.touches.into_iter().map(|it| (it.id, it)).collect();
+        let FrameInput {
+            events,
+            keys_down,
+            key_down_count,
+            flicks,
+            ..
+        } = input;
         {
-            fn to_local(Vec2 { x, y }: Vec2) -> Point {
-                Point::new(x / screen_width() * 2. - 1., y / screen_height() * 2. - 1.)
-            }
             let delta = (t - self.last_time) as f64 / (events.len() + 1) as f64;
             let mut t = self.last_time as f64;
             for Touch { id, phase, position: p } in events.into_iter() {
                 t += delta;
                 let t = t as f32;
-                let p = to_local(p);
+                let p = Point::new(p.x, p.y);
                 match phase {
                     TouchPhase::Started => {
                         self.trackers.insert(id, VelocityTracker::new(t / spd, p));
@@ -348,7 +491,9 @@ impl Judge {
                 }
             }
         }
-        let touches: Vec<Touch> = touches.into_values().collect();
+        let mut touches: Vec<Touch> = touches.into_values().collect();
+        // keep the order deterministic so that replays judge the same way
+        touches.sort_by_key(|it| it.id);
         // pos[line][touch]
         let mut pos = Vec::<Vec<Option<Point>>>::with_capacity(chart.lines.len());
         for id in 0..pos.capacity() {
@@ -377,11 +522,15 @@ impl Judge {
         for (id, touch) in touches.iter().enumerate() {
             let click = touch.phase == TouchPhase::Started;
             let flick = matches!(touch.phase, TouchPhase::Moved | TouchPhase::Stationary)
-                && self.trackers.get_mut(&touch.id).map_or(false, |it| it.has_flick(res));
+                && (flicks.contains(&touch.id)
+                    || self
+                        .trackers
+                        .get_mut(&touch.id)
+                        .map_or(false, |it| it.has_flick(res, flick_speed_threshold)));
             if !(click || flick) {
                 continue;
             }
-            let mut closest = (None, X_DIFF_MAX, LIMIT_BAD);
+            let mut closest = (None, X_DIFF_MAX, limit_bad);
             for (line_id, ((line, pos), (idx, st))) in chart.lines.iter_mut().zip(pos.iter()).zip(self.notes.iter_mut()).enumerate() {
                 let Some(pos) = pos[id] else { continue; };
                 for id in &idx[*st..] {
@@ -404,9 +553,9 @@ impl Judge {
                     }
                     if dt.abs()
                         > if matches!(note.kind, NoteKind::Click) {
-                            LIMIT_BAD - LIMIT_PERFECT * (dist - 0.9).max(0.)
+                            limit_bad - limit_perfect * (dist - 0.9).max(0.)
                         } else {
-                            LIMIT_GOOD
+                            limit_good
                         }
                     {
                         continue;
@@ -417,7 +566,8 @@ impl Judge {
                         } else {
                             0.
                         };
-                    if dt + (dist / res.note_width - 1.).max(0.) * DIST_FACTOR < closest.2 + (closest.1 / res.note_width - 1.).max(0.) * DIST_FACTOR {
+                    let note_width = res.note_width();
+                    if dt + (dist / note_width - 1.).max(0.) * DIST_FACTOR < closest.2 + (closest.1 / note_width - 1.).max(0.) * DIST_FACTOR {
                         closest = (Some((line_id, *id)), dist, dt + 0.01);
                     }
                 }
@@ -434,21 +584,27 @@ impl Judge {
                         continue; // to next loop
                     }
                     let dt = (dt - 0.01).abs();
-                    if dt <= LIMIT_GOOD || matches!(note.kind, NoteKind::Hold { .. }) {
+                    if dt <= limit_good || matches!(note.kind, NoteKind::Hold { .. }) {
                         match note.kind {
                             NoteKind::Click => {
                                 note.judge = JudgeStatus::Judged;
-                                judgements.push((if dt <= LIMIT_PERFECT { Judgement::Perfect } else { Judgement::Good }, line_id, id, None));
+                                judgements.push((
+                                    if dt <= limit_perfect { Judgement::Perfect } else { Judgement::Good },
+                                    line_id,
+                                    id,
+                                    Some((t - note.time) / spd),
+                                ));
                             }
                             NoteKind::Hold { .. } => {
-                                play_sfx(&mut res.sfx_click, &res.config);
-                                note.judge = JudgeStatus::Hold(dt <= LIMIT_PERFECT, t, (t - note.time) / spd, false, f32::INFINITY);
+                                res.play_sfx(&note.kind);
+                                note.judge = JudgeStatus::Hold(dt <= limit_perfect, t, (t - note.time) / spd, false, f32::INFINITY);
                             }
                             _ => unreachable!(),
                         };
                     } else {
-                        line.notes[id as usize].judge = JudgeStatus::Judged;
-                        judgements.push((Judgement::Bad, line_id, id, None));
+                        let note = &mut line.notes[id as usize];
+                        note.judge = JudgeStatus::Judged;
+                        judgements.push((Judgement::Bad, line_id, id, Some((t - note.time) / spd)));
                     }
                 } else {
                     // flick
@@ -480,26 +636,26 @@ impl Judge {
             {
                 let note = &mut chart.lines[line_id].notes[id as usize];
                 let dt = (t - note.time).abs() / spd;
-                if dt <= if matches!(note.kind, NoteKind::Click) { LIMIT_BAD } else { LIMIT_GOOD } {
+                if dt <= if matches!(note.kind, NoteKind::Click) { limit_bad } else { limit_good } {
                     match note.kind {
                         NoteKind::Click => {
                             note.judge = JudgeStatus::Judged;
                             judgements.push((
-                                if dt <= LIMIT_PERFECT {
+                                if dt <= limit_perfect {
                                     Judgement::Perfect
-                                } else if dt <= LIMIT_GOOD {
+                                } else if dt <= limit_good {
                                     Judgement::Good
                                 } else {
                                     Judgement::Bad
                                 },
                                 line_id,
                                 id,
-                                None,
+                                Some((t - note.time) / spd),
                             ));
                         }
                         NoteKind::Hold { .. } => {
-                            play_sfx(&mut res.sfx_click, &res.config);
-                            note.judge = JudgeStatus::Hold(dt <= LIMIT_PERFECT, t, (t - note.time) / spd, false, f32::INFINITY);
+                            res.play_sfx(&note.kind);
+                            note.judge = JudgeStatus::Hold(dt <= limit_perfect, t, (t - note.time) / spd, false, f32::INFINITY);
                         }
                         _ => unreachable!(),
                     };
@@ -514,7 +670,7 @@ impl Judge {
                 let note = &mut line.notes[*id as usize];
                 if let NoteKind::Hold { end_time, .. } = &note.kind {
                     if let JudgeStatus::Hold(.., ref mut pre_judge, ref mut up_time) = note.judge {
-                        if (*end_time - t) / spd <= LIMIT_BAD {
+                        if (*end_time - t) / spd <= limit_bad {
                             *pre_judge = true;
                             continue;
                         }
@@ -539,12 +695,12 @@ impl Judge {
                 }
                 // process miss
                 let dt = (t - note.time) / spd;
-                if dt > LIMIT_BAD {
+                if dt > limit_bad {
                     note.judge = JudgeStatus::Judged;
                     judgements.push((Judgement::Miss, line_id, *id, None));
                     continue;
                 }
-                if -t > LIMIT_BAD {
+                if -t > limit_bad {
                     break;
                 }
                 if !matches!(note.kind, NoteKind::Drag) && (key_down_count == 0 || !matches!(note.kind, NoteKind::Flick)) {
@@ -558,7 +714,7 @@ impl Judge {
                     || pos.iter().any(|it| {
                         it.map_or(false, |it| {
                             let dx = (it.x - x).abs();
-                            dx <= X_DIFF_MAX && dt <= (LIMIT_BAD - LIMIT_PERFECT * (dx - 0.9).max(0.))
+                            dx <= X_DIFF_MAX && dt <= (limit_bad - limit_perfect * (dx - 0.9).max(0.))
                         })
                     })
                 {
@@ -602,24 +758,33 @@ impl Judge {
             let line = &chart.lines[line_id];
             let note = &line.notes[id as usize];
             let line_tr = line.now_transform(res, &chart.lines);
-            self.commit(
-                judgement,
-                if matches!(judgement, Judgement::Good | Judgement::Bad) {
-                    Some(diff.unwrap_or((t - note.time) / spd))
-                } else {
-                    None
-                },
-            );
+            let diff = match judgement {
+                Judgement::Good | Judgement::Bad => Some(diff.unwrap_or((t - note.time) / spd)),
+                Judgement::Perfect => diff,
+                Judgement::Miss => None,
+            };
+            self.commit(judgement, diff.filter(|_| judgement != Judgement::Perfect));
+            if let Some(offset) = diff {
+                self.offsets.push(NoteOffset {
+                    line: line_id,
+                    kind: note.kind.clone(),
+                    judgement,
+                    time: note.time,
+                    offset,
+                });
+            }
             if matches!(note.kind, NoteKind::Hold { .. }) {
                 continue;
             }
             if match judgement {
                 Judgement::Perfect => {
-                    res.with_model(line_tr * note.object.now(res), |res| res.emit_at_origin(note.rotation(line), JUDGE_LINE_PERFECT_COLOR));
+                    let model = line_tr * note.object.now(res);
+                    res.emit(model, note.rotation(line), JUDGE_LINE_PERFECT_COLOR);
                     true
                 }
                 Judgement::Good => {
-                    res.with_model(line_tr * note.object.now(res), |res| res.emit_at_origin(note.rotation(line), JUDGE_LINE_GOOD_COLOR));
+                    let model = line_tr * note.object.now(res);
+                    res.emit(model, note.rotation(line), JUDGE_LINE_GOOD_COLOR);
                     true
                 }
                 Judgement::Bad => {
@@ -636,7 +801,7 @@ impl Judge {
                                 mat *= note.now_transform(
                                     res,
                                     &line.ctrl_obj.borrow_mut(),
-                                    (note.height - line.height.now()) / res.aspect_ratio * note.speed,
+                                    (note.height - line.height.now()) / res.aspect_ratio() * note.speed,
                                     incline_sin,
                                 );
                                 mat
@@ -647,14 +812,7 @@ impl Judge {
                 }
                 _ => false,
             } {
-                if let Some(sfx) = match note.kind {
-                    NoteKind::Click => Some(&mut res.sfx_click),
-                    NoteKind::Drag => Some(&mut res.sfx_drag),
-                    NoteKind::Flick => Some(&mut res.sfx_flick),
-                    _ => None,
-                } {
-                    play_sfx(sfx, &res.config);
-                }
+                res.play_sfx(&note.kind);
             }
         }
         for (line, (idx, st)) in chart.lines.iter().zip(self.notes.iter_mut()) {
@@ -668,9 +826,9 @@ impl Judge {
         self.last_time = t;
     }
 
-    fn auto_play_update(&mut self, res: &mut Resource, chart: &mut Chart) {
-        let t = res.time;
-        let spd = res.config.speed;
+    fn auto_play_update(&mut self, res: &mut impl Stage, chart: &mut Chart) {
+        let t = res.time();
+        let spd = res.config().speed;
         let mut judgements = Vec::new();
         for (line_id, (line, (idx, st))) in chart.lines.iter_mut().zip(self.notes.iter_mut()).enumerate() {
             for id in &idx[*st..] {
@@ -691,7 +849,7 @@ impl Judge {
                     break;
                 }
                 note.judge = if matches!(note.kind, NoteKind::Hold { .. }) {
-                    play_sfx(&mut res.sfx_click, &res.config);
+                    res.play_sfx(&note.kind);
                     JudgeStatus::Hold(true, t, (t - note.time) / spd, false, f32::INFINITY)
                 } else {
                     judgements.push((line_id, *id));
@@ -716,16 +874,10 @@ impl Judge {
                 (note.object.now(res), note.kind.clone())
             };
             let line = &chart.lines[line_id];
-            res.with_model(line.now_transform(res, &chart.lines) * note_transform, |res| {
-                res.emit_at_origin(line.notes[id as usize].rotation(line), JUDGE_LINE_PERFECT_COLOR)
-            });
-            if let Some(sfx) = match note_kind {
-                NoteKind::Click => Some(&mut res.sfx_click),
-                NoteKind::Drag => Some(&mut res.sfx_drag),
-                NoteKind::Flick => Some(&mut res.sfx_flick),
-                _ => None,
-            } {
-                play_sfx(sfx, &res.config);
+            let model = line.now_transform(res, &chart.lines) * note_transform;
+            res.emit(model, line.notes[id as usize].rotation(line), JUDGE_LINE_PERFECT_COLOR);
+            if !matches!(note_kind, NoteKind::Hold { .. }) {
+                res.play_sfx(&note_kind);
             }
         }
     }
@@ -736,10 +888,13 @@ impl Judge {
             score: self.score(),
             accuracy: self.accuracy(),
             max_combo: self.max_combo,
+            combo: self.combo,
             num_of_notes: self.num_of_notes,
             counts: self.counts,
             early,
             late: self.diffs.len() as u32 - early,
+            offsets: self.offsets.clone(),
+            rules: self.rules.preset,
         }
     }
 }
@@ -806,13 +961,117 @@ impl<'a> EventHandler for Handler<'a> {
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct PlayResult {
     pub score: u32,
     pub accuracy: f64,
     pub max_combo: u32,
+    /// The combo at the end of the run
+    pub combo: u32,
     pub num_of_notes: u32,
     pub counts: [u32; 4],
     pub early: u32,
     pub late: u32,
+    /// Timing of every note hit with a known offset, in the order they were judged
+    pub offsets: Vec<NoteOffset>,
+    /// The ruleset the run was judged with
+    pub rules: JudgePreset,
+}
+
+impl PlayResult {
+    /// Mean and standard deviation of the offsets, in seconds.
+    pub fn offset_stats(&self) -> Option<(f32, f32)> {
+        if self.offsets.is_empty() {
+            return None;
+        }
+        let n = self.offsets.len() as f32;
+        let mean = self.offsets.iter().map(|it| it.offset).sum::<f32>() / n;
+        let variance = self.offsets.iter().map(|it| (it.offset - mean).powi(2)).sum::<f32>() / n;
+        Some((mean, variance.sqrt()))
+    }
+
+    /// The offset (in seconds, scaled by the play speed) that would have centered the hits of clicks and holds, if there are any.
+    pub fn suggested_offset(&self) -> Option<f32> {
+        let offsets: Vec<f32> = self
+            .offsets
+            .iter()
+            .filter(|it| matches!(it.kind, NoteKind::Click | NoteKind::Hold { .. }))
+            .map(|it| it.offset)
+            .collect();
+        robust_median(&offsets)
+    }
+}
+
+fn median(values: &mut [f32]) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    Some(if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.
+    } else {
+        values[mid]
+    })
+}
+
+/// Median of `values` after dropping the outliers, which are more than three median absolute deviations away from the median.
+pub fn robust_median(values: &[f32]) -> Option<f32> {
+    let mut values = values.to_vec();
+    let center = median(&mut values)?;
+    let mad = median(&mut values.iter().map(|it| (it - center).abs()).collect::<Vec<_>>())?;
+    if mad <= f32::EPSILON {
+        return Some(center);
+    }
+    let mut kept: Vec<f32> = values.into_iter().filter(|it| (it - center).abs() <= mad * 3.).collect();
+    median(&mut kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset(kind: NoteKind, offset: f32) -> NoteOffset {
+        NoteOffset {
+            line: 0,
+            kind,
+            judgement: Judgement::Perfect,
+            time: 0.,
+            offset,
+        }
+    }
+
+    #[test]
+    fn median_without_outliers() {
+        assert_eq!(robust_median(&[]), None);
+        assert_eq!(robust_median(&[3., 1., 2.]), Some(2.));
+        assert_eq!(robust_median(&[4., 1., 3., 2.]), Some(2.5));
+        // 100 is more than 3 MADs (1.5) away from the median and is dropped, which moves the median from 2.5 to 2
+        assert_eq!(robust_median(&[0., 1., 2., 3., 4., 100.]), Some(2.));
+        // with most values equal the MAD is 0, and the median is kept as is
+        assert_eq!(robust_median(&[1., 1., 1., 50.]), Some(1.));
+    }
+
+    #[test]
+    fn suggested_offset_ignores_drags_and_flicks() {
+        let mut result = PlayResult {
+            offsets: vec![offset(NoteKind::Drag, -0.1), offset(NoteKind::Flick, -0.1)],
+            ..Default::default()
+        };
+        assert_eq!(result.suggested_offset(), None);
+        result.offsets.extend([
+            offset(NoteKind::Click, 0.01),
+            offset(NoteKind::Click, 0.03),
+            offset(
+                NoteKind::Hold {
+                    end_time: 1.,
+                    end_height: 1.,
+                },
+                0.02,
+            ),
+            offset(NoteKind::Drag, -0.1),
+            offset(NoteKind::Click, 0.02),
+        ]);
+        assert_eq!(result.suggested_offset(), Some(0.02));
+    }
 }
@@ -0,0 +1,136 @@
+use super::{FrameInput, JudgeStatus, LaneTarget};
+use crate::core::{Chart, Point, Stage};
+use macroquad::prelude::*;
+
+// ids of the touches simulated by input sources, counting down from here (the mouse takes the topmost ids)
+pub(crate) const KEYBOARD_TOUCH_ID: u64 = u64::MAX - 16;
+pub(crate) const MIDI_TOUCH_ID: u64 = KEYBOARD_TOUCH_ID - 256;
+
+/// Something besides the window that feeds touches into the judge, once per frame.
+pub trait InputSource {
+    /// Adds the touches of this frame to `input`.
+    fn apply(&mut self, input: &mut FrameInput, res: &dyn Stage, chart: &mut Chart);
+}
+
+fn lane_position(res: &(impl Stage + ?Sized), chart: &mut Chart, target: &LaneTarget) -> Vec2 {
+    match target {
+        LaneTarget::X(x) => vec2(*x, 0.),
+        LaneTarget::Line(index) => {
+            let t = res.time();
+            for line in &mut chart.lines {
+                line.object.set_time(t);
+            }
+            let Some(line) = chart.lines.get_mut(*index) else {
+                return vec2(0., 0.);
+            };
+            let x = line
+                .notes
+                .iter_mut()
+                .filter(|it| !it.fake && matches!(it.judge, JudgeStatus::NotJudged | JudgeStatus::PreJudge | JudgeStatus::Hold(..)))
+                .min_by(|a, b| a.time.total_cmp(&b.time))
+                .map_or(0., |note| {
+                    note.object.translation.0.set_time(t);
+                    note.object.translation.0.now()
+                });
+            let p = chart.lines[*index].now_transform(res, &chart.lines).transform_point(&Point::new(x, 0.));
+            // the judge flips y
+            vec2(p.x, -p.y)
+        }
+    }
+}
+
+/// Pushes the touch of a lane in the given phase, flicking it if asked to while it's held.
+pub(crate) fn push_lane(
+    input: &mut FrameInput,
+    res: &(impl Stage + ?Sized),
+    chart: &mut Chart,
+    id: u64,
+    phase: TouchPhase,
+    target: &LaneTarget,
+    flick: bool,
+) {
+    input.touches.push(Touch {
+        id,
+        phase,
+        position: lane_position(res, chart, target),
+    });
+    if flick && phase == TouchPhase::Moved {
+        input.flicks.push(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        core::{ChartExtra, HeadlessResource},
+        info::ChartInfo,
+        parse::parse_pec,
+    };
+
+    // line 1 sits at (0.5, -0.5) with a fake note and two real ones at 0.5s and 1s, 0.25 left and 0.5 right of its center
+    const SOURCE: &str = "150
+bp 0.00 120.00
+cv 0 0.00 7.00
+cp 0 0.00 1024.00 700.00
+cd 0 0.00 0.00
+ca 0 0.00 255
+cv 1 0.00 7.00
+cp 1 0.00 1536.00 350.00
+cd 1 0.00 0.00
+ca 1 0.00 255
+n1 1 0.50 1024.00 1 1
+# 1.00
+& 1.00
+n1 1 1.00 -256.00 1 0
+# 1.00
+& 1.00
+n1 1 2.00 512.00 1 0
+# 1.00
+& 1.00
+";
+
+    fn lane(res: &HeadlessResource, chart: &mut Chart, phase: TouchPhase, target: LaneTarget, flick: bool) -> FrameInput {
+        let mut input = FrameInput::default();
+        push_lane(&mut input, res, chart, 1, phase, &target, flick);
+        input
+    }
+
+    fn judge(chart: &mut Chart, time: f32) {
+        chart.lines[1].notes.iter_mut().find(|it| (it.time - time).abs() < 1e-4).unwrap().judge = JudgeStatus::Judged;
+    }
+
+    #[test]
+    fn line_lanes_follow_next_note() {
+        let mut chart = parse_pec(SOURCE, ChartExtra::default()).unwrap();
+        let res = HeadlessResource::new(Config::default(), &ChartInfo::default());
+        let y = 0.5 / res.aspect_ratio;
+
+        let input = lane(&res, &mut chart, TouchPhase::Started, LaneTarget::Line(1), false);
+        assert_eq!(input.touches[0].position, vec2(0.25, y));
+        judge(&mut chart, 0.5);
+        let input = lane(&res, &mut chart, TouchPhase::Started, LaneTarget::Line(1), false);
+        assert_eq!(input.touches[0].position, vec2(1., y));
+
+        // once the notes are done the lane stays at the center of the line
+        judge(&mut chart, 1.);
+        let input = lane(&res, &mut chart, TouchPhase::Started, LaneTarget::Line(1), false);
+        assert_eq!(input.touches[0].position, vec2(0.5, y));
+        let input = lane(&res, &mut chart, TouchPhase::Started, LaneTarget::Line(5), false);
+        assert_eq!(input.touches[0].position, vec2(0., 0.));
+    }
+
+    #[test]
+    fn held_lanes_flick() {
+        let mut chart = parse_pec(SOURCE, ChartExtra::default()).unwrap();
+        let res = HeadlessResource::new(Config::default(), &ChartInfo::default());
+        let input = lane(&res, &mut chart, TouchPhase::Started, LaneTarget::X(-0.6), true);
+        assert_eq!(input.touches[0].position, vec2(-0.6, 0.));
+        assert!(input.flicks.is_empty());
+        let input = lane(&res, &mut chart, TouchPhase::Moved, LaneTarget::X(-0.6), true);
+        assert_eq!(input.flicks, [1]);
+        let input = lane(&res, &mut chart, TouchPhase::Moved, LaneTarget::X(-0.6), false);
+        assert!(input.flicks.is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use super::{
+    input::{push_lane, KEYBOARD_TOUCH_ID},
+    FrameInput, InputSource,
+};
+use crate::core::{Chart, Stage};
+use anyhow::{bail, Result};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Where a key touches.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LaneTarget {
+    /// A horizontal position on screen, from -1 (left) to 1 (right)
+    X(f32),
+    /// The next note on the judge line with this index
+    Line(usize),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct KeyBinding {
+    pub key: String,
+    #[serde(flatten)]
+    pub target: LaneTarget,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyboardConfig {
+    pub lanes: Vec<KeyBinding>,
+    /// Held lanes flick while this key is pressed
+    pub flick: Option<String>,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        Self {
+            lanes: [("D", -0.6), ("F", -0.2), ("J", 0.2), ("K", 0.6)]
+                .into_iter()
+                .map(|(key, x)| KeyBinding {
+                    key: key.to_owned(),
+                    target: LaneTarget::X(x),
+                })
+                .collect(),
+            flick: Some("LeftShift".to_owned()),
+        }
+    }
+}
+
+fn key_from_name(name: &str) -> Option<KeyCode> {
+    use KeyCode::*;
+    const LETTERS: [KeyCode; 26] = [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z];
+    const DIGITS: [KeyCode; 10] = [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    if let [c] = name.as_bytes() {
+        let c = c.to_ascii_uppercase();
+        if c.is_ascii_uppercase() {
+            return Some(LETTERS[(c - b'A') as usize]);
+        }
+        if c.is_ascii_digit() {
+            return Some(DIGITS[(c - b'0') as usize]);
+        }
+    }
+    Some(match name {
+        "Space" => Space,
+        "Enter" => Enter,
+        "Tab" => Tab,
+        "LeftShift" => LeftShift,
+        "RightShift" => RightShift,
+        "LeftControl" => LeftControl,
+        "RightControl" => RightControl,
+        "LeftAlt" => LeftAlt,
+        "RightAlt" => RightAlt,
+        "Left" => Left,
+        "Right" => Right,
+        "Up" => Up,
+        "Down" => Down,
+        ";" | "Semicolon" => Semicolon,
+        "'" | "Apostrophe" => Apostrophe,
+        "," | "Comma" => Comma,
+        "." | "Period" => Period,
+        "/" | "Slash" => Slash,
+        "[" | "LeftBracket" => LeftBracket,
+        "]" | "RightBracket" => RightBracket,
+        "\\" | "Backslash" => Backslash,
+        "-" | "Minus" => Minus,
+        "=" | "Equal" => Equal,
+        _ => return None,
+    })
+}
+
+/// Turns held keys into touches, according to a [KeyboardConfig].
+pub struct KeyboardInput {
+    lanes: Vec<(KeyCode, LaneTarget)>,
+    flick: Option<KeyCode>,
+}
+
+impl KeyboardInput {
+    pub fn new(config: &KeyboardConfig) -> Result<Self> {
+        let parse = |name: &str| {
+            let Some(key) = key_from_name(name) else {
+                bail!("Unknown key: {name}");
+            };
+            Ok(key)
+        };
+        Ok(Self {
+            lanes: config
+                .lanes
+                .iter()
+                .map(|it| Ok((parse(&it.key)?, it.target.clone())))
+                .collect::<Result<_>>()?,
+            flick: config.flick.as_deref().map(parse).transpose()?,
+        })
+    }
+}
+
+impl InputSource for KeyboardInput {
+    /// Adds the touches of held keys to `input`. Keys no longer count as "any key" presses in keyboard mode.
+    fn apply(&mut self, input: &mut FrameInput, res: &dyn Stage, chart: &mut Chart) {
+        input.keys_down = 0;
+        input.key_down_count = 0;
+        let flick = self.flick.map_or(false, is_key_pressed);
+        for (index, (key, target)) in self.lanes.iter().enumerate() {
+            let phase = if is_key_pressed(*key) {
+                TouchPhase::Started
+            } else if is_key_down(*key) {
+                TouchPhase::Moved
+            } else if is_key_released(*key) {
+                TouchPhase::Ended
+            } else {
+                continue;
+            };
+            push_lane(input, res, chart, KEYBOARD_TOUCH_ID - index as u64, phase, target, flick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_names() {
+        assert_eq!(key_from_name("d"), Some(KeyCode::D));
+        assert_eq!(key_from_name("D"), Some(KeyCode::D));
+        assert_eq!(key_from_name("7"), Some(KeyCode::Key7));
+        assert_eq!(key_from_name("LeftShift"), Some(KeyCode::LeftShift));
+        assert_eq!(key_from_name(";"), Some(KeyCode::Semicolon));
+        assert_eq!(key_from_name("Semicolon"), Some(KeyCode::Semicolon));
+        assert_eq!(key_from_name(""), None);
+        assert_eq!(key_from_name("Shift"), None);
+        assert_eq!(key_from_name("é"), None);
+    }
+
+    #[test]
+    fn config_validation() {
+        let input = KeyboardInput::new(&KeyboardConfig::default()).unwrap();
+        assert_eq!(input.lanes.iter().map(|it| it.0).collect::<Vec<_>>(), [KeyCode::D, KeyCode::F, KeyCode::J, KeyCode::K]);
+        assert_eq!(input.flick, Some(KeyCode::LeftShift));
+
+        let mut config = KeyboardConfig::default();
+        config.lanes[2].key = "Shift".to_owned();
+        assert_eq!(KeyboardInput::new(&config).err().unwrap().to_string(), "Unknown key: Shift");
+        let config = KeyboardConfig {
+            flick: Some("Fn".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(KeyboardInput::new(&config).err().unwrap().to_string(), "Unknown key: Fn");
+        let config = KeyboardConfig {
+            flick: None,
+            ..Default::default()
+        };
+        assert_eq!(KeyboardInput::new(&config).unwrap().flick, None);
+    }
+}
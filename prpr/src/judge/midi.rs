@@ -0,0 +1,213 @@
+use super::LaneTarget;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PadBinding {
+    /// MIDI note number of the pad, on any channel
+    pub note: u8,
+    #[serde(flatten)]
+    pub target: LaneTarget,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+pub struct MidiConfig {
+    /// Connects to the first input port whose name contains this, or to the first port at all
+    pub port: Option<String>,
+    /// Creates a virtual port for other programs to connect to instead (Unix only)
+    pub virtual_port: bool,
+    pub pads: Vec<PadBinding>,
+    /// Held pads flick when the pad with this note is hit
+    pub flick: Option<u8>,
+}
+
+impl Default for MidiConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            virtual_port: false,
+            // the usual 8 pads of a drum pad controller, left to right
+            pads: (0..8)
+                .map(|index| PadBinding {
+                    note: 36 + index,
+                    target: LaneTarget::X(-0.875 + index as f32 * 0.25),
+                })
+                .collect(),
+            flick: None,
+        }
+    }
+}
+
+// separate from the device so that it can be tested without one
+#[cfg(any(test, all(feature = "midi", not(any(target_os = "android", target_os = "ios")))))]
+mod events {
+    use macroquad::prelude::TouchPhase;
+    use std::collections::VecDeque;
+
+    /// The (note, on) of a note on or off message. A note on with a velocity of 0 is a note off.
+    pub(super) fn parse_message(message: &[u8]) -> Option<(u8, bool)> {
+        match *message {
+            [status, note, velocity] if status & 0xF0 == 0x90 => Some((note, velocity != 0)),
+            [status, note, _] if status & 0xF0 == 0x80 => Some((note, false)),
+            _ => None,
+        }
+    }
+
+    /// The touch of one pad. At most one phase is sent per frame, so hits closer than that are delayed rather than lost.
+    #[derive(Default)]
+    pub(super) struct Pad {
+        down: bool,
+        phases: VecDeque<TouchPhase>,
+    }
+
+    impl Pad {
+        pub(super) fn hit(&mut self, on: bool) {
+            if on != self.down {
+                self.down = on;
+                self.phases.push_back(if on { TouchPhase::Started } else { TouchPhase::Ended });
+            }
+        }
+
+        pub(super) fn next_phase(&mut self) -> Option<TouchPhase> {
+            self.phases.pop_front().or(self.down.then_some(TouchPhase::Moved))
+        }
+    }
+}
+
+#[cfg(all(feature = "midi", not(any(target_os = "android", target_os = "ios"))))]
+pub use device::MidiInput;
+
+#[cfg(all(feature = "midi", not(any(target_os = "android", target_os = "ios"))))]
+mod device {
+    use super::{
+        events::{parse_message, Pad},
+        MidiConfig,
+    };
+    use crate::{
+        core::{Chart, Stage},
+        judge::{
+            input::{push_lane, MIDI_TOUCH_ID},
+            FrameInput, InputSource, LaneTarget,
+        },
+    };
+    use anyhow::{anyhow, Context, Result};
+    use midir::{Ignore, MidiInputConnection};
+    use std::sync::{Arc, Mutex};
+
+    /// Turns hits on the pads of a MIDI device into touches, according to a [MidiConfig].
+    pub struct MidiInput {
+        pads: Vec<(u8, LaneTarget, Pad)>,
+        flick: Option<u8>,
+        // (note, on) in the order they arrived
+        events: Arc<Mutex<Vec<(u8, bool)>>>,
+        _connection: MidiInputConnection<()>,
+    }
+
+    impl MidiInput {
+        pub fn new(config: &MidiConfig) -> Result<Self> {
+            let mut midi = midir::MidiInput::new("prpr").context("Failed to initialize MIDI")?;
+            midi.ignore(Ignore::All);
+            let events: Arc<Mutex<Vec<(u8, bool)>>> = Arc::default();
+            let sink = Arc::clone(&events);
+            let callback = move |_: u64, message: &[u8], _: &mut ()| {
+                if let Some(event) = parse_message(message) {
+                    sink.lock().unwrap().push(event);
+                }
+            };
+            let connection = if config.virtual_port {
+                #[cfg(unix)]
+                {
+                    use midir::os::unix::VirtualInput;
+                    midi.create_virtual("prpr", callback, ())
+                }
+                #[cfg(not(unix))]
+                {
+                    anyhow::bail!("Virtual MIDI ports are only supported on Unix");
+                }
+            } else {
+                let ports = midi.ports();
+                let port = match &config.port {
+                    Some(name) => ports
+                        .iter()
+                        .find(|port| midi.port_name(port).map_or(false, |it| it.contains(name.as_str())))
+                        .with_context(|| format!("MIDI input port not found: {name}"))?,
+                    None => ports.first().context("No MIDI input port available")?,
+                }
+                .clone();
+                midi.connect(&port, "prpr", callback, ())
+            }
+            .map_err(|err| anyhow!("Failed to connect to MIDI input: {err}"))?;
+            Ok(Self {
+                pads: config.pads.iter().map(|it| (it.note, it.target.clone(), Pad::default())).collect(),
+                flick: config.flick,
+                events,
+                _connection: connection,
+            })
+        }
+    }
+
+    impl InputSource for MidiInput {
+        fn apply(&mut self, input: &mut FrameInput, res: &dyn Stage, chart: &mut Chart) {
+            let mut flick = false;
+            for (note, on) in self.events.lock().unwrap().drain(..) {
+                if on && self.flick == Some(note) {
+                    flick = true;
+                }
+                for (_, _, pad) in self.pads.iter_mut().filter(|it| it.0 == note) {
+                    pad.hit(on);
+                }
+            }
+            for (index, (_, target, pad)) in self.pads.iter_mut().enumerate() {
+                let Some(phase) = pad.next_phase() else {
+                    continue;
+                };
+                push_lane(input, res, chart, MIDI_TOUCH_ID - index as u64, phase, target, flick);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::events::*;
+    use macroquad::prelude::TouchPhase;
+
+    #[test]
+    fn messages() {
+        assert_eq!(parse_message(&[0x90, 36, 100]), Some((36, true)));
+        // on any channel
+        assert_eq!(parse_message(&[0x99, 38, 1]), Some((38, true)));
+        assert_eq!(parse_message(&[0x90, 36, 0]), Some((36, false)));
+        assert_eq!(parse_message(&[0x80, 36, 64]), Some((36, false)));
+        assert_eq!(parse_message(&[0xB0, 7, 100]), None);
+        assert_eq!(parse_message(&[0x90, 36]), None);
+    }
+
+    // phases sent over `frames` frames, with `hits` arriving before the first one
+    fn phases(pad: &mut Pad, hits: &[bool], frames: usize) -> Vec<Option<TouchPhase>> {
+        for on in hits {
+            pad.hit(*on);
+        }
+        (0..frames).map(|_| pad.next_phase()).collect()
+    }
+
+    #[test]
+    fn pad_phases() {
+        use TouchPhase::*;
+        let mut pad = Pad::default();
+        assert_eq!(phases(&mut pad, &[true], 2), [Some(Started), Some(Moved)]);
+        assert_eq!(phases(&mut pad, &[false], 2), [Some(Ended), None]);
+        // a note on repeated while held changes nothing
+        assert_eq!(phases(&mut pad, &[true, true], 2), [Some(Started), Some(Moved)]);
+        assert_eq!(phases(&mut pad, &[false, false], 2), [Some(Ended), None]);
+
+        // a tap within a frame still starts and ends
+        assert_eq!(phases(&mut pad, &[true, false], 3), [Some(Started), Some(Ended), None]);
+        // two of them, neither is lost
+        assert_eq!(phases(&mut pad, &[true, false, true, false], 5), [Some(Started), Some(Ended), Some(Started), Some(Ended), None]);
+        // released and hit again in the same frame
+        assert_eq!(phases(&mut pad, &[true], 1), [Some(Started)]);
+        assert_eq!(phases(&mut pad, &[false, true], 3), [Some(Ended), Some(Started), Some(Moved)]);
+    }
+}
@@ -4,9 +4,15 @@ pub mod ext;
 pub mod fs;
 pub mod info;
 pub mod judge;
+pub mod lint;
 pub mod parse;
 pub mod particle;
+pub mod replay;
 pub mod scene;
+pub mod sim;
+pub mod stats;
+#[cfg(test)]
+mod testing;
 pub mod time;
 pub mod ui;
 
@@ -0,0 +1,298 @@
+use crate::{
+    core::{Chart, NoteKind, EPS},
+    fs::{self, FileSystem},
+    parse::load_chart,
+};
+use anyhow::Result;
+use sasa::AudioClip;
+use serde::Serialize;
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+    ops::DerefMut,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum DiagnosticKind {
+    /// The event overlaps with the previous one, which spans `[start, end)`.
+    OverlappingEvents {
+        desc: String,
+        start: f32,
+        end: f32,
+    },
+    HoldEndsBeforeStart {
+        end_time: f32,
+    },
+    NoteBeyondTrack {
+        track_length: f32,
+    },
+    UnknownTween {
+        id: i32,
+    },
+    ZeroLengthBpm {
+        beats: f32,
+    },
+    /// A fake note sits on top of the real note with index `real` on the same line.
+    FakeOnReal {
+        real: usize,
+    },
+    MissingTexture {
+        path: String,
+    },
+    IgnoredKey {
+        key: String,
+    },
+}
+
+impl Display for DiagnosticKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OverlappingEvents { desc, start, end } => write!(f, "{desc} event overlaps with the previous one [{start}, {end})"),
+            Self::HoldEndsBeforeStart { end_time } => write!(f, "hold ends ({end_time}) before it starts"),
+            Self::NoteBeyondTrack { track_length } => write!(f, "note is beyond the end of the music ({track_length})"),
+            Self::UnknownTween { id } => write!(f, "unknown tween id {id}, falling back to linear"),
+            Self::ZeroLengthBpm { beats } => write!(f, "BPM segment at beat {beats} has zero length"),
+            Self::FakeOnReal { real } => write!(f, "fake note is stacked on real note #{real}"),
+            Self::MissingTexture { path } => write!(f, "texture {path} is missing"),
+            Self::IgnoredKey { key } => write!(f, "key {key} is ignored"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    pub line: Option<usize>,
+    pub note: Option<usize>,
+    pub time: Option<f32>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, kind: DiagnosticKind) -> Self {
+        Self {
+            severity,
+            kind,
+            line: None,
+            note: None,
+            time: None,
+        }
+    }
+
+    pub fn line(mut self, line: usize) -> Self {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn note(mut self, note: usize) -> Self {
+        self.note = Some(note);
+        self
+    }
+
+    pub fn time(mut self, time: f32) -> Self {
+        self.time = Some(time);
+        self
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self.severity {
+                Severity::Info => "info",
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            }
+        )?;
+        if let Some(line) = self.line {
+            write!(f, " [line #{line}")?;
+            if let Some(note) = self.note {
+                write!(f, ", note #{note}")?;
+            }
+            write!(f, "]")?;
+        }
+        if let Some(time) = self.time {
+            write!(f, " @{time:.3}s")?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+thread_local! {
+    static DIAGNOSTICS: RefCell<Option<Vec<Diagnostic>>> = RefCell::default();
+    static LINE: Cell<Option<usize>> = Cell::default();
+}
+
+/// Records a diagnostic found by the parsers. It is dropped unless [check] is running.
+pub(crate) fn report(mut diagnostic: Diagnostic) {
+    if diagnostic.line.is_none() {
+        diagnostic.line = LINE.with(|it| it.get());
+    }
+    DIAGNOSTICS.with(|it| {
+        if let Some(diagnostics) = it.borrow_mut().as_mut() {
+            diagnostics.push(diagnostic);
+        }
+    });
+}
+
+/// Whether [check] is running. Parsers then carry on past the problems they report instead of failing.
+pub(crate) fn active() -> bool {
+    DIAGNOSTICS.with(|it| it.borrow().is_some())
+}
+
+/// Sets the judge line that subsequent reports without a line refer to.
+pub(crate) fn set_line(line: Option<usize>) {
+    LINE.with(|it| it.set(line));
+}
+
+/// Runs `f` and returns what it reported, along with its result.
+#[cfg(test)]
+pub(crate) fn capture<R>(f: impl FnOnce() -> R) -> (R, Vec<Diagnostic>) {
+    DIAGNOSTICS.with(|it| *it.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    set_line(None);
+    (result, DIAGNOSTICS.with(|it| it.borrow_mut().take()).unwrap_or_default())
+}
+
+/// Checks a loaded chart. `track_length` is the length of the music, if known.
+pub fn lint_chart(chart: &Chart, track_length: Option<f32>) -> Vec<Diagnostic> {
+    let mut result = Vec::new();
+    let mut bpm_list = chart.bpm_list.borrow().clone();
+    for w in bpm_list.ranges().windows(2) {
+        if w[1].0 - w[0].0 < EPS {
+            let time = bpm_list.time_beats(w[0].0);
+            result.push(Diagnostic::new(Severity::Warning, DiagnosticKind::ZeroLengthBpm { beats: w[0].0 }).time(time));
+        }
+    }
+    for (id, line) in chart.lines.iter().enumerate() {
+        let x = |index: usize| line.notes[index].object.translation.0.keyframes.first().map_or(0., |it| it.value);
+        for (index, note) in line.notes.iter().enumerate() {
+            let here = |severity, kind| Diagnostic::new(severity, kind).line(id).note(index).time(note.time);
+            let end_time = if let NoteKind::Hold { end_time, .. } = note.kind {
+                if end_time < note.time {
+                    result.push(here(Severity::Error, DiagnosticKind::HoldEndsBeforeStart { end_time }));
+                }
+                end_time
+            } else {
+                note.time
+            };
+            if let Some(track_length) = track_length {
+                if end_time.max(note.time) > track_length {
+                    result.push(here(Severity::Warning, DiagnosticKind::NoteBeyondTrack { track_length }));
+                }
+            }
+            if note.fake {
+                if let Some(real) = line
+                    .notes
+                    .iter()
+                    .enumerate()
+                    .position(|(other, it)| !it.fake && (it.time - note.time).abs() < EPS && (x(other) - x(index)).abs() < EPS)
+                {
+                    result.push(here(Severity::Warning, DiagnosticKind::FakeOnReal { real }));
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Loads the chart in `fs` and returns every diagnostic found, sorted by severity (most severe first).
+pub async fn check(fs: &mut Box<dyn FileSystem>) -> Result<Vec<Diagnostic>> {
+    DIAGNOSTICS.with(|it| *it.borrow_mut() = Some(Vec::new()));
+    let chart = async {
+        let info = fs::load_info(fs.deref_mut()).await?;
+        let track_length = fs
+            .load_file(&info.music)
+            .await
+            .ok()
+            .and_then(|it| AudioClip::new(it).ok())
+            .map(|it| it.length());
+        let chart = load_chart(fs, &info).await?;
+        Ok::<_, anyhow::Error>((chart, track_length))
+    }
+    .await;
+    set_line(None);
+    let mut result = DIAGNOSTICS.with(|it| it.borrow_mut().take()).unwrap_or_default();
+    let (chart, track_length) = chart?;
+    result.extend(lint_chart(&chart, track_length));
+    result.sort_by_key(|it| std::cmp::Reverse(it.severity));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::ChartExtra,
+        parse::{parse_pec, parse_phigros, parse_rpe},
+        testing::{block_on, fixture_str, zip_fs},
+    };
+
+    // (line, kind) of every diagnostic, in a stable order
+    fn kinds(diagnostics: &[Diagnostic]) -> Vec<(Option<usize>, String)> {
+        let mut res: Vec<_> = diagnostics
+            .iter()
+            .map(|it| (it.line, serde_json::to_value(&it.kind).unwrap()["type"].as_str().unwrap().to_owned()))
+            .collect();
+        res.sort();
+        res
+    }
+
+    fn expected(kinds: &[(Option<usize>, &str)]) -> Vec<(Option<usize>, String)> {
+        let mut res: Vec<_> = kinds.iter().map(|(line, kind)| (*line, kind.to_string())).collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn pec() {
+        let (chart, mut diagnostics) = capture(|| parse_pec(&fixture_str("broken.pec"), ChartExtra::default()).unwrap());
+        diagnostics.extend(lint_chart(&chart, Some(10.)));
+        assert_eq!(
+            kinds(&diagnostics),
+            expected(&[
+                (Some(0), "overlappingEvents"),
+                (Some(0), "fakeOnReal"),
+                (Some(0), "holdEndsBeforeStart"),
+                (Some(0), "noteBeyondTrack"),
+                (None, "zeroLengthBpm"),
+            ])
+        );
+    }
+
+    #[test]
+    fn rpe() {
+        let source = fixture_str("rpe_broken.json");
+        let (chart, diagnostics) = capture(|| block_on(parse_rpe(&source, zip_fs(&[]).as_mut(), ChartExtra::default())));
+        assert!(chart.is_ok());
+        assert_eq!(kinds(&diagnostics), expected(&[(Some(1), "overlappingEvents"), (Some(1), "unknownTween"), (Some(1), "missingTexture")]));
+
+        // outside of linting, a missing texture is still an error
+        let err = block_on(parse_rpe(&source, zip_fs(&[]).as_mut(), ChartExtra::default())).err().unwrap();
+        assert!(err.chain().any(|it| it.to_string() == "加载插图 missing.png 失败"));
+    }
+
+    #[test]
+    fn pgr() {
+        let source = fixture_str("pgr_overlap.json");
+        let (chart, diagnostics) = capture(|| parse_phigros(&source, ChartExtra::default()));
+        assert!(chart.is_ok());
+        assert_eq!(kinds(&diagnostics), expected(&[(Some(1), "overlappingEvents")]));
+        assert!(matches!(&diagnostics[0].kind, DiagnosticKind::OverlappingEvents { desc, .. } if desc == "move"));
+
+        let err = parse_phigros(&source, ChartExtra::default()).err().unwrap();
+        assert!(err.chain().any(|it| it.to_string() == "Events should be contiguous"));
+    }
+}
@@ -1,14 +1,20 @@
 mod extra;
 pub use extra::parse_extra;
 
+mod load;
+pub use load::{load_chart, FFMPEG_PATH};
+
+mod mania;
+pub use mania::{is_mania, mania_info, parse_mania};
+
 mod pec;
-pub use pec::parse_pec;
+pub use pec::{export_pec, parse_pec};
 
 mod pgr;
-pub use pgr::parse_phigros;
+pub use pgr::{export_phigros, parse_phigros};
 
 mod rpe;
-pub use rpe::{parse_rpe, RPE_HEIGHT, RPE_WIDTH};
+pub use rpe::{export_rpe, parse_rpe, RPE_HEIGHT, RPE_WIDTH};
 
 fn process_lines(v: &mut [crate::core::JudgeLine]) {
     use crate::ext::NotNanExt;
@@ -83,3 +89,97 @@ const TWEEN_MAP: [crate::core::TweenId; 30] = {
         e(Bounce, InOut), e(Elastic, InOut),
     ]
 };
+
+/// Reverse lookup of [TWEEN_MAP], returning the easing number used by RPE and PEC.
+fn easing_of(tween: crate::core::TweenId) -> Option<u8> {
+    TWEEN_MAP.iter().skip(1).position(|it| *it == tween).map(|it| it as u8 + 1)
+}
+
+/// Eased segments are split into this many pieces when the target format can only express them linearly.
+const LINEARIZE_STEPS: usize = 16;
+
+/// Evaluates `anim` (including the layers chained after it) at `time`. With `left` set, the limit from the left is taken instead, which differs
+/// from the value at `time` when a keyframe jumps there.
+fn anim_value(anim: &crate::core::AnimFloat, time: f32, left: bool, default: f32) -> f32 {
+    fn inner(anim: &crate::core::AnimFloat, time: f32, left: bool) -> Option<f32> {
+        let kfs = &anim.keyframes;
+        if kfs.is_empty() {
+            return None;
+        }
+        let i = kfs.partition_point(|kf| if left { kf.time < time } else { kf.time <= time });
+        let value = if i == 0 {
+            kfs[0].value
+        } else if i == kfs.len() {
+            kfs[i - 1].value
+        } else {
+            let (kf, next) = (&kfs[i - 1], &kfs[i]);
+            kf.value + (next.value - kf.value) * kf.tween.y((time - kf.time) / (next.time - kf.time))
+        };
+        Some(value + anim.next.as_ref().and_then(|next| inner(next, time, left)).unwrap_or_default())
+    }
+    inner(anim, time, left).unwrap_or(default)
+}
+
+fn is_linear(tween: &dyn crate::core::TweenFunction) -> bool {
+    // hold, jump and linear
+    tween.as_any().downcast_ref::<crate::core::StaticTween>().map_or(false, |it| it.0 <= 2)
+}
+
+/// Collects the keyframe times of `anims` within `range` (both ends included). If `split` is set, eased segments are further divided into
+/// [LINEARIZE_STEPS] pieces so that they can be approximated linearly.
+fn breakpoints(anims: &[&crate::core::AnimFloat], range: std::ops::Range<f32>, split: bool) -> Vec<f32> {
+    use crate::ext::NotNanExt;
+    fn collect(anim: &crate::core::AnimFloat, split: bool, times: &mut Vec<ordered_float::NotNan<f32>>) {
+        for (kf, next) in anim.keyframes.iter().zip(anim.keyframes.iter().skip(1)) {
+            times.push(kf.time.not_nan());
+            if split && next.time > kf.time && !is_linear(kf.tween.as_ref()) {
+                for step in 1..LINEARIZE_STEPS {
+                    times.push((kf.time + (next.time - kf.time) * step as f32 / LINEARIZE_STEPS as f32).not_nan());
+                }
+            }
+        }
+        if let Some(kf) = anim.keyframes.last() {
+            times.push(kf.time.not_nan());
+        }
+        if let Some(next) = &anim.next {
+            collect(next, split, times);
+        }
+    }
+    let mut times = vec![range.start.not_nan(), range.end.not_nan()];
+    for anim in anims {
+        collect(anim, split, &mut times);
+    }
+    times.retain(|it| **it >= range.start && **it <= range.end);
+    times.sort();
+    times.dedup();
+    times.into_iter().map(|it| *it).collect()
+}
+
+/// The time after which nothing in the chart changes anymore.
+fn max_time(chart: &crate::core::Chart) -> f32 {
+    use crate::{core::NoteKind, ext::NotNanExt};
+    *chart
+        .lines
+        .iter()
+        .flat_map(|line| {
+            let object = &line.object;
+            [
+                &line.height,
+                &object.alpha,
+                &object.rotation,
+                &object.translation.0,
+                &object.translation.1,
+            ]
+            .into_iter()
+            .filter_map(|anim| anim.keyframes.last().map(|it| it.time))
+            .chain(line.notes.iter().map(|note| match note.kind {
+                NoteKind::Hold { end_time, .. } => end_time,
+                _ => note.time,
+            }))
+            .map(|it| it.not_nan())
+            .collect::<Vec<_>>()
+        })
+        .max()
+        .unwrap_or_default()
+        + 1.
+}
@@ -0,0 +1,72 @@
+use super::{is_mania, parse_extra, parse_mania, parse_pec, parse_phigros, parse_rpe};
+use crate::{
+    core::{Chart, ChartExtra},
+    fs::FileSystem,
+    info::{ChartFormat, ChartInfo},
+};
+use anyhow::{bail, Context, Result};
+use concat_string::concat_string;
+use macroquad::prelude::warn;
+use std::{
+    io::ErrorKind,
+    ops::DerefMut,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Mutex,
+};
+
+pub static FFMPEG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+async fn load_chart_bytes(fs: &mut Box<dyn FileSystem>, info: &ChartInfo) -> Result<Vec<u8>> {
+    if let Ok(bytes) = fs.load_file(&info.chart).await {
+        return Ok(bytes);
+    }
+    if let Some(name) = info.chart.strip_suffix(".pec") {
+        if let Ok(bytes) = fs.load_file(&concat_string!(name, ".json")).await {
+            return Ok(bytes);
+        }
+    }
+    bail!("Cannot find chart file")
+}
+
+/// Loads the chart `info` describes from `fs`, along with its `extra.json` if there is one.
+pub async fn load_chart(fs: &mut Box<dyn FileSystem>, info: &ChartInfo) -> Result<Chart> {
+    let extra = fs.load_file("extra.json").await.ok().map(|it| String::from_utf8(it)).transpose()?;
+    let extra = if let Some(extra) = extra {
+        let ffmpeg: PathBuf = FFMPEG_PATH.lock().unwrap().to_owned().unwrap_or_else(|| "ffmpeg".into());
+        let ffmpeg = if match Command::new(&ffmpeg).stdout(Stdio::null()).stderr(Stdio::null()).spawn() {
+            Ok(_) => true,
+            Err(err) => err.kind() != ErrorKind::NotFound,
+        } {
+            Some(ffmpeg.as_path())
+        } else {
+            warn!("ffmpeg not found at {}, disabling video", ffmpeg.display());
+            None
+        };
+        parse_extra(&extra, fs.deref_mut(), ffmpeg).await.context("Failed to parse extra")?
+    } else {
+        ChartExtra::default()
+    };
+    let text = String::from_utf8(load_chart_bytes(fs, info).await.context("Failed to load chart")?)?;
+    let format = info.format.clone().unwrap_or_else(|| {
+        if is_mania(&text) {
+            ChartFormat::Mania
+        } else if text.starts_with('{') {
+            if text.contains("\"META\"") {
+                ChartFormat::Rpe
+            } else {
+                ChartFormat::Pgr
+            }
+        } else {
+            ChartFormat::Pec
+        }
+    });
+    let mut chart = match format {
+        ChartFormat::Rpe => parse_rpe(&text, fs.deref_mut(), extra).await,
+        ChartFormat::Pgr => parse_phigros(&text, extra),
+        ChartFormat::Pec => parse_pec(&text, extra),
+        ChartFormat::Mania => parse_mania(&text, extra),
+    }?;
+    chart.settings.hold_partial_cover = info.hold_partial_cover;
+    Ok(chart)
+}
@@ -0,0 +1,411 @@
+use super::process_lines;
+use crate::{
+    core::{
+        Anim, AnimFloat, AnimVector, BpmList, Chart, ChartExtra, ChartSettings, JudgeLine, JudgeLineCache, JudgeLineKind, Keyframe, Note, NoteKind,
+        Object,
+    },
+    info::ChartInfo,
+    judge::JudgeStatus,
+};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{cell::RefCell, collections::HashMap};
+
+// the line sits in the lower part of the screen, with notes falling onto it
+const LINE_Y: f32 = -0.6;
+const SCROLL_SPEED: f32 = 1.4;
+const LANES_WIDTH: f32 = 1.5;
+
+struct ManiaNote {
+    lane: u32,
+    time: f32,
+    end_time: Option<f32>,
+}
+
+struct ManiaChart {
+    offset: f32,
+    bpm_list: BpmList,
+    keys: u32,
+    notes: Vec<ManiaNote>,
+}
+
+/// Whether `source` looks like an osu! or Malody chart.
+pub fn is_mania(source: &str) -> bool {
+    let source = source.trim_start_matches('\u{feff}');
+    source.starts_with("osu file format") || (source.starts_with('{') && source.contains("\"meta\"") && source.contains("\"note\""))
+}
+
+fn is_osu(source: &str) -> bool {
+    source.trim_start_matches('\u{feff}').starts_with("osu file format")
+}
+
+fn osu_sections(source: &str) -> HashMap<&str, Vec<&str>> {
+    let mut sections = HashMap::new();
+    let mut current = "";
+    for line in source.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|it| it.strip_suffix(']')) {
+            current = name;
+        } else {
+            sections.entry(current).or_insert_with(Vec::new).push(line);
+        }
+    }
+    sections
+}
+
+fn osu_values<'a>(sections: &HashMap<&str, Vec<&'a str>>, section: &str) -> HashMap<&'a str, &'a str> {
+    sections
+        .get(section)
+        .into_iter()
+        .flatten()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect()
+}
+
+fn parse_osu(source: &str) -> Result<ManiaChart> {
+    let sections = osu_sections(source);
+    let general = osu_values(&sections, "General");
+    if general.get("Mode").map_or(true, |it| *it != "3") {
+        bail!("Only osu!mania charts are supported");
+    }
+    let keys: u32 = osu_values(&sections, "Difficulty")
+        .get("CircleSize")
+        .context("Missing key count (CircleSize)")?
+        .parse::<f32>()
+        .context("Invalid key count")? as u32;
+    if keys == 0 {
+        bail!("Invalid key count: 0");
+    }
+
+    // (time, bpm) of the uninherited timing points, inherited ones only change the scroll speed
+    let mut points = Vec::new();
+    for line in sections.get("TimingPoints").into_iter().flatten() {
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let (Some(time), Some(beat_length)) = (fields.first(), fields.get(1)) else {
+            bail!("Invalid timing point: {line}");
+        };
+        let time: f32 = time.parse().with_context(|| format!("Invalid timing point: {line}"))?;
+        let beat_length: f32 = beat_length.parse().with_context(|| format!("Invalid timing point: {line}"))?;
+        if fields.get(6).map_or(true, |it| *it == "1") && beat_length > 0. {
+            points.push((time / 1000., 60000. / beat_length));
+        }
+    }
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let bpm_list = match points.first() {
+        Some(&(_, first_bpm)) => {
+            // the first BPM also covers the time before its point
+            let mut ranges = vec![(0., first_bpm)];
+            let (mut last_time, mut last_bpm, mut beats) = (0., first_bpm, 0.);
+            for &(time, bpm) in &points[1..] {
+                beats += (time - last_time) * last_bpm / 60.;
+                ranges.push((beats, bpm));
+                (last_time, last_bpm) = (time, bpm);
+            }
+            BpmList::new(ranges)
+        }
+        None => BpmList::new(vec![(0., 120.)]),
+    };
+
+    let mut notes = Vec::new();
+    for line in sections.get("HitObjects").into_iter().flatten() {
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let parse = |index: usize| -> Result<f32> {
+            fields
+                .get(index)
+                .and_then(|it| it.parse().ok())
+                .with_context(|| format!("Invalid hit object: {line}"))
+        };
+        let lane = ((parse(0)? * keys as f32 / 512.).floor() as u32).min(keys - 1);
+        let time = parse(2)? / 1000.;
+        let kind = parse(3)? as u32;
+        let end_time = if kind & 128 != 0 {
+            let end = fields
+                .get(5)
+                .and_then(|it| it.split(':').next())
+                .and_then(|it| it.parse::<f32>().ok())
+                .with_context(|| format!("Invalid hold: {line}"))?;
+            Some(end / 1000.)
+        } else {
+            None
+        };
+        notes.push(ManiaNote { lane, time, end_time });
+    }
+    Ok(ManiaChart {
+        offset: 0.,
+        bpm_list,
+        keys,
+        notes,
+    })
+}
+
+fn beats([int, num, den]: [u32; 3]) -> f32 {
+    int as f32 + if den == 0 { 0. } else { num as f32 / den as f32 }
+}
+
+#[derive(Deserialize)]
+struct MalodySong {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    artist: String,
+}
+
+#[derive(Deserialize)]
+struct MalodyModeExt {
+    column: u32,
+}
+
+#[derive(Deserialize)]
+struct MalodyMeta {
+    #[serde(default)]
+    creator: String,
+    #[serde(default)]
+    background: String,
+    #[serde(default)]
+    version: String,
+    #[serde(default)]
+    mode: u32,
+    song: Option<MalodySong>,
+    mode_ext: Option<MalodyModeExt>,
+}
+
+#[derive(Deserialize)]
+struct MalodyTime {
+    beat: [u32; 3],
+    bpm: f32,
+}
+
+#[derive(Deserialize)]
+struct MalodyNote {
+    beat: [u32; 3],
+    endbeat: Option<[u32; 3]>,
+    column: Option<u32>,
+    // the note carrying the music has no column
+    sound: Option<String>,
+    #[serde(default)]
+    offset: f32,
+}
+
+#[derive(Deserialize)]
+struct MalodyChart {
+    meta: MalodyMeta,
+    time: Vec<MalodyTime>,
+    note: Vec<MalodyNote>,
+}
+
+fn parse_malody(source: &str) -> Result<ManiaChart> {
+    let mc: MalodyChart = serde_json::from_str(source).context("Failed to parse JSON")?;
+    if mc.meta.mode != 0 {
+        bail!("Only Malody key mode charts are supported");
+    }
+    let keys = mc.meta.mode_ext.context("Missing column count")?.column;
+    if keys == 0 {
+        bail!("Invalid column count: 0");
+    }
+    let mut ranges: Vec<_> = mc.time.iter().map(|it| (beats(it.beat), it.bpm)).collect();
+    ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if ranges.first().map_or(true, |it| it.0 > 0.) {
+        ranges.insert(0, (0., ranges.first().map_or(120., |it| it.1)));
+    }
+    let mut bpm_list = BpmList::new(ranges);
+    // the music starts this long (in ms) after beat 0
+    let offset = mc.note.iter().find(|it| it.sound.is_some()).map_or(0., |it| it.offset);
+    let notes = mc
+        .note
+        .iter()
+        .filter_map(|note| {
+            Some(ManiaNote {
+                lane: note.column?.min(keys - 1),
+                time: bpm_list.time_beats(beats(note.beat)),
+                end_time: note.endbeat.map(|it| bpm_list.time_beats(beats(it))),
+            })
+        })
+        .collect();
+    Ok(ManiaChart {
+        offset: -offset / 1000.,
+        bpm_list,
+        keys,
+        notes,
+    })
+}
+
+/// Parses an osu!mania (`.osu`) or Malody key mode (`.mc`) chart onto a single static judge line, with one position per lane.
+pub fn parse_mania(source: &str, extra: ChartExtra) -> Result<Chart> {
+    let mania = if is_osu(source) { parse_osu(source) } else { parse_malody(source) }?;
+    let lane_x = |lane: u32| ((lane * 2 + 1) as f32 / mania.keys as f32 - 1.) * LANES_WIDTH / 2.;
+    let mut notes: Vec<_> = mania
+        .notes
+        .iter()
+        .map(|note| Note {
+            object: Object {
+                translation: AnimVector(AnimFloat::fixed(lane_x(note.lane)), AnimFloat::default()),
+                ..Default::default()
+            },
+            kind: match note.end_time {
+                Some(end_time) if end_time > note.time => NoteKind::Hold {
+                    end_time,
+                    end_height: end_time * SCROLL_SPEED,
+                },
+                _ => NoteKind::Click,
+            },
+            time: note.time,
+            height: note.time * SCROLL_SPEED,
+            speed: 1.,
+            visible_time: f32::INFINITY,
+
+            above: true,
+            multiple_hint: false,
+            fake: false,
+            judge: JudgeStatus::NotJudged,
+        })
+        .collect();
+    if notes.is_empty() {
+        bail!("The chart has no notes");
+    }
+    let max_time = mania
+        .notes
+        .iter()
+        .map(|it| it.end_time.unwrap_or(it.time).max(it.time))
+        .fold(0., f32::max)
+        + 1.;
+    let cache = JudgeLineCache::new(&mut notes);
+    let mut lines = vec![JudgeLine {
+        object: Object {
+            translation: AnimVector(AnimFloat::default(), AnimFloat::fixed(LINE_Y)),
+            ..Default::default()
+        },
+        ctrl_obj: RefCell::default(),
+        kind: JudgeLineKind::Normal,
+        height: AnimFloat::new(vec![Keyframe::new(0., 0., 2), Keyframe::new(max_time, max_time * SCROLL_SPEED, 0)]),
+        incline: AnimFloat::default(),
+        notes,
+        color: Anim::default(),
+        parent: None,
+        z_index: 0,
+        show_below: false,
+        attach_ui: None,
+        group: None,
+
+        cache,
+    }];
+    process_lines(&mut lines);
+    Ok(Chart::new(mania.offset, lines, mania.bpm_list, ChartSettings::default(), extra))
+}
+
+/// Infers the chart information from the metadata of an osu! or Malody chart. Files named there may not exist.
+pub fn mania_info(source: &str) -> Result<ChartInfo> {
+    let mut info = ChartInfo::default();
+    if is_osu(source) {
+        let sections = osu_sections(source);
+        let general = osu_values(&sections, "General");
+        let metadata = osu_values(&sections, "Metadata");
+        let get = |key: &str| metadata.get(key).map(|it| it.to_string());
+        if let Some(name) = get("TitleUnicode").or_else(|| get("Title")) {
+            info.name = name;
+        }
+        if let Some(composer) = get("ArtistUnicode").or_else(|| get("Artist")) {
+            info.composer = composer;
+        }
+        if let Some(charter) = get("Creator") {
+            info.charter = charter;
+        }
+        if let Some(level) = get("Version") {
+            info.level = level;
+        }
+        if let Some(music) = general.get("AudioFilename") {
+            info.music = music.to_string();
+        }
+        if let Some(preview) = general.get("PreviewTime").and_then(|it| it.parse::<f32>().ok()).filter(|it| *it >= 0.) {
+            info.preview_time = preview / 1000.;
+        }
+        // background events look like `0,0,"bg.jpg",0,0`
+        if let Some(background) = sections
+            .get("Events")
+            .into_iter()
+            .flatten()
+            .find_map(|line| line.strip_prefix("0,0,").and_then(|it| it.split(',').next()))
+        {
+            info.illustration = background.trim_matches('"').to_owned();
+        }
+    } else {
+        let mc: MalodyChart = serde_json::from_str(source).context("Failed to parse JSON")?;
+        if let Some(song) = mc.meta.song {
+            info.name = song.title;
+            info.composer = song.artist;
+        }
+        info.charter = mc.meta.creator;
+        info.level = mc.meta.version;
+        if !mc.meta.background.is_empty() {
+            info.illustration = mc.meta.background;
+        }
+        if let Some(sound) = mc.note.into_iter().find_map(|it| it.sound) {
+            info.music = sound;
+        }
+    }
+    info.format = Some(crate::info::ChartFormat::Mania);
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{info::ChartFormat, testing::fixture_str};
+
+    // (time, lane x, hold end) of every note, by time
+    fn notes(chart: &Chart) -> Vec<(f32, f32, Option<f32>)> {
+        let mut res: Vec<_> = chart.lines[0]
+            .notes
+            .iter()
+            .map(|note| {
+                let end = match note.kind {
+                    NoteKind::Hold { end_time, .. } => Some(end_time),
+                    _ => None,
+                };
+                (note.time, note.object.translation.0.now(), end)
+            })
+            .collect();
+        res.sort_by(|a, b| a.0.total_cmp(&b.0));
+        res
+    }
+
+    #[test]
+    fn osu() {
+        let chart = parse_mania(&fixture_str("simple.osu"), ChartExtra::default()).unwrap();
+        assert_eq!(chart.lines.len(), 1);
+        // 4 lanes spread over LANES_WIDTH, the hold ends at 2.5s
+        assert_eq!(notes(&chart), [(0.5, -0.5625, None), (1., -0.1875, None), (1.5, 0.1875, Some(2.5)), (3., 0.5625, None)]);
+        // the inherited point is ignored, the uninherited one at 0 doesn't add an empty segment
+        assert_eq!(chart.bpm_list.borrow().ranges(), [(0., 120.), (4., 240.)]);
+        assert_eq!(chart.offset, 0.);
+    }
+
+    #[test]
+    fn malody() {
+        let chart = parse_mania(&fixture_str("simple.mc"), ChartExtra::default()).unwrap();
+        // 4 beats at 120 BPM, then 240 BPM; the sound note isn't a note
+        assert_eq!(notes(&chart), [(0.5, -0.5625, None), (1.25, 0.5625, None), (1.5, -0.1875, Some(2.25))]);
+        assert_eq!(chart.bpm_list.borrow().ranges(), [(0., 120.), (4., 240.)]);
+        // the music starts 120ms after beat 0, so the notes come that much later
+        assert_eq!(chart.offset, -0.12);
+    }
+
+    #[test]
+    fn info() {
+        let info = mania_info(&fixture_str("simple.osu")).unwrap();
+        assert_eq!((info.name.as_str(), info.composer.as_str(), info.charter.as_str(), info.level.as_str()), ("曲", "Artist", "Mapper", "Hard"));
+        assert_eq!((info.music.as_str(), info.illustration.as_str()), ("audio.mp3", "bg.jpg"));
+        assert_eq!(info.preview_time, 1.5);
+        assert!(matches!(info.format, Some(ChartFormat::Mania)));
+
+        let info = mania_info(&fixture_str("simple.mc")).unwrap();
+        assert_eq!(
+            (info.name.as_str(), info.composer.as_str(), info.charter.as_str(), info.level.as_str()),
+            ("Title", "Artist", "Charter", "4K Lv.10")
+        );
+        assert_eq!((info.music.as_str(), info.illustration.as_str()), ("song.ogg", "bg.png"));
+        assert!(matches!(info.format, Some(ChartFormat::Mania)));
+    }
+}
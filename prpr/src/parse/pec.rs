@@ -1,15 +1,16 @@
-use super::{process_lines, TWEEN_MAP};
+use super::{anim_value, breakpoints, easing_of, is_linear, max_time, process_lines, LINEARIZE_STEPS, TWEEN_MAP};
 use crate::{
     core::{
         Anim, AnimFloat, AnimVector, BpmList, Chart, ChartExtra, ChartSettings, JudgeLine, JudgeLineCache, JudgeLineKind, Keyframe, Note, NoteKind,
-        Object, TweenId, EPS,
+        Object, StaticTween, TweenId, EPS,
     },
     ext::NotNanExt,
     judge::JudgeStatus,
+    lint::{self, Diagnostic, DiagnosticKind, Severity},
 };
 use anyhow::{anyhow, bail, Context, Result};
 use macroquad::prelude::warn;
-use std::cell::RefCell;
+use std::{cell::RefCell, fmt::Write};
 
 trait Take {
     fn take_f32(&mut self) -> Result<f32>;
@@ -90,6 +91,18 @@ fn sanitize_events(events: &mut [PECEvent], id: usize, desc: &str) {
                 e.start_time, e.end_time,
                 e.end_time
             );
+            lint::report(
+                Diagnostic::new(
+                    Severity::Warning,
+                    DiagnosticKind::OverlappingEvents {
+                        desc: desc.to_owned(),
+                        start: last_start,
+                        end: last_end,
+                    },
+                )
+                .line(id)
+                .time(e.start_time),
+            );
             e.start_time = last_end;
         }
         last_start = e.start_time;
@@ -171,6 +184,7 @@ fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: f32) -> Result<J
         z_index: 0,
         show_below: false,
         attach_ui: None,
+        group: None,
 
         cache,
     })
@@ -262,6 +276,7 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
                         time,
                         height: 0.0,
                         speed: 1.0,
+                        visible_time: f32::INFINITY,
 
                         above,
                         multiple_hint: false,
@@ -373,3 +388,224 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
         extra,
     ))
 }
+
+/// An exported piece of animation: values go from `starts` to `ends` during `[start_time, end_time]` with the PEC easing `easing`.
+struct PECPiece {
+    start_time: f32,
+    end_time: f32,
+    starts: Vec<f32>,
+    ends: Vec<f32>,
+    easing: u8,
+}
+
+/// The PEC easing of `anim` on `[start, end]`, or [None] if `[start, end]` is not exactly one of its segments.
+fn segment_easing(anim: &AnimFloat, start: f32, end: f32) -> Option<u8> {
+    if anim.next.is_some() {
+        return None;
+    }
+    let kfs = &anim.keyframes;
+    let i = kfs.partition_point(|kf| kf.time <= start);
+    if i == 0 || i >= kfs.len() || kfs[i - 1].time != start || kfs[i].time != end {
+        return None;
+    }
+    kfs[i - 1].tween.as_any().downcast_ref::<StaticTween>().and_then(|it| easing_of(it.0))
+}
+
+/// Splits `anims` into pieces sharing one easing. Segments whose easing cannot be expressed (or is not linear if `eased` is false) are
+/// approximated by linear pieces.
+fn export_pieces(anims: &[(&AnimFloat, f32)], max_time: f32, eased: bool) -> Vec<PECPiece> {
+    let values = |time: f32, left: bool| {
+        anims
+            .iter()
+            .map(|(anim, default)| anim_value(anim, time, left, *default))
+            .collect::<Vec<_>>()
+    };
+    let times = breakpoints(&anims.iter().map(|it| it.0).collect::<Vec<_>>(), 0.0..max_time, false);
+    let mut pieces = Vec::new();
+    for w in times.windows(2) {
+        let (start, end) = (w[0], w[1]);
+        let (starts, ends) = (values(start, false), values(end, true));
+        let middle = values((start + end) / 2., false);
+        let mut easing = Some(None);
+        for (i, (anim, _)) in anims.iter().enumerate() {
+            if starts[i] == ends[i] && middle[i] == starts[i] {
+                continue;
+            }
+            let this = segment_easing(anim, start, end).filter(|it| eased || *it == 1).or_else(|| {
+                let kfs = &anim.keyframes;
+                let j = kfs.partition_point(|kf| kf.time <= start);
+                (anim.next.is_none() && j != 0 && j < kfs.len() && is_linear(kfs[j - 1].tween.as_ref())).then_some(1)
+            });
+            easing = match (easing, this) {
+                (Some(None), Some(e)) => Some(Some(e)),
+                (Some(Some(a)), Some(b)) if a == b => Some(Some(a)),
+                _ => None,
+            };
+        }
+        if let Some(easing) = easing {
+            pieces.push(PECPiece {
+                start_time: start,
+                end_time: end,
+                starts,
+                ends,
+                easing: easing.unwrap_or(1),
+            });
+        } else {
+            let times = (0..=LINEARIZE_STEPS)
+                .map(|step| start + (end - start) * step as f32 / LINEARIZE_STEPS as f32)
+                .collect::<Vec<_>>();
+            for w in times.windows(2) {
+                pieces.push(PECPiece {
+                    start_time: w[0],
+                    end_time: w[1],
+                    starts: values(w[0], false),
+                    ends: values(w[1], true),
+                    easing: 1,
+                });
+            }
+        }
+    }
+    pieces
+}
+
+/// Writes `pieces` as a set event (`single`) whenever the value jumps, followed by an interpolating event (`interpolate`) if it changes.
+fn write_pieces(
+    out: &mut String,
+    r: &mut BpmList,
+    pieces: Vec<PECPiece>,
+    single: impl Fn(f32, &[f32]) -> String,
+    interpolate: impl Fn(f32, f32, &[f32], u8) -> String,
+) {
+    let mut current: Option<Vec<f32>> = None;
+    for piece in pieces {
+        let (start, end) = (r.beat(piece.start_time), r.beat(piece.end_time));
+        if current.as_ref() != Some(&piece.starts) {
+            writeln!(out, "{}", single(start, &piece.starts)).unwrap();
+        }
+        if piece.ends != piece.starts {
+            writeln!(out, "{}", interpolate(start, end, &piece.ends, piece.easing)).unwrap();
+        }
+        current = Some(piece.ends);
+    }
+}
+
+pub fn export_pec(chart: &Chart, bpm_list: &BpmList) -> String {
+    let mut r = bpm_list.clone();
+    let max_time = max_time(chart);
+    let mut out = String::new();
+    writeln!(out, "{}", ((chart.offset + 0.15) * 1000.).round() as i32).unwrap();
+    for (beats, bpm) in r.ranges() {
+        writeln!(out, "bp {beats} {bpm}").unwrap();
+    }
+    for (id, line) in chart.lines.iter().enumerate() {
+        let mut notes = line.notes.iter().collect::<Vec<_>>();
+        notes.sort_by_key(|it| it.time.not_nan());
+        for note in notes {
+            let time = r.beat(note.time);
+            let x = anim_value(&note.object.translation.0, note.time, false, 0.) * 1024.;
+            let (above, fake) = (if note.above { 1 } else { 2 }, note.fake as u8);
+            let command = match note.kind {
+                NoteKind::Click => format!("n1 {id} {time} {x} {above} {fake}"),
+                NoteKind::Hold { end_time, .. } => format!("n2 {id} {time} {} {x} {above} {fake}", r.beat(end_time)),
+                NoteKind::Flick => format!("n3 {id} {time} {x} {above} {fake}"),
+                NoteKind::Drag => format!("n4 {id} {time} {x} {above} {fake}"),
+            };
+            writeln!(out, "{command}").unwrap();
+            writeln!(out, "# {}", note.speed).unwrap();
+            writeln!(out, "& {}", anim_value(&note.object.scale.0, note.time, false, 1.)).unwrap();
+        }
+
+        let mut last_speed = None;
+        for w in breakpoints(&[&line.height], 0.0..max_time, true).windows(2) {
+            let speed = (anim_value(&line.height, w[1], true, 0.) - anim_value(&line.height, w[0], false, 0.)) / (w[1] - w[0]) * 5.85;
+            if last_speed != Some(speed) {
+                writeln!(out, "cv {id} {} {speed}", r.beat(w[0])).unwrap();
+                last_speed = Some(speed);
+            }
+        }
+
+        let object = &line.object;
+        let to_x = |x: f32| (x + 1.) / 2. * 2048.;
+        let to_y = |y: f32| (y + 1.) / 2. * 1400.;
+        write_pieces(
+            &mut out,
+            &mut r,
+            export_pieces(&[(&object.translation.0, 0.), (&object.translation.1, 0.)], max_time, true),
+            |time, v| format!("cp {id} {time} {} {}", to_x(v[0]), to_y(v[1])),
+            |start, end, v, easing| format!("cm {id} {start} {end} {} {} {easing}", to_x(v[0]), to_y(v[1])),
+        );
+        write_pieces(
+            &mut out,
+            &mut r,
+            export_pieces(&[(&object.rotation, 0.)], max_time, true),
+            |time, v| format!("cd {id} {time} {}", -v[0]),
+            |start, end, v, easing| format!("cr {id} {start} {end} {} {easing}", -v[0]),
+        );
+        let to_alpha = |alpha: f32| if alpha >= 0. { alpha * 255. } else { alpha };
+        write_pieces(
+            &mut out,
+            &mut r,
+            export_pieces(&[(&object.alpha, 1.)], max_time, false),
+            |time, v| format!("ca {id} {time} {}", to_alpha(v[0])),
+            |start, end, v, _| format!("cf {id} {start} {end} {}", to_alpha(v[0])),
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture_str;
+
+    fn assert_close(a: f32, b: f32, tolerance: f32, what: &str) {
+        assert!((a - b).abs() <= tolerance * a.abs().max(1.), "{what}: {a} != {b}");
+    }
+
+    fn position(note: &Note) -> f32 {
+        anim_value(&note.object.translation.0, note.time, false, 0.)
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let chart = parse_pec(&fixture_str("pec_round_trip.pec"), ChartExtra::default()).unwrap();
+        let bpm_list = chart.bpm_list.borrow().clone();
+        let exported = parse_pec(&export_pec(&chart, &bpm_list), ChartExtra::default()).unwrap();
+
+        assert_close(exported.offset, chart.offset, 1e-6, "offset");
+        assert_eq!(exported.bpm_list.borrow().ranges(), bpm_list.ranges());
+        assert_eq!(exported.lines.len(), chart.lines.len());
+        for (a, b) in chart.lines.iter().zip(exported.lines.iter()) {
+            assert_eq!(a.notes.len(), b.notes.len());
+            // notes are written out sorted by time
+            let sorted = |line: &JudgeLine| {
+                let mut notes = line.notes.iter().collect::<Vec<_>>();
+                notes.sort_by(|x, y| (x.time, position(x)).partial_cmp(&(y.time, position(y))).unwrap());
+                notes
+            };
+            for (x, y) in sorted(a).into_iter().zip(sorted(b)) {
+                assert_close(x.time, y.time, 1e-4, "note time");
+                match (&x.kind, &y.kind) {
+                    (NoteKind::Hold { end_time: e1, .. }, NoteKind::Hold { end_time: e2, .. }) => assert_close(*e1, *e2, 1e-4, "hold end"),
+                    (k1, k2) => assert_eq!(std::mem::discriminant(k1), std::mem::discriminant(k2)),
+                }
+                assert_eq!((x.above, x.fake), (y.above, y.fake));
+                assert_close(x.speed, y.speed, 1e-4, "note speed");
+                assert_close(position(x), position(y), 1e-4, "note position");
+            }
+            for step in 0..=300 {
+                let t = step as f32 * 0.02;
+                for (what, x, y, default, tolerance) in [
+                    ("alpha", &a.object.alpha, &b.object.alpha, 1., 1e-3),
+                    ("move X", &a.object.translation.0, &b.object.translation.0, 0., 1e-3),
+                    ("move Y", &a.object.translation.1, &b.object.translation.1, 0., 1e-3),
+                    ("rotation", &a.object.rotation, &b.object.rotation, 0., 1e-3),
+                    // speeds are exported as slopes measured numerically
+                    ("height", &a.height, &b.height, 0., 1e-2),
+                ] {
+                    assert_close(anim_value(x, t, false, default), anim_value(y, t, false, default), tolerance, &format!("{what} at {t}"));
+                }
+            }
+        }
+    }
+}
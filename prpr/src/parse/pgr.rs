@@ -1,4 +1,4 @@
-use super::process_lines;
+use super::{anim_value, breakpoints, max_time, process_lines};
 use crate::{
     core::{
         Anim, AnimFloat, AnimVector, BpmList, Chart, ChartExtra, ChartSettings, JudgeLine, JudgeLineCache, JudgeLineKind, Keyframe, Note, NoteKind,
@@ -6,33 +6,38 @@ use crate::{
     },
     ext::NotNanExt,
     judge::JudgeStatus,
+    lint::{self, Diagnostic, DiagnosticKind, Severity},
 };
 use anyhow::{bail, Context, Result};
 use macroquad::prelude::warn;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct PgrEvent {
     pub start_time: f32,
     pub end_time: f32,
     pub start: f32,
     pub end: f32,
+    // format version 1 packs both coordinates of move events into `start` and `end` instead
+    #[serde(default)]
     pub start2: f32,
+    #[serde(default)]
     pub end2: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct PgrSpeedEvent {
     pub start_time: f32,
     pub end_time: f32,
     pub value: f32,
-    pub floor_position: f32,
+    // missing in format version 1, in which case it is accumulated from the speeds
+    pub floor_position: Option<f32>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PgrNote {
     #[serde(rename = "type")]
@@ -44,7 +49,7 @@ pub struct PgrNote {
     floor_position: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct PgrJudgeLine {
     bpm: f32,
@@ -60,15 +65,17 @@ struct PgrJudgeLine {
     notes_below: Vec<PgrNote>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct PgrChart {
+    #[serde(default)]
+    format_version: u32,
     offset: f32,
     judge_line_list: Vec<PgrJudgeLine>,
 }
 
 macro_rules! validate_events {
-    ($pgr:expr) => {
+    ($pgr:expr, $r:expr, $desc:expr) => {
         $pgr.retain(|it| {
             if it.start_time > it.end_time {
                 warn!("Invalid time range, ignoring");
@@ -78,8 +85,25 @@ macro_rules! validate_events {
             }
         });
         for i in 0..($pgr.len() - 1) {
-            if $pgr[i].end_time != $pgr[i + 1].start_time {
-                bail!("Events should be contiguous");
+            let (end, next) = ($pgr[i].end_time, $pgr[i + 1].start_time);
+            if end != next {
+                // overlaps are clipped while linting, so that the rest of the chart still gets checked
+                if end > next && lint::active() {
+                    lint::report(
+                        Diagnostic::new(
+                            Severity::Warning,
+                            DiagnosticKind::OverlappingEvents {
+                                desc: $desc.to_owned(),
+                                start: $pgr[i].start_time * $r,
+                                end: end * $r,
+                            },
+                        )
+                        .time(next * $r),
+                    );
+                    $pgr[i + 1].start_time = end;
+                } else {
+                    bail!("Events should be contiguous");
+                }
             }
         }
         if $pgr.last().unwrap().end_time <= 900000000.0 {
@@ -89,20 +113,25 @@ macro_rules! validate_events {
 }
 
 fn parse_speed_events(r: f32, mut pgr: Vec<PgrSpeedEvent>, max_time: f32) -> Result<(AnimFloat, AnimFloat)> {
-    validate_events!(pgr);
+    validate_events!(pgr, r, "speed");
     assert_eq!(pgr[0].start_time, 0.0);
+    let mut floor_position = 0.;
+    for e in &mut pgr {
+        floor_position = *e.floor_position.get_or_insert(floor_position);
+        floor_position += (e.end_time - e.start_time) * r * e.value;
+    }
     let mut kfs = Vec::new();
-    kfs.extend(pgr.iter().map(|it| Keyframe::new(it.start_time * r, it.floor_position, 2)));
+    kfs.extend(pgr.iter().map(|it| Keyframe::new(it.start_time * r, it.floor_position.unwrap(), 2)));
     let last = pgr.last().unwrap();
-    kfs.push(Keyframe::new(max_time, last.floor_position + (max_time - last.start_time * r) * last.value, 0));
+    kfs.push(Keyframe::new(max_time, last.floor_position.unwrap() + (max_time - last.start_time * r) * last.value, 0));
     for kf in &mut kfs {
         kf.value /= HEIGHT_RATIO;
     }
     Ok((AnimFloat::new(pgr.iter().map(|it| Keyframe::new(it.start_time * r, it.value, 0)).collect()), AnimFloat::new(kfs)))
 }
 
-fn parse_float_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimFloat> {
-    validate_events!(pgr);
+fn parse_float_events(r: f32, mut pgr: Vec<PgrEvent>, desc: &str) -> Result<AnimFloat> {
+    validate_events!(pgr, r, desc);
     let mut kfs = Vec::<Keyframe<f32>>::new();
     for e in pgr {
         if !kfs.last().map_or(false, |it| it.value == e.start) {
@@ -114,8 +143,16 @@ fn parse_float_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimFloat> {
     Ok(AnimFloat::new(kfs))
 }
 
-fn parse_move_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
-    validate_events!(pgr);
+fn parse_move_events(r: f32, mut pgr: Vec<PgrEvent>, format_version: u32) -> Result<AnimVector> {
+    validate_events!(pgr, r, "move");
+    if format_version == 1 {
+        // x * 1000 + y, with x in 0..880 and y in 0..520
+        let unpack = |v: f32| ((v / 1000.).floor() / 880., (v % 1000.) / 520.);
+        for e in &mut pgr {
+            (e.start, e.start2) = unpack(e.start);
+            (e.end, e.end2) = unpack(e.end);
+        }
+    }
     let mut kf1 = Vec::<Keyframe<f32>>::new();
     let mut kf2 = Vec::<Keyframe<f32>>::new();
     for e in pgr {
@@ -175,6 +212,7 @@ fn parse_notes(r: f32, mut pgr: Vec<PgrNote>, speed: &mut AnimFloat, height: &mu
                     pgr.speed
                 },
                 height: pgr.floor_position / HEIGHT_RATIO,
+                visible_time: f32::INFINITY,
 
                 above,
                 multiple_hint: false,
@@ -185,7 +223,7 @@ fn parse_notes(r: f32, mut pgr: Vec<PgrNote>, speed: &mut AnimFloat, height: &mu
         .collect()
 }
 
-fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
+fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32, format_version: u32) -> Result<JudgeLine> {
     let r = 60. / pgr.bpm / 32.;
     let (mut speed, mut height) = parse_speed_events(r, pgr.speed_events, max_time).context("Failed to parse speed events")?;
     let notes_above = parse_notes(r, pgr.notes_above, &mut speed, &mut height, true).context("Failed to parse notes above")?;
@@ -195,9 +233,9 @@ fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
     let cache = JudgeLineCache::new(&mut notes);
     Ok(JudgeLine {
         object: Object {
-            alpha: parse_float_events(r, pgr.alpha_events).context("Failed to parse alpha events")?,
-            rotation: parse_float_events(r, pgr.rotate_events).context("Failed to parse rotate events")?,
-            translation: parse_move_events(r, pgr.move_events).context("Failed to parse move events")?,
+            alpha: parse_float_events(r, pgr.alpha_events, "alpha").context("Failed to parse alpha events")?,
+            rotation: parse_float_events(r, pgr.rotate_events, "rotate").context("Failed to parse rotate events")?,
+            translation: parse_move_events(r, pgr.move_events, format_version).context("Failed to parse move events")?,
             ..Default::default()
         },
         ctrl_obj: RefCell::default(),
@@ -210,6 +248,7 @@ fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
         z_index: 0,
         show_below: true,
         attach_ui: None,
+        group: None,
 
         cache,
     })
@@ -217,6 +256,16 @@ fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
 
 pub fn parse_phigros(source: &str, extra: ChartExtra) -> Result<Chart> {
     let pgr: PgrChart = serde_json::from_str(source).context("Failed to parse JSON")?;
+    // all versions measure time in 1/32 beats at the BPM of each line, so they share the time base and differ only in how move events and
+    // floor positions are stored. Version 2 stores them as version 3 does
+    let format_version = match pgr.format_version {
+        0 => {
+            warn!("Format version is missing, assuming 3");
+            3
+        }
+        version @ 1..=3 => version,
+        version => bail!("Unsupported format version: {version} (expected 1, 2 or 3)"),
+    };
     let max_time = *pgr
         .judge_line_list
         .iter()
@@ -232,12 +281,212 @@ pub fn parse_phigros(source: &str, extra: ChartExtra) -> Result<Chart> {
         .max()
         .unwrap_or_default()
         + 1.;
-    let mut lines = pgr
+    // lines keep their own BPM, the chart-wide list only maps times to beats
+    let bpm_list = BpmList::new(vec![(0., pgr.judge_line_list.first().map_or(120., |it| it.bpm))]);
+    let lines = pgr
         .judge_line_list
         .into_iter()
         .enumerate()
-        .map(|(id, pgr)| parse_judge_line(pgr, max_time).with_context(|| format!("In judge line #{id}")))
-        .collect::<Result<Vec<_>>>()?;
+        .map(|(id, pgr)| {
+            lint::set_line(Some(id));
+            parse_judge_line(pgr, max_time, format_version).with_context(|| format!("In judge line #{id}"))
+        })
+        .collect::<Result<Vec<_>>>();
+    lint::set_line(None);
+    let mut lines = lines?;
     process_lines(&mut lines);
-    Ok(Chart::new(pgr.offset, lines, BpmList::default(), ChartSettings::default(), extra))
+    Ok(Chart::new(pgr.offset, lines, bpm_list, ChartSettings::default(), extra))
+}
+
+const PGR_END_TIME: f32 = 1000000000.;
+
+// every kind of event needs at least one span, even when nothing happens after time 0
+fn event_times(anims: &[&AnimFloat], max_time: f32) -> Vec<f32> {
+    let mut times = breakpoints(anims, 0.0..max_time, true);
+    match times.len() {
+        0 => vec![0., 1.],
+        1 => {
+            times.push(times[0] + 1.);
+            times
+        }
+        _ => times,
+    }
+}
+
+fn export_events(r: f32, times: &[f32], value: impl Fn(f32, bool) -> (f32, f32)) -> Vec<PgrEvent> {
+    let last = times.len() - 2;
+    times
+        .windows(2)
+        .enumerate()
+        .map(|(i, w)| {
+            let (start, start2) = value(w[0], false);
+            let (end, end2) = value(w[1], true);
+            PgrEvent {
+                start_time: w[0] / r,
+                end_time: if i == last { PGR_END_TIME } else { w[1] / r },
+                start,
+                end,
+                start2,
+                end2,
+            }
+        })
+        .collect()
+}
+
+fn export_judge_line(line: &JudgeLine, bpm: f32, max_time: f32) -> PgrJudgeLine {
+    let r = 60. / bpm / 32.;
+    let object = &line.object;
+    let single = |anim: &AnimFloat, default: f32| {
+        let times = event_times(&[anim], max_time);
+        export_events(r, &times, |time, left| (anim_value(anim, time, left, default), 0.))
+    };
+    let move_times = event_times(&[&object.translation.0, &object.translation.1], max_time);
+    let move_events = export_events(r, &move_times, |time, left| {
+        ((anim_value(&object.translation.0, time, left, 0.) + 1.) / 2., (anim_value(&object.translation.1, time, left, 0.) + 1.) / 2.)
+    });
+    let height_times = event_times(&[&line.height], max_time);
+    let last = height_times.len() - 2;
+    let speed_events = height_times
+        .windows(2)
+        .enumerate()
+        .map(|(i, w)| {
+            let start = anim_value(&line.height, w[0], false, 0.);
+            let end = anim_value(&line.height, w[1], true, 0.);
+            PgrSpeedEvent {
+                start_time: w[0] / r,
+                end_time: if i == last { PGR_END_TIME } else { w[1] / r },
+                value: (end - start) / (w[1] - w[0]) * HEIGHT_RATIO,
+                floor_position: Some(start * HEIGHT_RATIO),
+            }
+        })
+        .collect::<Vec<_>>();
+    let speed_at = |time: f32| speed_events.iter().rev().find(|it| it.start_time * r <= time).map_or(1., |it| it.value);
+    let (mut notes_above, mut notes_below) = (Vec::new(), Vec::new());
+    for note in line.notes.iter().filter(|it| !it.fake) {
+        let (kind, hold_time) = match note.kind {
+            NoteKind::Click => (1, 0.),
+            NoteKind::Drag => (2, 0.),
+            NoteKind::Hold { end_time, .. } => (3, (end_time - note.time) / r),
+            NoteKind::Flick => (4, 0.),
+        };
+        let pgr = PgrNote {
+            kind,
+            time: note.time / r,
+            position_x: anim_value(&note.object.translation.0, note.time, false, 0.) / (2. * 9. / 160.),
+            hold_time,
+            speed: if kind == 3 { note.speed * speed_at(note.time) } else { note.speed },
+            floor_position: note.height * HEIGHT_RATIO,
+        };
+        if note.above {
+            notes_above.push(pgr);
+        } else {
+            notes_below.push(pgr);
+        }
+    }
+    PgrJudgeLine {
+        bpm,
+        alpha_events: single(&object.alpha, 1.),
+        rotate_events: single(&object.rotation, 0.),
+        move_events,
+        speed_events,
+        notes_above,
+        notes_below,
+    }
+}
+
+pub fn export_phigros(chart: &Chart, bpm_list: &BpmList) -> String {
+    // events are stored in absolute time, so the BPM only determines the time unit
+    let bpm = bpm_list.ranges().first().map_or(120., |it| it.1);
+    let max_time = max_time(chart);
+    let pgr = PgrChart {
+        format_version: 3,
+        offset: chart.offset,
+        judge_line_list: chart.lines.iter().map(|line| export_judge_line(line, bpm, max_time)).collect(),
+    };
+    serde_json::to_string(&pgr).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture_str;
+
+    fn parse(name: &str) -> Chart {
+        parse_phigros(&fixture_str(name), ChartExtra::default()).unwrap()
+    }
+
+    fn values(anim: &AnimFloat) -> Vec<f32> {
+        (0..=40).map(|it| anim_value(anim, it as f32 * 0.1, false, 0.)).collect()
+    }
+
+    fn assert_close(a: f32, b: f32, tolerance: f32, what: &str) {
+        assert!((a - b).abs() <= tolerance * a.abs().max(1.), "{what}: {a} != {b}");
+    }
+
+    #[test]
+    fn format_versions() {
+        let v3 = parse("pgr_v3.json");
+        let line = &v3.lines[0];
+        // 64 units of 1/32 beat at 120 BPM make a second
+        assert_eq!(anim_value(&line.object.translation.0, 1., false, 0.), 0.5);
+        assert_eq!(anim_value(&line.object.translation.1, 1., false, 0.), -0.5);
+        assert_eq!(anim_value(&line.height, 2., false, 0.), 2. / HEIGHT_RATIO);
+
+        for name in ["pgr_v1.json", "pgr_v2.json"] {
+            let chart = parse(name);
+            let (a, b) = (&chart.lines[0], &v3.lines[0]);
+            assert_eq!(values(&a.object.translation.0), values(&b.object.translation.0), "{name}: move X");
+            assert_eq!(values(&a.object.translation.1), values(&b.object.translation.1), "{name}: move Y");
+            assert_eq!(values(&a.height), values(&b.height), "{name}: floor position");
+            let notes = |line: &JudgeLine| line.notes.iter().map(|it| (it.time, it.height, it.speed)).collect::<Vec<_>>();
+            assert_eq!(notes(a), notes(b), "{name}: notes");
+        }
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let chart = parse("pgr_round_trip.json");
+        let bpm_list = chart.bpm_list.borrow().clone();
+        let exported = parse_phigros(&export_phigros(&chart, &bpm_list), ChartExtra::default()).unwrap();
+
+        assert_eq!(exported.offset, chart.offset);
+        assert_eq!(exported.lines.len(), chart.lines.len());
+        let position = |note: &Note| anim_value(&note.object.translation.0, note.time, false, 0.);
+        for (a, b) in chart.lines.iter().zip(exported.lines.iter()) {
+            assert_eq!(a.notes.len(), b.notes.len());
+            // notes above and below the line are stored apart, so compare them in time order
+            let sorted = |line: &JudgeLine| {
+                let mut notes = line.notes.iter().collect::<Vec<_>>();
+                notes.sort_by(|x, y| (x.time, position(x)).partial_cmp(&(y.time, position(y))).unwrap());
+                notes
+            };
+            for (x, y) in sorted(a).into_iter().zip(sorted(b)) {
+                assert_close(x.time, y.time, 1e-4, "note time");
+                match (&x.kind, &y.kind) {
+                    (NoteKind::Hold { end_time: e1, .. }, NoteKind::Hold { end_time: e2, .. }) => assert_close(*e1, *e2, 1e-4, "hold end"),
+                    (k1, k2) => assert_eq!(std::mem::discriminant(k1), std::mem::discriminant(k2)),
+                }
+                assert_eq!(x.above, y.above);
+                assert_close(position(x), position(y), 1e-4, "note position");
+            }
+            for (what, x, y) in [
+                ("alpha", &a.object.alpha, &b.object.alpha),
+                ("move X", &a.object.translation.0, &b.object.translation.0),
+                ("move Y", &a.object.translation.1, &b.object.translation.1),
+                ("rotation", &a.object.rotation, &b.object.rotation),
+                ("floor position", &a.height, &b.height),
+            ] {
+                for (i, (x, y)) in values(x).into_iter().zip(values(y)).enumerate() {
+                    assert_close(x, y, 1e-3, &format!("{what} at {}", i as f32 * 0.1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_format_version() {
+        let source = fixture_str("pgr_v3.json").replace("\"formatVersion\": 3", "\"formatVersion\": 4");
+        let err = parse_phigros(&source, ChartExtra::default()).err().unwrap();
+        assert_eq!(err.to_string(), "Unsupported format version: 4 (expected 1, 2 or 3)");
+    }
 }
@@ -1,23 +1,24 @@
-use super::{process_lines, TWEEN_MAP};
+use super::{easing_of, process_lines, TWEEN_MAP};
 use crate::{
     core::{
         Anim, AnimFloat, AnimVector, BezierTween, BpmList, Chart, ChartSettings, ClampedTween, CtrlObject, JudgeLine, JudgeLineCache, JudgeLineKind,
-        Keyframe, Note, NoteKind, Object, StaticTween, Triple, TweenFunction, Tweenable, UIElement, EPS, HEIGHT_RATIO, JUDGE_LINE_PERFECT_COLOR, ChartExtra,
+        Keyframe, Note, NoteKind, Object, StaticTween, Triple, TweenFunction, TweenId, Tweenable, UIElement, EPS, HEIGHT_RATIO, JUDGE_LINE_PERFECT_COLOR, ChartExtra,
     },
     ext::NotNanExt,
     fs::FileSystem,
     judge::JudgeStatus,
+    lint::{self, Diagnostic, DiagnosticKind, Severity},
 };
 use anyhow::{bail, Context, Result};
-use macroquad::prelude::Color;
-use serde::Deserialize;
+use macroquad::prelude::{warn, Color};
+use serde::{Deserialize, Serialize};
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 pub const RPE_WIDTH: f32 = 1350.;
 pub const RPE_HEIGHT: f32 = 900.;
 const SPEED_RATIO: f32 = 10. / 45. / HEIGHT_RATIO;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEBpmItem {
     bpm: f32,
@@ -33,10 +34,13 @@ fn f32_one() -> f32 {
     1.
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEEvent<T = f32> {
-    // TODO linkgroup
+    // events in the same link group are edited together in RPE. It has no effect on how they play, and since events become keyframes it can't
+    // be exported back either, so it is only reported
+    #[serde(default)]
+    linkgroup: i32,
     #[serde(default = "f32_zero")]
     easing_left: f32,
     #[serde(default = "f32_one")]
@@ -52,7 +56,7 @@ struct RPEEvent<T = f32> {
     end_time: Triple,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPECtrlEvent {
     easing: u8,
@@ -61,17 +65,18 @@ struct RPECtrlEvent {
     value: HashMap<String, f32>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPESpeedEvent {
-    // TODO linkgroup
+    #[serde(default)]
+    linkgroup: i32,
     start_time: Triple,
     end_time: Triple,
     start: f32,
     end: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEEventLayer {
     alpha_events: Option<Vec<RPEEvent>>,
@@ -81,7 +86,7 @@ struct RPEEventLayer {
     speed_events: Option<Vec<RPESpeedEvent>>,
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct RGBColor(u8, u8, u8);
 impl From<RGBColor> for Color {
     fn from(RGBColor(r, g, b): RGBColor) -> Self {
@@ -89,7 +94,14 @@ impl From<RGBColor> for Color {
     }
 }
 
-#[derive(Deserialize)]
+impl From<Color> for RGBColor {
+    fn from(color: Color) -> Self {
+        let int = |v: f32| (v * 255.).round() as u8;
+        Self(int(color.r), int(color.g), int(color.b))
+    }
+}
+
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEExtendedEvents {
     color_events: Option<Vec<RPEEvent<RGBColor>>>,
@@ -100,12 +112,12 @@ struct RPEExtendedEvents {
     paint_events: Option<Vec<RPEEvent>>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPENote {
-    // TODO above == 0? what does that even mean?
     #[serde(rename = "type")]
     kind: u8,
+    // 1 is above the line, anything else (2, or 0 in some charts) below it
     above: u8,
     start_time: Triple,
     end_time: Triple,
@@ -118,11 +130,15 @@ struct RPENote {
     visible_time: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEJudgeLine {
-    // TODO group
-    // TODO alphaControl, bpmfactor
+    // index into `judgeLineGroup` of the chart
+    #[serde(rename = "Group", default)]
+    group: usize,
+    // the line runs at the BPM divided by this
+    #[serde(default = "f32_one")]
+    bpmfactor: f32,
     #[serde(rename = "Name")]
     name: String,
     #[serde(rename = "Texture")]
@@ -148,19 +164,21 @@ struct RPEJudgeLine {
     y_control: Vec<RPECtrlEvent>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEMetadata {
     offset: i32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEChart {
     #[serde(rename = "META")]
     meta: RPEMetadata,
     #[serde(rename = "BPMList")]
     bpm_list: Vec<RPEBpmItem>,
+    #[serde(default)]
+    judge_line_group: Vec<String>,
     judge_line_list: Vec<RPEJudgeLine>,
 }
 
@@ -172,11 +190,19 @@ fn bezier_key<T>(event: &RPEEvent<T>) -> (u16, i16, i16) {
     ((int(p[0]) * 100 + int(p[1])) as u16, int(p[2]), int(p[3]))
 }
 
+fn tween_of(easing_type: i32) -> TweenId {
+    TWEEN_MAP.get(easing_type.max(1) as usize).copied().unwrap_or_else(|| {
+        lint::report(Diagnostic::new(Severity::Warning, DiagnosticKind::UnknownTween { id: easing_type }));
+        TWEEN_MAP[0]
+    })
+}
+
 fn parse_events<T: Tweenable, V: Clone + Into<T>>(
     r: &mut BpmList,
     rpe: &[RPEEvent<V>],
     default: Option<T>,
     bezier_map: &BezierMap,
+    desc: &str,
 ) -> Result<Anim<T>> {
     let mut kfs = Vec::new();
     if let Some(default) = default {
@@ -184,12 +210,30 @@ fn parse_events<T: Tweenable, V: Clone + Into<T>>(
             kfs.push(Keyframe::new(0.0, default, 0));
         }
     }
+    let mut last = None;
     for e in rpe {
+        let start_time = r.time(&e.start_time);
+        if let Some((start, end)) = last {
+            if start_time < end {
+                lint::report(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        DiagnosticKind::OverlappingEvents {
+                            desc: desc.to_owned(),
+                            start,
+                            end,
+                        },
+                    )
+                    .time(start_time),
+                );
+            }
+        }
+        last = Some((start_time, r.time(&e.end_time)));
         kfs.push(Keyframe {
-            time: r.time(&e.start_time),
+            time: start_time,
             value: e.start.clone().into(),
             tween: {
-                let tween = TWEEN_MAP.get(e.easing_type.max(1) as usize).copied().unwrap_or(TWEEN_MAP[0]);
+                let tween = tween_of(e.easing_type);
                 if e.bezier != 0 {
                     Rc::clone(&bezier_map[&bezier_key(e)])
                 } else if e.easing_left.abs() < EPS && (e.easing_right - 1.0).abs() < EPS {
@@ -281,15 +325,10 @@ fn parse_notes(r: &mut BpmList, rpe: Vec<RPENote>, height: &mut AnimFloat) -> Re
             let y_offset = note.y_offset * 2. / RPE_HEIGHT * note.speed;
             Ok(Note {
                 object: Object {
-                    alpha: if note.visible_time >= time {
-                        if note.alpha >= 255 {
-                            AnimFloat::default()
-                        } else {
-                            AnimFloat::fixed(note.alpha as f32 / 255.)
-                        }
+                    alpha: if note.alpha >= 255 {
+                        AnimFloat::default()
                     } else {
-                        let alpha = note.alpha.min(255) as f32 / 255.;
-                        AnimFloat::new(vec![Keyframe::new(0.0, 0.0, 0), Keyframe::new(time - note.visible_time, alpha, 0)])
+                        AnimFloat::fixed(note.alpha as f32 / 255.)
                     },
                     translation: AnimVector(AnimFloat::fixed(note.position_x / (RPE_WIDTH / 2.)), AnimFloat::fixed(y_offset)),
                     scale: AnimVector(
@@ -319,6 +358,8 @@ fn parse_notes(r: &mut BpmList, rpe: Vec<RPENote>, height: &mut AnimFloat) -> Re
                 time,
                 height: note_height,
                 speed: note.speed,
+                // RPE writes 999999 for notes that are always visible
+                visible_time: if note.visible_time >= time { f32::INFINITY } else { note.visible_time },
 
                 above: note.above == 1,
                 multiple_hint: false,
@@ -337,12 +378,19 @@ fn parse_ctrl_events(rpe: &[RPECtrlEvent], key: &str) -> AnimFloat {
     AnimFloat::new(
         rpe.iter()
             .zip(vals.into_iter())
-            .map(|(it, val)| Keyframe::new(it.x, val, TWEEN_MAP.get(it.easing.max(1) as usize).copied().unwrap_or(TWEEN_MAP[0])))
+            .map(|(it, val)| Keyframe::new(it.x, val, tween_of(it.easing as i32)))
             .collect(),
     )
 }
 
-async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs: &mut dyn FileSystem, bezier_map: &BezierMap) -> Result<JudgeLine> {
+async fn parse_judge_line(
+    r: &mut BpmList,
+    rpe: RPEJudgeLine,
+    max_time: f32,
+    fs: &mut dyn FileSystem,
+    bezier_map: &BezierMap,
+    groups: &[String],
+) -> Result<JudgeLine> {
     let event_layers: Vec<_> = rpe.event_layers.into_iter().flatten().collect();
     fn events_with_factor(
         r: &mut BpmList,
@@ -354,7 +402,7 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
     ) -> Result<AnimFloat> {
         let anis: Vec<_> = event_layers
             .iter()
-            .filter_map(|it| get(it).as_ref().map(|es| parse_events(r, es, None, bezier_map)))
+            .filter_map(|it| get(it).as_ref().map(|es| parse_events(r, es, None, bezier_map, desc)))
             .collect::<Result<_>>()
             .with_context(|| format!("Failed to parse {desc} events"))?;
         let mut res = AnimFloat::chain(anis);
@@ -376,7 +424,7 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
                 fn parse(r: &mut BpmList, opt: &Option<Vec<RPEEvent>>, factor: f32, bezier_map: &BezierMap) -> Result<AnimFloat> {
                     let mut res = opt
                         .as_ref()
-                        .map(|it| parse_events(r, it, None, bezier_map))
+                        .map(|it| parse_events(r, it, None, bezier_map, "scale"))
                         .transpose()?
                         .unwrap_or_default();
                     res.map_value(|v| v * factor);
@@ -424,31 +472,34 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
         }),
         height,
         incline: if let Some(events) = rpe.extended.as_ref().and_then(|e| e.incline_events.as_ref()) {
-            parse_events(r, events, Some(0.), bezier_map).context("Failed to parse incline events")?
+            parse_events(r, events, Some(0.), bezier_map, "incline").context("Failed to parse incline events")?
         } else {
             AnimFloat::default()
         },
         notes,
         kind: if rpe.texture == "line.png" {
             if let Some(events) = rpe.extended.as_ref().and_then(|e| e.paint_events.as_ref()) {
-                JudgeLineKind::Paint(parse_events(r, events, Some(-1.), bezier_map).context("Failed to parse paint events")?, RefCell::default())
+                JudgeLineKind::Paint(
+                    parse_events(r, events, Some(-1.), bezier_map, "paint").context("Failed to parse paint events")?,
+                    RefCell::default(),
+                )
             } else if let Some(events) = rpe.extended.as_ref().and_then(|e| e.text_events.as_ref()) {
-                JudgeLineKind::Text(parse_events(r, events, Some(String::new()), bezier_map).context("Failed to parse text events")?)
+                JudgeLineKind::Text(parse_events(r, events, Some(String::new()), bezier_map, "text").context("Failed to parse text events")?)
             } else {
                 JudgeLineKind::Normal
             }
         } else {
-            JudgeLineKind::Texture(
-                image::load_from_memory(
-                    &fs.load_file(&rpe.texture)
-                        .await
-                        .with_context(|| format!("加载插图 {} 失败", rpe.texture))?,
-                )?
-                .into(),
-            )
+            match fs.load_file(&rpe.texture).await.with_context(|| format!("加载插图 {} 失败", rpe.texture)) {
+                Ok(bytes) => JudgeLineKind::Texture(image::load_from_memory(&bytes)?.into()),
+                Err(_) if lint::active() => {
+                    lint::report(Diagnostic::new(Severity::Error, DiagnosticKind::MissingTexture { path: rpe.texture.clone() }));
+                    JudgeLineKind::Normal
+                }
+                Err(err) => return Err(err),
+            }
         },
         color: if let Some(events) = rpe.extended.as_ref().and_then(|e| e.color_events.as_ref()) {
-            parse_events(r, events, Some(JUDGE_LINE_PERFECT_COLOR), bezier_map).context("Failed to parse color events")?
+            parse_events(r, events, Some(JUDGE_LINE_PERFECT_COLOR), bezier_map, "color").context("Failed to parse color events")?
         } else {
             Anim::default()
         },
@@ -463,6 +514,7 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
         z_index: rpe.z_order,
         show_below: rpe.is_cover != 1,
         attach_ui: rpe.attach_ui,
+        group: groups.get(rpe.group).cloned(),
 
         cache,
     })
@@ -497,9 +549,21 @@ fn get_bezier_map(rpe: &RPEChart) -> BezierMap {
 }
 
 pub async fn parse_rpe(source: &str, fs: &mut dyn FileSystem, extra: ChartExtra) -> Result<Chart> {
-    let rpe: RPEChart = serde_json::from_str(source).context("Failed to parse JSON")?;
+    let mut rpe: RPEChart = serde_json::from_str(source).context("Failed to parse JSON")?;
     let bezier_map = get_bezier_map(&rpe);
-    let mut r = BpmList::new(rpe.bpm_list.into_iter().map(|it| (it.start_time.beats(), it.bpm)).collect());
+    let groups = std::mem::take(&mut rpe.judge_line_group);
+    let r = BpmList::new(rpe.bpm_list.into_iter().map(|it| (it.start_time.beats(), it.bpm)).collect());
+    let mut line_bpm_lists: Vec<_> = rpe
+        .judge_line_list
+        .iter()
+        .map(|line| {
+            if line.bpmfactor > 0. && line.bpmfactor != 1. {
+                r.with_factor(line.bpmfactor)
+            } else {
+                r.clone()
+            }
+        })
+        .collect();
     fn vec<T>(v: &Option<Vec<T>>) -> impl Iterator<Item = &T> {
         v.iter().flat_map(|it| it.iter())
     }
@@ -507,7 +571,8 @@ pub async fn parse_rpe(source: &str, fs: &mut dyn FileSystem, extra: ChartExtra)
     let max_time = *rpe
         .judge_line_list
         .iter()
-        .map(|line| {
+        .zip(line_bpm_lists.iter_mut())
+        .map(|(line, r)| {
             line.notes.as_ref().map(|notes| {
                 notes
                     .iter()
@@ -536,14 +601,421 @@ pub async fn parse_rpe(source: &str, fs: &mut dyn FileSystem, extra: ChartExtra)
         .max().unwrap_or_default() + 1.;
     // don't want to add a whole crate for a mere join_all...
     let mut lines = Vec::new();
-    for (id, rpe) in rpe.judge_line_list.into_iter().enumerate() {
+    for (id, (rpe, r)) in rpe.judge_line_list.into_iter().zip(line_bpm_lists.iter_mut()).enumerate() {
         let name = rpe.name.clone();
+        lint::set_line(Some(id));
+        if rpe.event_layers.iter().flatten().any(|layer| {
+            vec(&layer.alpha_events)
+                .chain(vec(&layer.move_x_events))
+                .chain(vec(&layer.move_y_events))
+                .chain(vec(&layer.rotate_events))
+                .any(|it| it.linkgroup != 0)
+                || vec(&layer.speed_events).any(|it| it.linkgroup != 0)
+        }) {
+            warn!("Event link groups of judge line #{id} are not supported and will be lost on export");
+            lint::report(Diagnostic::new(Severity::Warning, DiagnosticKind::IgnoredKey { key: "linkgroup".to_owned() }));
+        }
         lines.push(
-            parse_judge_line(&mut r, rpe, max_time, fs, &bezier_map)
+            parse_judge_line(r, rpe, max_time, fs, &bezier_map, &groups)
                 .await
                 .with_context(move || format!("In judge line #{id} ({})", name))?,
         );
     }
+    lint::set_line(None);
     process_lines(&mut lines);
     Ok(Chart::new(rpe.meta.offset as f32 / 1000.0, lines, r, ChartSettings::default(), extra))
 }
+
+#[derive(Clone, Copy)]
+struct RPEEasing {
+    easing_type: i32,
+    easing_left: f32,
+    easing_right: f32,
+    bezier_points: Option<[f32; 4]>,
+}
+
+impl RPEEasing {
+    const LINEAR: Self = Self::of(1);
+
+    const fn of(easing_type: i32) -> Self {
+        Self {
+            easing_type,
+            easing_left: 0.,
+            easing_right: 1.,
+            bezier_points: None,
+        }
+    }
+}
+
+enum ExportedTween {
+    Hold,
+    Jump,
+    Ease(RPEEasing),
+}
+
+fn export_tween(tween: &dyn TweenFunction) -> ExportedTween {
+    let easing_type = |tween: TweenId| {
+        easing_of(tween).map_or_else(
+            || {
+                warn!("Tween {tween} is not supported by RPE, falling back to linear");
+                1
+            },
+            |it| it as i32,
+        )
+    };
+    let any = tween.as_any();
+    if let Some(StaticTween(tween)) = any.downcast_ref::<StaticTween>() {
+        match *tween {
+            0 => ExportedTween::Hold,
+            1 => ExportedTween::Jump,
+            tween => ExportedTween::Ease(RPEEasing::of(easing_type(tween))),
+        }
+    } else if let Some(clamped) = any.downcast_ref::<ClampedTween>() {
+        let range = clamped.range();
+        ExportedTween::Ease(RPEEasing {
+            easing_left: range.start,
+            easing_right: range.end,
+            ..RPEEasing::of(easing_type(clamped.tween()))
+        })
+    } else if let Some(bezier) = any.downcast_ref::<BezierTween>() {
+        let ((x1, y1), (x2, y2)) = bezier.points();
+        ExportedTween::Ease(RPEEasing {
+            bezier_points: Some([x1, y1, x2, y2]),
+            ..RPEEasing::LINEAR
+        })
+    } else {
+        warn!("Unknown tween function, falling back to linear");
+        ExportedTween::Ease(RPEEasing::LINEAR)
+    }
+}
+
+fn export_event<V>(r: &mut BpmList, start_time: f32, end_time: f32, start: V, end: V, easing: RPEEasing) -> RPEEvent<V> {
+    RPEEvent {
+        linkgroup: 0,
+        easing_left: easing.easing_left,
+        easing_right: easing.easing_right,
+        bezier: easing.bezier_points.is_some() as u8,
+        bezier_points: easing.bezier_points.unwrap_or_default(),
+        easing_type: easing.easing_type,
+        start,
+        end,
+        start_time: Triple::from_beats(r.beat(start_time)),
+        end_time: Triple::from_beats(r.beat(end_time)),
+    }
+}
+
+fn export_events<T: Tweenable, V>(r: &mut BpmList, anim: &Anim<T>, f: &impl Fn(&T) -> V) -> Vec<RPEEvent<V>> {
+    // Keyframes joined by a hold tween are exactly the gaps between two RPE events, so they need no event of their own
+    let kfs = &anim.keyframes;
+    let mut events = Vec::new();
+    let mut covered = false;
+    for (i, kf) in kfs.iter().enumerate() {
+        match kfs
+            .get(i + 1)
+            .filter(|next| next.time > kf.time)
+            .map(|next| (next, export_tween(&*kf.tween)))
+        {
+            None | Some((_, ExportedTween::Hold)) => {
+                if !covered {
+                    events.push(export_event(r, kf.time, kf.time, f(&kf.value), f(&kf.value), RPEEasing::LINEAR));
+                }
+                covered = false;
+            }
+            Some((next, ExportedTween::Jump)) => {
+                events.push(export_event(r, kf.time, next.time, f(&next.value), f(&next.value), RPEEasing::LINEAR));
+                covered = true;
+            }
+            Some((next, ExportedTween::Ease(easing))) => {
+                events.push(export_event(r, kf.time, next.time, f(&kf.value), f(&next.value), easing));
+                covered = true;
+            }
+        }
+    }
+    events
+}
+
+fn export_speed_events(r: &mut BpmList, height: &AnimFloat) -> Vec<RPESpeedEvent> {
+    const D: f32 = 1e-3;
+    let kfs = &height.keyframes;
+    let mut events = Vec::new();
+    for (kf, next) in kfs.iter().zip(kfs.iter().skip(1)) {
+        if next.time <= kf.time {
+            continue;
+        }
+        let slope = (next.value - kf.value) / (next.time - kf.time);
+        let y = |x: f32| kf.tween.y(x);
+        events.push(RPESpeedEvent {
+            linkgroup: 0,
+            start_time: Triple::from_beats(r.beat(kf.time)),
+            end_time: Triple::from_beats(r.beat(next.time)),
+            start: slope * (y(D) - y(0.)) / D / SPEED_RATIO,
+            end: slope * (y(1.) - y(1. - D)) / D / SPEED_RATIO,
+        });
+    }
+    events
+}
+
+fn export_ctrl_events(anim: &AnimFloat, key: &str) -> Vec<RPECtrlEvent> {
+    anim.keyframes
+        .iter()
+        .map(|kf| RPECtrlEvent {
+            easing: match export_tween(&*kf.tween) {
+                ExportedTween::Ease(easing) => easing.easing_type as u8,
+                _ => 1,
+            },
+            x: kf.time,
+            value: HashMap::from([(key.to_owned(), kf.value)]),
+        })
+        .collect()
+}
+
+fn export_note(r: &mut BpmList, note: &Note) -> RPENote {
+    let first = |anim: &AnimFloat, default: f32| anim.keyframes.first().map_or(default, |it| it.value);
+    let alpha = first(&note.object.alpha, 1.);
+    let visible_time = if note.visible_time.is_finite() { note.visible_time } else { 999999. };
+    let end_time = if let NoteKind::Hold { end_time, .. } = note.kind {
+        end_time
+    } else {
+        note.time
+    };
+    RPENote {
+        kind: match note.kind {
+            NoteKind::Click => 1,
+            NoteKind::Hold { .. } => 2,
+            NoteKind::Flick => 3,
+            NoteKind::Drag => 4,
+        },
+        above: if note.above { 1 } else { 2 },
+        start_time: Triple::from_beats(r.beat(note.time)),
+        end_time: Triple::from_beats(r.beat(end_time)),
+        position_x: first(&note.object.translation.0, 0.) * (RPE_WIDTH / 2.),
+        y_offset: if note.speed == 0. {
+            0.
+        } else {
+            first(&note.object.translation.1, 0.) * (RPE_HEIGHT / 2.) / note.speed
+        },
+        alpha: (alpha * 255.).round() as u16,
+        size: first(&note.object.scale.0, 1.),
+        speed: note.speed,
+        is_fake: note.fake as u8,
+        visible_time,
+    }
+}
+
+fn export_judge_line(r: &mut BpmList, line: &JudgeLine, group: usize) -> RPEJudgeLine {
+    fn layers<T: Tweenable>(anim: &Anim<T>) -> Vec<&Anim<T>> {
+        let mut res = Vec::new();
+        let mut cur = Some(anim);
+        while let Some(anim) = cur {
+            if !anim.keyframes.is_empty() {
+                res.push(anim);
+            }
+            cur = anim.next.as_deref();
+        }
+        res
+    }
+    fn non_empty<T>(v: Vec<T>) -> Option<Vec<T>> {
+        if v.is_empty() {
+            None
+        } else {
+            Some(v)
+        }
+    }
+    fn layer_events(r: &mut BpmList, layers: &[&AnimFloat], index: usize, factor: f32) -> Option<Vec<RPEEvent>> {
+        layers
+            .get(index)
+            .and_then(|anim| non_empty(export_events(r, *anim, &|v: &f32| *v / factor)))
+    }
+    let alpha = layers(&line.object.alpha);
+    let rotation = layers(&line.object.rotation);
+    let move_x = layers(&line.object.translation.0);
+    let move_y = layers(&line.object.translation.1);
+    let mut speed_events = non_empty(export_speed_events(r, &line.height));
+    let layer_count = [alpha.len(), rotation.len(), move_x.len(), move_y.len(), 1].into_iter().max().unwrap();
+    let event_layers = (0..layer_count)
+        .map(|index| {
+            Some(RPEEventLayer {
+                alpha_events: layer_events(r, &alpha, index, 1. / 255.),
+                move_x_events: layer_events(r, &move_x, index, 2. / RPE_WIDTH),
+                move_y_events: layer_events(r, &move_y, index, 2. / RPE_HEIGHT),
+                rotate_events: layer_events(r, &rotation, index, -1.),
+                speed_events: speed_events.take(),
+            })
+        })
+        .collect();
+    if matches!(line.kind, JudgeLineKind::Texture(_)) {
+        warn!("Judge line textures cannot be exported, using the default line instead");
+    }
+    let scale_x_factor = if matches!(line.kind, JudgeLineKind::Text(_)) || line.attach_ui.is_some() {
+        1.
+    } else {
+        0.5
+    };
+    let extended = RPEExtendedEvents {
+        color_events: non_empty(export_events(r, &line.color, &|c: &Color| RGBColor::from(*c))),
+        text_events: if let JudgeLineKind::Text(anim) = &line.kind {
+            non_empty(export_events(r, anim, &String::clone))
+        } else {
+            None
+        },
+        scale_x_events: non_empty(export_events(r, &line.object.scale.0, &|v: &f32| *v / scale_x_factor)),
+        scale_y_events: non_empty(export_events(r, &line.object.scale.1, &|v: &f32| *v)),
+        incline_events: non_empty(export_events(r, &line.incline, &|v: &f32| *v)),
+        paint_events: if let JudgeLineKind::Paint(anim, _) = &line.kind {
+            non_empty(export_events(r, anim, &|v: &f32| *v))
+        } else {
+            None
+        },
+    };
+    let mut notes: Vec<_> = line.notes.iter().collect();
+    notes.sort_by_key(|it| it.time.not_nan());
+    let ctrl_obj = line.ctrl_obj.borrow();
+    RPEJudgeLine {
+        group,
+        // times are exported with the chart's BPM list, which already includes any factor the line had
+        bpmfactor: 1.,
+        name: "Untitled".to_owned(),
+        texture: "line.png".to_owned(),
+        parent: Some(line.parent.map_or(-1, |it| it as isize)),
+        event_layers,
+        extended: Some(extended),
+        notes: Some(notes.into_iter().map(|note| export_note(r, note)).collect()),
+        is_cover: if line.show_below { 0 } else { 1 },
+        z_order: line.z_index,
+        attach_ui: line.attach_ui,
+
+        pos_control: export_ctrl_events(&ctrl_obj.pos, "pos"),
+        size_control: export_ctrl_events(&ctrl_obj.size, "size"),
+        alpha_control: export_ctrl_events(&ctrl_obj.alpha, "alpha"),
+        y_control: export_ctrl_events(&ctrl_obj.y, "y"),
+    }
+}
+
+/// Serializes `chart` as an RPE chart. Times are converted to beats with `bpm_list`, which must not be empty.
+pub fn export_rpe(chart: &Chart, bpm_list: &BpmList) -> String {
+    let mut r = bpm_list.clone();
+    let mut groups = vec!["Default".to_owned()];
+    let line_groups: Vec<_> = chart
+        .lines
+        .iter()
+        .map(|line| {
+            let Some(group) = &line.group else {
+                return 0;
+            };
+            groups.iter().position(|it| it == group).unwrap_or_else(|| {
+                groups.push(group.clone());
+                groups.len() - 1
+            })
+        })
+        .collect();
+    let rpe = RPEChart {
+        meta: RPEMetadata {
+            offset: (chart.offset * 1000.).round() as i32,
+        },
+        bpm_list: r
+            .ranges()
+            .into_iter()
+            .map(|(beats, bpm)| RPEBpmItem {
+                bpm,
+                start_time: Triple::from_beats(beats),
+            })
+            .collect(),
+        judge_line_group: groups,
+        judge_line_list: chart
+            .lines
+            .iter()
+            .zip(line_groups)
+            .map(|(line, group)| export_judge_line(&mut r, line, group))
+            .collect(),
+    };
+    serde_json::to_string(&rpe).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::anim_value, *};
+    use crate::testing::{block_on, fixture_str, zip_fs};
+
+    fn parse(source: &str) -> Chart {
+        block_on(parse_rpe(source, zip_fs(&[]).as_mut(), ChartExtra::default())).unwrap()
+    }
+
+    fn assert_close(a: f32, b: f32, tolerance: f32, what: &str) {
+        assert!((a - b).abs() <= tolerance * a.abs().max(1.), "{what}: {a} != {b}");
+    }
+
+    #[test]
+    fn line_groups_and_bpm_factor() {
+        let (chart, diagnostics) = lint::capture(|| parse(&fixture_str("rpe_groups.json")));
+        let groups: Vec<_> = chart.lines.iter().map(|it| it.group.as_deref()).collect();
+        assert_eq!(groups, [Some("Default"), Some("Side"), Some("Side")]);
+
+        let mut bpm_list = chart.bpm_list.borrow().clone();
+        for (line, factor) in chart.lines.iter().zip([1., 2., 0.5]) {
+            let mut r = bpm_list.with_factor(factor);
+            let times: Vec<_> = line.notes.iter().map(|it| it.time).collect();
+            assert_eq!(times, [r.time_beats(2.), r.time_beats(6.)]);
+            // 4 beats at 120 BPM, then 240 BPM, all slowed down by the factor
+            assert_close(times[0], factor, 1e-6, "first note");
+            assert_close(times[1], 2.5 * factor, 1e-6, "second note");
+        }
+        // the chart-wide list is left alone
+        assert_eq!(bpm_list.time_beats(6.), 2.5);
+
+        // only the line with link groups is reported
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(&diagnostics[0].kind, DiagnosticKind::IgnoredKey { key } if key == "linkgroup"));
+        assert_eq!(diagnostics[0].line, Some(1));
+
+        // exported with the chart-wide BPM list, every line keeps its timing
+        let exported = parse(&export_rpe(&chart, &bpm_list));
+        for (a, b) in chart.lines.iter().zip(exported.lines.iter()) {
+            assert_eq!(a.group, b.group);
+            for (x, y) in a.notes.iter().zip(b.notes.iter()) {
+                assert_close(x.time, y.time, 1e-4, "note time");
+            }
+        }
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let chart = parse(&fixture_str("sample_rpe.json"));
+        let bpm_list = chart.bpm_list.borrow().clone();
+        let exported = parse(&export_rpe(&chart, &bpm_list));
+
+        assert_eq!(exported.offset, chart.offset);
+        assert_eq!(exported.bpm_list.borrow().ranges(), bpm_list.ranges());
+        assert_eq!(exported.lines.len(), chart.lines.len());
+        for (a, b) in chart.lines.iter().zip(exported.lines.iter()) {
+            assert_eq!(a.notes.len(), b.notes.len());
+            for (x, y) in a.notes.iter().zip(b.notes.iter()) {
+                assert_close(x.time, y.time, 1e-4, "note time");
+                match (&x.kind, &y.kind) {
+                    (NoteKind::Hold { end_time: e1, .. }, NoteKind::Hold { end_time: e2, .. }) => assert_close(*e1, *e2, 1e-4, "hold end"),
+                    (k1, k2) => assert_eq!(std::mem::discriminant(k1), std::mem::discriminant(k2)),
+                }
+                assert_eq!((x.above, x.fake), (y.above, y.fake));
+                assert_close(x.speed, y.speed, 1e-4, "note speed");
+                assert_close(
+                    anim_value(&x.object.translation.0, 0., false, 0.),
+                    anim_value(&y.object.translation.0, 0., false, 0.),
+                    1e-4,
+                    "note position",
+                );
+            }
+            for step in 0..=200 {
+                let t = step as f32 * 0.02;
+                for (what, x, y, tolerance) in [
+                    ("alpha", &a.object.alpha, &b.object.alpha, 1e-3),
+                    ("move X", &a.object.translation.0, &b.object.translation.0, 1e-3),
+                    ("move Y", &a.object.translation.1, &b.object.translation.1, 1e-3),
+                    ("rotation", &a.object.rotation, &b.object.rotation, 1e-3),
+                    // speeds are exported as slopes measured numerically
+                    ("height", &a.height, &b.height, 1e-2),
+                ] {
+                    assert_close(anim_value(x, t, false, 0.), anim_value(y, t, false, 0.), tolerance, &format!("{what} at {t}"));
+                }
+            }
+        }
+    }
+}
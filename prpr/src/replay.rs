@@ -0,0 +1,390 @@
+use crate::{
+    config::Config,
+    core::Chart,
+    info::ChartInfo,
+    judge::{FrameInput, JudgePreset, PlayResult},
+    sim::Simulation,
+};
+use anyhow::{bail, Context, Result};
+use macroquad::prelude::{vec2, Touch, TouchPhase};
+
+const MAGIC: &[u8; 4] = b"PRRP";
+// 1: initial format; 2: judge rules; 3: forced flicks of each frame
+const VERSION: u8 = 3;
+
+/// The part of [Config] (and of the device) that affects judging.
+#[derive(Clone, Debug)]
+pub struct ReplayConfig {
+    pub offset: f32,
+    pub speed: f32,
+    pub note_scale: f32,
+    pub aspect_ratio: f32,
+    pub dpi: u32,
+    pub judge_rules: JudgePreset,
+}
+
+#[derive(Clone)]
+pub struct ReplayFrame {
+    /// Chart time at which the frame was judged
+    pub time: f32,
+    pub input: FrameInput,
+}
+
+/// Touch input of a single run, recorded frame by frame as it was fed into [crate::judge::Judge].
+#[derive(Clone)]
+pub struct Replay {
+    pub config: ReplayConfig,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    pub fn new(config: ReplayConfig) -> Self {
+        Self { config, frames: Vec::new() }
+    }
+
+    pub fn push(&mut self, time: f32, input: &FrameInput) {
+        self.frames.push(ReplayFrame { time, input: input.clone() });
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Applies the recorded settings to `config`, turning autoplay off.
+    pub fn apply_to(&self, config: &mut Config) {
+        config.autoplay = false;
+        config.offset = self.config.offset;
+        config.speed = self.config.speed;
+        config.note_scale = self.config.note_scale;
+        config.aspect_ratio = Some(self.config.aspect_ratio);
+        config.judge_rules = self.config.judge_rules;
+    }
+
+    /// Plays the replay back on `chart` without a window.
+    pub fn simulation(&self, chart: Chart, mut config: Config, info: &ChartInfo) -> Simulation {
+        self.apply_to(&mut config);
+        let mut sim = Simulation::new(chart, config, info);
+        sim.res.dpi = self.config.dpi;
+        for frame in &self.frames {
+            sim.step(frame.time, frame.input.clone());
+        }
+        sim
+    }
+
+    /// Plays the replay back on `chart` without a window and returns the result.
+    pub fn simulate(&self, chart: Chart, config: Config, info: &ChartInfo) -> PlayResult {
+        self.simulation(chart, config, info).result()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        w.0.extend_from_slice(MAGIC);
+        w.0.push(VERSION);
+        let config = &self.config;
+        w.f32(config.offset);
+        w.f32(config.speed);
+        w.f32(config.note_scale);
+        w.f32(config.aspect_ratio);
+        w.u32(config.dpi);
+        w.0.push(config.judge_rules as u8);
+        w.u32(self.frames.len() as u32);
+        for frame in &self.frames {
+            w.f32(frame.time);
+            w.u32(frame.input.keys_down);
+            w.u32(frame.input.key_down_count);
+            w.touches(&frame.input.touches);
+            w.touches(&frame.input.events);
+            w.0.extend_from_slice(&(frame.input.flicks.len() as u16).to_le_bytes());
+            for id in &frame.input.flicks {
+                w.0.extend_from_slice(&id.to_le_bytes());
+            }
+        }
+        w.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut r = Reader(bytes);
+        if r.take(4)? != MAGIC {
+            bail!("Not a replay file");
+        }
+        let version = r.take(1)?[0];
+        if !(1..=VERSION).contains(&version) {
+            bail!("Unsupported replay version: {version}");
+        }
+        let config = ReplayConfig {
+            offset: r.f32()?,
+            speed: r.f32()?,
+            note_scale: r.f32()?,
+            aspect_ratio: r.f32()?,
+            dpi: r.u32()?,
+            judge_rules: if version < 2 {
+                JudgePreset::Official
+            } else {
+                match r.take(1)?[0] {
+                    0 => JudgePreset::Official,
+                    1 => JudgePreset::Strict,
+                    2 => JudgePreset::Lenient,
+                    value => bail!("Invalid judge rules: {value}"),
+                }
+            },
+        };
+        let len = r.u32()? as usize;
+        let mut frames = Vec::with_capacity(len.min(bytes.len()));
+        for _ in 0..len {
+            let time = r.f32()?;
+            let keys_down = r.u32()?;
+            let key_down_count = r.u32()?;
+            frames.push(ReplayFrame {
+                time,
+                input: FrameInput {
+                    touches: r.touches()?,
+                    events: r.touches()?,
+                    keys_down,
+                    key_down_count,
+                    flicks: if version < 3 { Vec::new() } else { r.ids()? },
+                },
+            });
+        }
+        if !r.0.is_empty() {
+            bail!("Trailing data in replay file");
+        }
+        Ok(Self { config, frames })
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        Self::from_bytes(&std::fs::read(path).context("Failed to read replay")?).context("Failed to parse replay")
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        std::fs::write(path, self.to_bytes()).context("Failed to write replay")
+    }
+}
+
+fn phase_to_u8(phase: TouchPhase) -> u8 {
+    match phase {
+        TouchPhase::Started => 0,
+        TouchPhase::Stationary => 1,
+        TouchPhase::Moved => 2,
+        TouchPhase::Ended => 3,
+        TouchPhase::Cancelled => 4,
+    }
+}
+
+fn u8_to_phase(value: u8) -> Result<TouchPhase> {
+    Ok(match value {
+        0 => TouchPhase::Started,
+        1 => TouchPhase::Stationary,
+        2 => TouchPhase::Moved,
+        3 => TouchPhase::Ended,
+        4 => TouchPhase::Cancelled,
+        _ => bail!("Invalid touch phase: {value}"),
+    })
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn touches(&mut self, touches: &[Touch]) {
+        self.0.extend_from_slice(&(touches.len() as u16).to_le_bytes());
+        for touch in touches {
+            self.0.extend_from_slice(&touch.id.to_le_bytes());
+            self.0.push(phase_to_u8(touch.phase));
+            self.f32(touch.position.x);
+            self.f32(touch.position.y);
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("Unexpected end of replay file");
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn ids(&mut self) -> Result<Vec<u64>> {
+        let len = u16::from_le_bytes(self.take(2)?.try_into().unwrap());
+        (0..len).map(|_| Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))).collect()
+    }
+
+    fn touches(&mut self) -> Result<Vec<Touch>> {
+        let len = u16::from_le_bytes(self.take(2)?.try_into().unwrap());
+        (0..len)
+            .map(|_| {
+                let id = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+                let phase = u8_to_phase(self.take(1)?[0])?;
+                let x = self.f32()?;
+                let y = self.f32()?;
+                Ok(Touch {
+                    id,
+                    phase,
+                    position: vec2(x, y),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::{ChartExtra, NoteKind},
+        parse::parse_pec,
+    };
+
+    fn chart() -> Chart {
+        parse_pec(include_str!("../tests/fixtures/simple.pec"), ChartExtra::default()).unwrap()
+    }
+
+    fn touch(id: u64, phase: TouchPhase) -> Touch {
+        Touch {
+            id,
+            phase,
+            position: vec2(0., 0.),
+        }
+    }
+
+    // taps every note in the middle of the screen at its exact time, keeping holds down until they end
+    fn play(chart: &Chart) -> Vec<(f32, FrameInput)> {
+        let notes = chart.lines[0]
+            .notes
+            .iter()
+            .filter(|it| !it.fake)
+            .map(|note| {
+                let end = match note.kind {
+                    NoteKind::Hold { end_time, .. } => end_time,
+                    _ => note.time + 0.1,
+                };
+                (note.time, end, matches!(note.kind, NoteKind::Flick))
+            })
+            .collect::<Vec<_>>();
+        let mut times = (0..=360).map(|it| it as f32 / 60.).chain(notes.iter().map(|it| it.0)).collect::<Vec<_>>();
+        times.sort_by(f32::total_cmp);
+        times.dedup();
+        let mut last = f32::NEG_INFINITY;
+        let mut frames = Vec::new();
+        for time in times {
+            let mut input = FrameInput::default();
+            for (id, &(start, end, flick)) in notes.iter().enumerate() {
+                let id = id as u64;
+                let phase = if start == time {
+                    TouchPhase::Started
+                } else if start < time && time <= end {
+                    if flick {
+                        input.flicks.push(id);
+                        TouchPhase::Moved
+                    } else {
+                        TouchPhase::Stationary
+                    }
+                } else if last <= end && end < time {
+                    TouchPhase::Ended
+                } else {
+                    continue;
+                };
+                input.touches.push(touch(id, phase));
+                if matches!(phase, TouchPhase::Started | TouchPhase::Ended) {
+                    input.events.push(touch(id, phase));
+                }
+            }
+            frames.push((time, input));
+            last = time;
+        }
+        frames
+    }
+
+    #[test]
+    fn round_trip() {
+        let info = ChartInfo::default();
+        let mut replay = Replay::new(ReplayConfig {
+            offset: 0.,
+            speed: 1.,
+            note_scale: 1.,
+            aspect_ratio: 16. / 9.,
+            dpi: 160,
+            judge_rules: JudgePreset::Strict,
+        });
+        let mut sim = replay.simulation(chart(), Config::default(), &info);
+        for (time, input) in play(&sim.chart) {
+            replay.push(time, &input);
+            sim.step(time, input);
+        }
+        let expected = sim.result();
+        // the three clicks at least are hit perfectly
+        assert!(expected.counts[0] >= 3);
+
+        let decoded = Replay::from_bytes(&replay.to_bytes()).unwrap();
+        assert_eq!(decoded.frames.len(), replay.frames.len());
+        assert_eq!(decoded.config.judge_rules, JudgePreset::Strict);
+        let result = decoded.simulate(chart(), Config::default(), &info);
+        assert_eq!(result.score, expected.score);
+        assert_eq!(result.accuracy, expected.accuracy);
+        assert_eq!(result.max_combo, expected.max_combo);
+        assert_eq!(result.num_of_notes, expected.num_of_notes);
+        assert_eq!(result.counts, expected.counts);
+        assert_eq!((result.early, result.late), (expected.early, expected.late));
+        assert_eq!(result.rules, expected.rules);
+        assert_eq!(result.offsets.len(), expected.offsets.len());
+        for (a, b) in result.offsets.iter().zip(expected.offsets.iter()) {
+            assert_eq!((a.line, a.time, a.offset), (b.line, b.time, b.offset));
+        }
+    }
+
+    // a replay with a single frame in the format of `version`
+    fn legacy(version: u8) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        w.0.extend_from_slice(MAGIC);
+        w.0.push(version);
+        for value in [0.1, 1., 1., 16. / 9.] {
+            w.f32(value);
+        }
+        w.u32(160);
+        if version >= 2 {
+            w.0.push(JudgePreset::Lenient as u8);
+        }
+        w.u32(1);
+        w.f32(0.5);
+        w.u32(0);
+        w.u32(0);
+        w.touches(&[touch(0, TouchPhase::Started)]);
+        w.touches(&[touch(0, TouchPhase::Started)]);
+        w.0
+    }
+
+    #[test]
+    fn decode_older_versions() {
+        let v1 = Replay::from_bytes(&legacy(1)).unwrap();
+        assert_eq!(v1.config.judge_rules, JudgePreset::Official);
+        assert_eq!(v1.config.dpi, 160);
+        assert_eq!(v1.frames.len(), 1);
+        assert_eq!(v1.frames[0].input.touches.len(), 1);
+        assert!(v1.frames[0].input.flicks.is_empty());
+
+        let v2 = Replay::from_bytes(&legacy(2)).unwrap();
+        assert_eq!(v2.config.judge_rules, JudgePreset::Lenient);
+        assert_eq!(v2.frames[0].time, 0.5);
+
+        assert!(Replay::from_bytes(&legacy(VERSION + 1)).is_err());
+    }
+}
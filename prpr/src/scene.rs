@@ -2,7 +2,9 @@ mod ending;
 pub use ending::EndingScene;
 
 mod game;
-pub use game::{GameMode, GameScene, FFMPEG_PATH};
+pub use game::{
+    GameMode, GameScene, PracticeSection, CARRIED_COMBO, DEBUG_VIEW, HOT_RELOAD, PLAYBACK, PRACTICE_SECTIONS, REPLAY_PATH, RESULT_LISTENER,
+};
 
 mod loading;
 pub use loading::LoadingScene;
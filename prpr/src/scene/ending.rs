@@ -1,11 +1,12 @@
 use super::{draw_background, draw_illustration, NextScene, Scene};
 use crate::{
     config::Config,
+    core::{JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR},
     ext::{
         create_audio_manger, draw_parallelogram, draw_parallelogram_ex, draw_text_aligned, screen_aspect, SafeTexture, ScaleType, PARALLELOGRAM_SLOPE,
     },
     info::ChartInfo,
-    judge::{Judge, PlayResult},
+    judge::{Judge, JudgePreset, JudgeRules, PlayResult},
     ui::Ui,
 };
 use anyhow::Result;
@@ -31,6 +32,7 @@ pub struct EndingScene {
     challenge_rank: u32,
     autoplay: bool,
     speed: f32,
+    show_timing: bool,
     next: u8, // 0 -> none, 1 -> pop, 2 -> exit
 }
 
@@ -76,11 +78,68 @@ impl EndingScene {
             challenge_rank: config.challenge_rank,
             autoplay: config.autoplay,
             speed: config.speed,
+            show_timing: false,
             next: 0,
         })
     }
 }
 
+fn offset_color(offset: f32, rules: &JudgeRules) -> Color {
+    let offset = offset.abs();
+    if offset <= rules.perfect {
+        JUDGE_LINE_PERFECT_COLOR
+    } else if offset <= rules.good {
+        JUDGE_LINE_GOOD_COLOR
+    } else {
+        Color::new(0.9, 0.4, 0.4, 1.)
+    }
+}
+
+/// Draws the hit-error histogram and the timing-over-time plot inside the parallelogram `r`.
+fn draw_timing(ui: &mut Ui, r: Rect, result: &PlayResult) {
+    draw_parallelogram(r, None, Color::new(0., 0., 0., 0.8), false);
+    let pad = 0.03;
+    let l = r.h * PARALLELOGRAM_SLOPE;
+    let inner = Rect::new(r.x + l + pad, r.y + pad, r.w - l * 2. - pad * 2., r.h - pad * 2.);
+    let Some((mean, std)) = result.offset_stats() else {
+        draw_text_aligned(ui, "No timing data", inner.center().x, inner.center().y, (0.5, 0.5), 0.5, WHITE);
+        return;
+    };
+    let text = draw_text_aligned(ui, &format!("Mean {:+.1}ms    σ {:.1}ms", mean * 1000., std * 1000.), inner.x, inner.y, (0., 0.), 0.4, WHITE);
+
+    const BINS: usize = 44;
+    let rules = result.rules.rules();
+    let limit = rules.bad;
+    let h = (inner.bottom() - text.bottom() - 0.1) / 2.;
+    let hist = Rect::new(inner.x, text.bottom() + 0.03, inner.w, h);
+    let mut counts = [0; BINS];
+    for it in &result.offsets {
+        let bin = ((it.offset + limit) / (limit * 2.) * BINS as f32).floor();
+        if (0. ..BINS as f32).contains(&bin) {
+            counts[bin as usize] += 1;
+        }
+    }
+    let max = counts.iter().copied().max().unwrap_or_default().max(1);
+    let w = hist.w / BINS as f32;
+    for (i, count) in counts.into_iter().enumerate() {
+        let center = ((i as f32 + 0.5) / BINS as f32 * 2. - 1.) * limit;
+        let h = hist.h * count as f32 / max as f32;
+        ui.fill_rect(Rect::new(hist.x + w * i as f32, hist.bottom() - h, w * 0.8, h), offset_color(center, &rules));
+    }
+    ui.fill_rect(Rect::new(hist.center().x - 0.001, hist.y, 0.002, hist.h), WHITE);
+    draw_text_aligned(ui, "Early", hist.x, hist.bottom() + 0.005, (0., 0.), 0.3, WHITE);
+    draw_text_aligned(ui, "Late", hist.right(), hist.bottom() + 0.005, (1., 0.), 0.3, WHITE);
+
+    let plot = Rect::new(inner.x, inner.bottom() - h, inner.w, h);
+    ui.fill_rect(Rect::new(plot.x, plot.center().y - 0.001, plot.w, 0.002), Color::new(1., 1., 1., 0.5));
+    let end = result.offsets.iter().map(|it| it.time).fold(0., f32::max).max(1e-3);
+    for it in &result.offsets {
+        let x = plot.x + it.time / end * plot.w;
+        let y = plot.center().y + (it.offset / limit).clamp(-1., 1.) * plot.h / 2.;
+        ui.fill_circle(x, y, 0.004, offset_color(it.offset, &rules));
+    }
+}
+
 impl Scene for EndingScene {
     fn enter(&mut self, tm: &mut crate::time::TimeManager, target: Option<RenderTarget>) -> Result<()> {
         tm.reset();
@@ -131,6 +190,7 @@ impl Scene for EndingScene {
 
         tran(gl, (1. - ran(now, 0.1, 1.3)).powi(3));
         let r = draw_illustration(*self.illustration, -0.38, 0., 1., 1.2, WHITE);
+        let illustration = r;
         let slope = PARALLELOGRAM_SLOPE;
         let ratio = 0.2;
         draw_parallelogram_ex(
@@ -165,8 +225,13 @@ impl Scene for EndingScene {
             let r = draw_text_aligned(
                 ui,
                 &format!(
-                    "PRPR{} {}   {:07}  +{:07}",
+                    "PRPR{}{} {}   {:07}  +{:07}",
                     if self.autoplay { "[AUTOPLAY]" } else { "" },
+                    match res.rules {
+                        JudgePreset::Official => "",
+                        JudgePreset::Strict => "[STRICT]",
+                        JudgePreset::Lenient => "[LENIENT]",
+                    },
                     if (self.speed - 1.).abs() <= 1e-4 {
                         String::new()
                     } else {
@@ -288,6 +353,14 @@ impl Scene for EndingScene {
             self.next = 2;
         }
 
+        // tap the illustration to show the timing statistics
+        if now >= 1.3 && touched(illustration) {
+            self.show_timing = !self.show_timing;
+        }
+        if self.show_timing {
+            draw_timing(ui, illustration, &self.result);
+        }
+
         let alpha = ran(now, 1.5, 1.9);
         let main = Rect::new(1. - 0.28, -top + dy * 2.5, 0.35, 0.1);
         draw_parallelogram(main, None, Color::new(0., 0., 0., c.a * alpha), false);
@@ -1,29 +1,50 @@
-use super::{draw_background, request_input, return_input, show_message, take_input, EndingScene, NextScene, Scene};
+use super::{draw_background, request_input, return_input, show_error, show_message, take_input, EndingScene, NextScene, Scene};
 use crate::{
     config::Config,
-    core::{copy_fbo, BadNote, Chart, ChartExtra, Effect, Point, Resource, UIElement, Vector, JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR},
+    core::{copy_fbo, BadNote, Chart, Effect, Point, Resource, UIElement, Vector, JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR},
     ext::{screen_aspect, RectExt, SafeTexture},
     fs::FileSystem,
-    info::{ChartFormat, ChartInfo},
-    judge::Judge,
-    parse::{parse_extra, parse_pec, parse_phigros, parse_rpe},
+    info::ChartInfo,
+    judge::{FrameInput, InputSource, Judge, KeyboardInput, PlayResult},
+    parse::load_chart,
+    replay::{Replay, ReplayConfig},
     time::TimeManager,
     ui::{RectButton, Ui},
 };
 use anyhow::{bail, Context, Result};
-use concat_string::concat_string;
 use macroquad::{prelude::*, window::InternalGlContext};
 use sasa::{Music, MusicParams};
 use std::{
-    io::ErrorKind,
-    ops::{DerefMut, Range},
+    ops::Range,
     path::PathBuf,
-    process::{Command, Stdio},
     rc::Rc,
-    sync::Mutex,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
 };
 
-pub static FFMPEG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+mod debug;
+pub use debug::DEBUG_VIEW;
+use debug::{DebugView, FRAME_STEP};
+
+mod practice;
+use practice::{fmt_beat, Practice};
+pub use practice::{PracticeSection, PRACTICE_SECTIONS};
+
+mod reload;
+use reload::ChartWatcher;
+pub use reload::HOT_RELOAD;
+
+/// If set, the input of every finished run in [GameMode::Normal] is saved to this path as a [Replay].
+pub static REPLAY_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+/// If set, games in [GameMode::Normal] play this replay back instead of reading input.
+pub static PLAYBACK: Mutex<Option<Replay>> = Mutex::new(None);
+/// If set, called with the result of every run in [GameMode::Normal] that was actually played (not autoplay or playback).
+pub static RESULT_LISTENER: Mutex<Option<Box<dyn FnMut(&PlayResult) + Send>>> = Mutex::new(None);
+/// Added to the combo shown in [GameMode::Normal] until the first bad or miss, so that a challenge carries the combo over from one chart
+/// to the next.
+pub static CARRIED_COMBO: AtomicU32 = AtomicU32::new(0);
 
 const WAIT_TIME: f32 = 0.5;
 const AFTER_TIME: f32 = 0.7;
@@ -50,6 +71,8 @@ pub enum GameMode {
     Normal,
     TweakOffset,
     Exercise,
+    /// Plays normally, then suggests an offset for the chart from the hits
+    Calibrate,
 }
 
 #[derive(Clone)]
@@ -68,15 +91,20 @@ pub struct GameScene {
     pub res: Resource,
     pub chart: Chart,
     pub judge: Judge,
+    carried_combo: u32,
     pub gl: InternalGlContext<'static>,
     info_offset: f32,
     compatible_mode: bool,
     effects: Vec<Effect>,
 
     first_in: bool,
+    calibrated: bool,
     exercise_range: Range<f32>,
     exercise_press: Option<(i8, u64)>,
     exercise_btns: (RectButton, RectButton),
+    practice: Practice,
+    debug: Option<DebugView>,
+    watcher: Option<ChartWatcher>,
 
     pub music: Music,
 
@@ -87,17 +115,29 @@ pub struct GameScene {
     pause_rewind: Option<f64>,
 
     bad_notes: Vec<BadNote>,
+    inputs: Vec<Box<dyn InputSource>>,
+    replay: Option<Replay>,
+    // the replay being played back and the index of the next frame
+    playback: Option<(Replay, usize)>,
 }
 
 macro_rules! reset {
     ($self:ident, $res:expr, $tm:ident) => {{
         $self.bad_notes.clear();
         $self.judge.reset();
+        if let Some(replay) = &mut $self.replay {
+            replay.clear();
+        }
+        if let Some((_, next)) = &mut $self.playback {
+            *next = 0;
+        }
         $self.chart.reset();
         $res.judge_line_color = JUDGE_LINE_PERFECT_COLOR;
         $self.music.pause()?;
         $self.music.seek_to(0.)?;
         $tm.reset();
+        $self.calibrated = false;
+        $self.practice.base_counts = None;
         $self.last_update_time = $tm.now();
         $self.state = State::Starting;
     }};
@@ -107,55 +147,6 @@ impl GameScene {
     pub const BEFORE_TIME: f32 = 0.7;
     pub const FADEOUT_TIME: f32 = WAIT_TIME + AFTER_TIME + 0.3;
 
-    pub async fn load_chart(fs: &mut Box<dyn FileSystem>, info: &ChartInfo) -> Result<Chart> {
-        async fn load_chart_bytes(fs: &mut Box<dyn FileSystem>, info: &ChartInfo) -> Result<Vec<u8>> {
-            if let Ok(bytes) = fs.load_file(&info.chart).await {
-                return Ok(bytes);
-            }
-            if let Some(name) = info.chart.strip_suffix(".pec") {
-                if let Ok(bytes) = fs.load_file(&concat_string!(name, ".json")).await {
-                    return Ok(bytes);
-                }
-            }
-            bail!("Cannot find chart file")
-        }
-        let extra = fs.load_file("extra.json").await.ok().map(|it| String::from_utf8(it)).transpose()?;
-        let extra = if let Some(extra) = extra {
-            let ffmpeg: PathBuf = FFMPEG_PATH.lock().unwrap().to_owned().unwrap_or_else(|| "ffmpeg".into());
-            let ffmpeg = if match Command::new(&ffmpeg).stdout(Stdio::null()).stderr(Stdio::null()).spawn() {
-                Ok(_) => true,
-                Err(err) => err.kind() != ErrorKind::NotFound,
-            } {
-                Some(ffmpeg.as_path())
-            } else {
-                warn!("ffmpeg not found at {}, disabling video", ffmpeg.display());
-                None
-            };
-            parse_extra(&extra, fs.deref_mut(), ffmpeg).await.context("Failed to parse extra")?
-        } else {
-            ChartExtra::default()
-        };
-        let text = String::from_utf8(load_chart_bytes(fs, info).await.context("Failed to load chart")?)?;
-        let format = info.format.clone().unwrap_or_else(|| {
-            if text.starts_with('{') {
-                if text.contains("\"META\"") {
-                    ChartFormat::Rpe
-                } else {
-                    ChartFormat::Pgr
-                }
-            } else {
-                ChartFormat::Pec
-            }
-        });
-        let mut chart = match format {
-            ChartFormat::Rpe => parse_rpe(&text, fs.deref_mut(), extra).await,
-            ChartFormat::Pgr => parse_phigros(&text, extra),
-            ChartFormat::Pec => parse_pec(&text, extra),
-        }?;
-        chart.settings.hold_partial_cover = info.hold_partial_cover;
-        Ok(chart)
-    }
-
     pub async fn new(
         mode: GameMode,
         info: ChartInfo,
@@ -170,27 +161,73 @@ impl GameScene {
             GameMode::TweakOffset => {
                 config.autoplay = true;
             }
-            GameMode::Exercise => {
+            GameMode::Exercise | GameMode::Calibrate => {
                 config.autoplay = false;
             }
             _ => {}
         }
-        let mut chart = Self::load_chart(&mut fs, &info).await?;
-        let effects = std::mem::take(&mut chart.extra.global_effects);
-        if config.fxaa {
-            chart
-                .extra
-                .effects
-                .push(Effect::new(0.0..f32::INFINITY, include_str!("fxaa.glsl"), Vec::new(), false).unwrap());
+        let playback = if mode == GameMode::Normal {
+            PLAYBACK.lock().unwrap().clone()
+        } else {
+            None
+        };
+        let carried_combo = if mode == GameMode::Normal {
+            CARRIED_COMBO.load(Ordering::SeqCst)
+        } else {
+            0
+        };
+        if let Some(replay) = &playback {
+            replay.apply_to(&mut config);
         }
+        let mut chart = load_chart(&mut fs, &info).await?;
+        let effects = Self::take_effects(&mut chart, &config);
+        let watcher = if HOT_RELOAD.load(Ordering::SeqCst) {
+            ChartWatcher::new(&mut fs, &info)
+        } else {
+            None
+        };
 
         let info_offset = info.offset;
         let mut res = Resource::new(config, info, fs, player, background, illustration, chart.extra.effects.is_empty() && effects.is_empty())
             .await
             .context("Failed to load resources")?;
+        if let Some(replay) = &playback {
+            res.dpi = replay.config.dpi;
+        }
         let exercise_range = (chart.offset + info_offset + res.config.offset)..res.track_length;
+        let sections = if mode == GameMode::Exercise {
+            PRACTICE_SECTIONS.lock().unwrap().clone()
+        } else {
+            Vec::new()
+        };
+        let practice = Practice::new(sections, res.config.speed);
 
-        let judge = Judge::new(&chart);
+        let judge = Judge::new(&chart, res.config.judge_rules.rules());
+        let mut inputs: Vec<Box<dyn InputSource>> = Vec::new();
+        if let Some(keyboard) = &res.config.keyboard {
+            inputs.push(Box::new(KeyboardInput::new(keyboard).context("Invalid keyboard configuration")?));
+        }
+        if let Some(midi) = &res.config.midi {
+            #[cfg(all(feature = "midi", not(any(target_os = "android", target_os = "ios"))))]
+            inputs.push(Box::new(crate::judge::MidiInput::new(midi)?));
+            #[cfg(not(all(feature = "midi", not(any(target_os = "android", target_os = "ios")))))]
+            {
+                let _ = midi;
+                bail!("MIDI input is not enabled in this build");
+            }
+        }
+        let replay = if mode == GameMode::Normal && !res.config.autoplay && playback.is_none() && REPLAY_PATH.lock().unwrap().is_some() {
+            Some(Replay::new(ReplayConfig {
+                offset: res.config.offset,
+                speed: res.config.speed,
+                note_scale: res.config.note_scale,
+                aspect_ratio: res.aspect_ratio,
+                dpi: res.dpi,
+                judge_rules: res.config.judge_rules,
+            }))
+        } else {
+            None
+        };
 
         let music = Self::new_music(&mut res)?;
         Ok(Self {
@@ -201,15 +238,20 @@ impl GameScene {
             res,
             chart,
             judge,
+            carried_combo,
             gl: unsafe { get_internal_gl() },
             compatible_mode: false,
             effects,
             info_offset,
 
             first_in: false,
+            calibrated: false,
             exercise_range,
             exercise_press: None,
             exercise_btns: (RectButton::new(), RectButton::new()),
+            practice,
+            debug: DEBUG_VIEW.load(Ordering::SeqCst).then(DebugView::default),
+            watcher,
 
             music,
 
@@ -220,9 +262,43 @@ impl GameScene {
             pause_rewind: None,
 
             bad_notes: Vec::new(),
+            inputs,
+            replay,
+            playback: playback.map(|it| (it, 0)),
         })
     }
 
+    // global effects are rendered by the scene, the others by the chart
+    fn take_effects(chart: &mut Chart, config: &Config) -> Vec<Effect> {
+        let effects = std::mem::take(&mut chart.extra.global_effects);
+        if config.fxaa {
+            chart
+                .extra
+                .effects
+                .push(Effect::new(0.0..f32::INFINITY, include_str!("fxaa.glsl"), Vec::new(), false).unwrap());
+        }
+        effects
+    }
+
+    /// Swaps in a reloaded chart, keeping the time and everything loaded by [Resource].
+    fn replace_chart(&mut self, info: ChartInfo, mut chart: Chart) {
+        self.effects = Self::take_effects(&mut chart, &self.res.config);
+        let no_effect = self.res.config.disable_effect || (chart.extra.effects.is_empty() && self.effects.is_empty());
+        if no_effect != self.res.no_effect {
+            // the render target is recreated (or not) on the next render
+            self.res.no_effect = no_effect;
+            self.res.chart_target = None;
+            self.res.last_screen_size = (0, 0);
+        }
+        self.judge = Judge::new(&chart, self.res.config.judge_rules.rules());
+        self.bad_notes.clear();
+        self.practice.base_counts = None;
+        self.chart = chart;
+        self.info_offset = info.offset;
+        self.res.info = info;
+        show_message("Chart reloaded");
+    }
+
     fn new_music(res: &mut Resource) -> Result<Music> {
         res.audio.create_music(
             res.music.clone(),
@@ -295,9 +371,14 @@ impl GameScene {
                 ui.fill_rect(r, c);
             });
         });
-        if self.judge.combo >= 3 {
+        let combo = if self.judge.counts[2] + self.judge.counts[3] == 0 {
+            self.carried_combo + self.judge.combo
+        } else {
+            self.judge.combo
+        };
+        if combo >= 3 {
             let btm = self.chart.with_element(ui, res, UIElement::ComboNumber, |ui, color, scale| {
-                ui.text(self.judge.combo.to_string())
+                ui.text(combo.to_string())
                     .pos(0., top + eps * 2. - (1. - p) * 0.4)
                     .anchor(0.5, 0.)
                     .color(Color { a: color.a * c.a, ..color })
@@ -357,7 +438,8 @@ impl GameScene {
     fn overlay_ui(&mut self, ui: &mut Ui, tm: &mut TimeManager) -> Result<()> {
         let c = Color::new(1., 1., 1., self.res.alpha);
         let res = &mut self.res;
-        if tm.paused() {
+        // the debug view keeps the chart visible while paused, it's controlled with the keyboard instead
+        if tm.paused() && self.debug.is_none() {
             let h = 1. / res.aspect_ratio;
             draw_rectangle(-1., -h, 2., h * 2., Color::new(0., 0., 0., 0.6));
             let o = if self.mode == GameMode::Exercise { -0.3 } else { 0. };
@@ -500,12 +582,16 @@ impl GameScene {
                         if *ctrl == 0 {
                             tm.seek_to(p as f64);
                             self.music.seek_to(p)?;
+                            self.practice.base_counts = None;
                         } else {
+                            // markers snap to beats
+                            let p = self.from_beat(self.to_beat(p).round());
                             *(if *ctrl == -1 {
                                 &mut self.exercise_range.start
                             } else {
                                 &mut self.exercise_range.end
                             }) = p;
+                            self.practice.restart();
                         }
                         if matches!(touch.phase, TouchPhase::Cancelled | TouchPhase::Ended) {
                             self.exercise_press = None;
@@ -513,29 +599,34 @@ impl GameScene {
                     }
                 }
                 ui.dy(0.2);
+                let start = format!("Beat {}", fmt_beat(self.to_beat(self.exercise_range.start)));
+                let end = format!("Beat {}", fmt_beat(self.to_beat(self.exercise_range.end)));
                 let r = ui.text("至").size(0.8).anchor(0.5, 0.).draw();
-                let mut tx = ui
-                    .text(fmt_time(self.exercise_range.start))
-                    .pos(r.x - 0.02, 0.)
-                    .anchor(1., 0.)
-                    .size(0.8)
-                    .color(BLACK);
+                let mut tx = ui.text(start).pos(r.x - 0.02, 0.).anchor(1., 0.).size(0.8).color(BLACK);
                 let re = tx.measure();
                 self.exercise_btns.0.set(tx.ui, re);
                 tx.ui
                     .fill_rect(re.feather(0.01), Color::new(1., 1., 1., if self.exercise_btns.0.touching() { 0.5 } else { 1. }));
                 tx.draw();
 
-                let mut tx = ui
-                    .text(fmt_time(self.exercise_range.end))
-                    .pos(r.right() + 0.02, 0.)
-                    .size(0.8)
-                    .color(BLACK);
+                let mut tx = ui.text(end).pos(r.right() + 0.02, 0.).size(0.8).color(BLACK);
                 let re = tx.measure();
                 self.exercise_btns.1.set(tx.ui, re);
                 tx.ui
                     .fill_rect(re.feather(0.01), Color::new(1., 1., 1., if self.exercise_btns.1.touching() { 0.5 } else { 1. }));
                 tx.draw();
+
+                self.practice_ui(ui);
+            }
+        }
+        if self.debug.is_some() {
+            let time = tm.now() as f32;
+            let beat = self.to_beat(time);
+            let range = (self.offset().min(0.), self.res.track_length);
+            let debug = self.debug.as_mut().unwrap();
+            debug.lines(ui, &self.res, &self.chart);
+            if let Some(time) = debug.timeline(ui, range, time, beat) {
+                self.debug_seek(tm, time)?;
             }
         }
         if let Some(time) = self.pause_rewind {
@@ -561,6 +652,119 @@ impl GameScene {
         self.chart.offset + self.res.config.offset + self.info_offset
     }
 
+    fn to_beat(&self, t: f32) -> f32 {
+        self.chart.bpm_list.borrow_mut().beat(t - self.offset())
+    }
+
+    fn from_beat(&self, beat: f32) -> f32 {
+        self.chart.bpm_list.borrow_mut().time_beats(beat) + self.offset()
+    }
+
+    fn set_speed(&mut self, tm: &mut TimeManager, speed: f32) -> Result<()> {
+        if self.res.config.speed != speed {
+            self.res.config.speed = speed;
+            self.music = Self::new_music(&mut self.res)?;
+            tm.speed = speed as _;
+        }
+        Ok(())
+    }
+
+    fn debug_seek(&mut self, tm: &mut TimeManager, time: f32) -> Result<()> {
+        if !matches!(self.state, State::BeforeMusic | State::Playing) {
+            return Ok(());
+        }
+        let time = time.clamp(self.offset().min(0.), self.res.track_length);
+        if (time as f64) < tm.now() {
+            // bring the notes back, the judge catches up with the new time on the next update
+            self.bad_notes.clear();
+            self.judge.reset();
+            self.chart.reset();
+            if let Some((_, next)) = &mut self.playback {
+                *next = 0;
+            }
+        }
+        tm.seek_to(time as f64);
+        if time < 0. {
+            self.music.pause()?;
+            self.state = State::BeforeMusic;
+        } else if matches!(self.state, State::Playing) {
+            self.music.seek_to(time)?;
+        }
+        Ok(())
+    }
+
+    fn practice_ui(&mut self, ui: &mut Ui) {
+        ui.dy(0.1);
+        let h = 0.06;
+        let count = self.practice.sections.len();
+        let current = self.practice.current;
+        let name = current.map_or_else(|| "Custom section".to_owned(), |it| self.practice.sections[it].name.clone());
+        let mut r = Rect::new(-0.58, 0., h, h);
+        if ui.button("section_prev", r, "<") && count != 0 {
+            self.select_section(current.map_or(count - 1, |it| (it + count - 1) % count));
+        }
+        r.x += r.w;
+        ui.text(&name)
+            .pos(r.x + 0.16, h / 2.)
+            .anchor(0.5, 0.5)
+            .no_baseline()
+            .size(0.5)
+            .max_width(0.3)
+            .draw();
+        r.x += 0.32;
+        if ui.button("section_next", r, ">") && count != 0 {
+            self.select_section(current.map_or(0, |it| (it + 1) % count));
+        }
+        r.x += r.w + 0.03;
+        r.w = 0.14;
+        if ui.button("section_save", r, "Save") {
+            request_input("practice_name", &current.map_or_else(|| format!("Section {}", count + 1), |_| name));
+        }
+        r.x += r.w + 0.01;
+        if ui.button("section_delete", r, "Delete") {
+            if let Some(index) = current {
+                self.practice.sections.remove(index);
+                self.practice.current = None;
+            }
+        }
+        r.x += r.w + 0.06;
+        r.w = h;
+        if ui.button("loops_sub", r, "-") && self.practice.loops > 1 {
+            self.practice.loops -= 1;
+            self.practice.restart();
+        }
+        r.x += r.w;
+        ui.text(format!("{} loops", self.practice.loops))
+            .pos(r.x + 0.1, h / 2.)
+            .anchor(0.5, 0.5)
+            .no_baseline()
+            .size(0.5)
+            .draw();
+        r.x += 0.2;
+        if ui.button("loops_add", r, "+") && self.practice.loops < 20 {
+            self.practice.loops += 1;
+            self.practice.restart();
+        }
+        ui.dy(h + 0.03);
+        let history = &self.practice.history;
+        if !history.is_empty() {
+            let text = history[history.len().saturating_sub(6)..]
+                .iter()
+                .map(|(speed, accuracy)| format!("{speed:.2}x {}", accuracy.map_or_else(|| "-".to_owned(), |it| format!("{:.2}%", it * 100.))))
+                .collect::<Vec<_>>()
+                .join("   ");
+            ui.text(text).anchor(0.5, 0.).size(0.45).draw();
+        }
+    }
+
+    fn select_section(&mut self, index: usize) {
+        let section = &self.practice.sections[index];
+        self.exercise_range = self.from_beat(section.start)..self.from_beat(section.end);
+        self.practice.current = Some(index);
+        self.practice.restart();
+        self.practice.history.clear();
+    }
+
     fn tweak_offset(&mut self, ui: &mut Ui, ita: bool) {
         ui.scope(|ui| {
             let width = 0.55;
@@ -649,16 +853,56 @@ impl Scene for GameScene {
 
     fn update(&mut self, tm: &mut TimeManager) -> Result<()> {
         self.res.audio.recover_if_needed()?;
+        if let Some(result) = self.watcher.as_mut().and_then(|it| it.poll(tm.real_time())) {
+            match result {
+                Ok((info, chart)) => self.replace_chart(info, chart),
+                Err(err) => show_error(err.context("Failed to reload the chart")),
+            }
+        }
+        if let Some(debug) = &mut self.debug {
+            if is_key_pressed(KeyCode::Tab) {
+                debug.show_lines ^= true;
+            }
+            if tm.paused() {
+                let step = if is_key_pressed(KeyCode::Period) {
+                    FRAME_STEP
+                } else if is_key_pressed(KeyCode::Comma) {
+                    -FRAME_STEP
+                } else {
+                    0.
+                };
+                if step != 0. {
+                    debug.stepped = true;
+                    self.debug_seek(tm, tm.now() as f32 + step)?;
+                }
+            }
+        }
         if matches!(self.state, State::Playing) {
             tm.update(self.music.position() as f64);
         }
         if self.mode == GameMode::Exercise && tm.now() > self.exercise_range.end as f64 && !tm.paused() {
+            let index = self.practice.loop_index;
+            let last = self.practice.finish_loop(&self.judge);
+            if let Some((speed, accuracy)) = self.practice.history.last() {
+                let accuracy = accuracy.map_or_else(|| "-".to_owned(), |it| format!("{:.2}%", it * 100.));
+                show_message(format!("Loop {}/{} ({speed:.2}x): {accuracy}", index + 1, self.practice.loops));
+            }
             let state = self.state.clone();
             reset!(self, self.res, tm);
             self.state = state;
-            tm.seek_to(self.exercise_range.start as f64);
-            tm.pause();
-            self.music.pause()?;
+            self.set_speed(tm, self.practice.speed())?;
+            let start = self.exercise_range.start;
+            tm.seek_to(start as f64);
+            if last {
+                tm.pause();
+                self.music.pause()?;
+            } else if start < 0. {
+                // the music starts again once the time reaches 0
+                self.state = State::BeforeMusic;
+            } else {
+                self.music.seek_to(start)?;
+                self.music.play()?;
+            }
         }
         let offset = self.offset();
         let time = tm.now() as f32;
@@ -707,6 +951,18 @@ impl Scene for GameScene {
             State::Ending => {
                 let t = time - self.res.track_length - WAIT_TIME;
                 if t >= AFTER_TIME + 0.3 {
+                    if let Some(replay) = &self.replay {
+                        if let Some(path) = REPLAY_PATH.lock().unwrap().as_ref() {
+                            if let Err(err) = replay.save(path) {
+                                warn!("Failed to save replay: {err:?}");
+                            }
+                        }
+                    }
+                    if self.mode == GameMode::Normal && !self.res.config.autoplay && self.playback.is_none() {
+                        if let Some(listener) = RESULT_LISTENER.lock().unwrap().as_mut() {
+                            listener(&self.judge.result());
+                        }
+                    }
                     self.next_scene = match self.mode {
                         GameMode::Normal => Some(NextScene::Overlay(Box::new(EndingScene::new(
                             self.res.background.clone(),
@@ -723,6 +979,16 @@ impl Scene for GameScene {
                         )?))),
                         GameMode::TweakOffset => Some(NextScene::PopWithResult(Box::new(None::<f32>))),
                         GameMode::Exercise => None,
+                        GameMode::Calibrate => {
+                            if !self.calibrated {
+                                self.calibrated = true;
+                                match self.judge.result().suggested_offset() {
+                                    Some(offset) => self.info_offset += offset * self.res.config.speed,
+                                    None => show_message("No hits available for calibration"),
+                                }
+                            }
+                            None
+                        }
                     };
                 }
                 self.res.alpha = 1. - (t / AFTER_TIME).min(1.).powi(2);
@@ -731,9 +997,38 @@ impl Scene for GameScene {
         };
         let time = (time - offset).max(0.);
         self.res.time = time;
-        if !tm.paused() && self.pause_rewind.is_none() {
+        let stepped = self.debug.as_mut().map_or(false, |it| std::mem::take(&mut it.stepped));
+        if (!tm.paused() || stepped) && self.pause_rewind.is_none() {
             self.gl.quad_gl.viewport(self.res.camera.viewport);
-            self.judge.update(&mut self.res, &mut self.chart, &mut self.bad_notes);
+            if let Some((replay, next)) = &mut self.playback {
+                // feed every recorded frame up to now, in the order they were judged
+                while let Some(frame) = replay.frames.get(*next).filter(|it| it.time <= time) {
+                    self.res.time = frame.time;
+                    self.judge
+                        .update_with(&mut self.res, &mut self.chart, &mut self.bad_notes, frame.input.clone());
+                    self.chart.update_lines(&mut self.res);
+                    *next += 1;
+                }
+                self.res.time = time;
+            } else {
+                let input = if self.res.config.autoplay {
+                    FrameInput::default()
+                } else {
+                    let mut input = Judge::frame_input();
+                    for source in &mut self.inputs {
+                        source.apply(&mut input, &self.res, &mut self.chart);
+                    }
+                    input
+                };
+                if let Some(replay) = &mut self.replay {
+                    replay.config.aspect_ratio = self.res.aspect_ratio;
+                    replay.push(time, &input);
+                }
+                self.judge.update_with(&mut self.res, &mut self.chart, &mut self.bad_notes, input);
+            }
+            if self.practice.base_counts.is_none() {
+                self.practice.base_counts = Some(self.judge.counts);
+            }
             self.gl.quad_gl.viewport(None);
         }
         self.res.judge_line_color = if self.judge.counts[2] + self.judge.counts[3] == 0 {
@@ -782,36 +1077,16 @@ impl Scene for GameScene {
             e.update(&self.res);
         }
         if let Some((id, text)) = take_input() {
-            let parse_time = |s: &str| -> Option<f32> {
-                if s.is_empty() {
-                    return None;
-                }
-                let r = s.split(':').collect::<Vec<_>>();
-                if r.len() > 3 {
-                    return None;
-                }
-                let mut iter = r.into_iter().rev();
-                let mut res = iter.next().unwrap().parse::<f32>().ok()?;
-                if res < 0. {
-                    return None;
-                }
-                if let Some(mins) = iter.next() {
-                    res += mins.parse::<u32>().ok()? as f32 * 60.;
-                }
-                if let Some(hrs) = iter.next() {
-                    res += hrs.parse::<u32>().ok()? as f32 * 3600.;
-                }
-                Some(res)
-            };
             let offset = self.offset().min(0.);
             #[allow(clippy::manual_clamp)]
             match id.as_str() {
                 "exercise_start" => {
-                    if let Some(t) = parse_time(&text) {
+                    if let Some(t) = text.trim().parse::<f32>().ok().map(|beat| self.from_beat(beat)) {
                         if !(offset..self.res.track_length.min(self.exercise_range.end - 3.).max(offset)).contains(&t) {
                             show_message("时间不在范围内");
                         } else {
                             self.exercise_range.start = t;
+                            self.practice.restart();
                             show_message("设置成功");
                         }
                     } else {
@@ -819,17 +1094,43 @@ impl Scene for GameScene {
                     }
                 }
                 "exercise_end" => {
-                    if let Some(t) = parse_time(&text) {
+                    if let Some(t) = text.trim().parse::<f32>().ok().map(|beat| self.from_beat(beat)) {
                         if !((self.exercise_range.start + 3.).max(offset).min(self.res.track_length)..self.res.track_length).contains(&t) {
                             show_message("时间不在范围内");
                         } else {
                             self.exercise_range.end = t;
+                            self.practice.restart();
                             show_message("设置成功");
                         }
                     } else {
                         show_message("格式有误");
                     }
                 }
+                "practice_name" => {
+                    let name = text.trim().to_owned();
+                    if name.is_empty() {
+                        show_message("The name cannot be empty");
+                    } else {
+                        let section = PracticeSection {
+                            start: self.to_beat(self.exercise_range.start),
+                            end: self.to_beat(self.exercise_range.end),
+                            name,
+                        };
+                        let sections = &mut self.practice.sections;
+                        let index = match sections.iter().position(|it| it.name == section.name) {
+                            Some(index) => {
+                                sections[index] = section;
+                                index
+                            }
+                            None => {
+                                sections.push(section);
+                                sections.len() - 1
+                            }
+                        };
+                        self.practice.current = Some(index);
+                        show_message("Section saved");
+                    }
+                }
                 _ => return_input(id, text),
             }
         }
@@ -839,11 +1140,11 @@ impl Scene for GameScene {
     fn touch(&mut self, tm: &mut TimeManager, touch: &Touch) -> Result<bool> {
         if self.mode == GameMode::Exercise && tm.paused() {
             if self.exercise_btns.0.touch(touch) {
-                request_input("exercise_start", &fmt_time(self.exercise_range.start));
+                request_input("exercise_start", &fmt_beat(self.to_beat(self.exercise_range.start)));
                 return Ok(true);
             }
             if self.exercise_btns.1.touch(touch) {
-                request_input("exercise_end", &fmt_time(self.exercise_range.end));
+                request_input("exercise_end", &fmt_beat(self.to_beat(self.exercise_range.end)));
                 return Ok(true);
             }
         }
@@ -900,7 +1201,7 @@ impl Scene for GameScene {
         self.ui(ui, tm)?;
         self.overlay_ui(ui, tm)?;
 
-        if self.mode == GameMode::TweakOffset {
+        if self.mode == GameMode::TweakOffset || self.calibrated {
             push_camera_state();
             self.gl.quad_gl.viewport(None);
             set_camera(&Camera2D {
@@ -971,8 +1272,9 @@ impl Scene for GameScene {
             }
             tm.speed = 1.0;
             match self.mode {
-                GameMode::Normal | GameMode::Exercise => NextScene::Pop,
-                GameMode::TweakOffset => NextScene::PopWithResult(Box::new(None::<f32>)),
+                GameMode::Normal => NextScene::Pop,
+                GameMode::Exercise => NextScene::PopWithResult(Box::new(self.practice.sections.clone())),
+                GameMode::TweakOffset | GameMode::Calibrate => NextScene::PopWithResult(Box::new(None::<f32>)),
             }
         } else if let Some(next_scene) = self.next_scene.take() {
             tm.speed = 1.0;
@@ -0,0 +1,102 @@
+use crate::{
+    core::{Chart, Point, Resource},
+    judge::Judge,
+    ui::Ui,
+};
+use macroquad::prelude::*;
+use std::sync::atomic::AtomicBool;
+
+/// If set, games show a seekable timeline and can be stepped frame by frame while paused.
+pub static DEBUG_VIEW: AtomicBool = AtomicBool::new(false);
+
+/// Time of a single step while paused.
+pub(super) const FRAME_STEP: f32 = 1. / 60.;
+
+const TIMELINE_WIDTH: f32 = 0.8;
+const TIMELINE_HEIGHT: f32 = 0.02;
+
+#[derive(Default)]
+pub(super) struct DebugView {
+    /// Whether each line is labelled and its object listed
+    pub show_lines: bool,
+    // the touch dragging the timeline
+    pub press: Option<u64>,
+    // set when a frame has been stepped, so that the judge runs even though the game is paused
+    pub stepped: bool,
+}
+
+impl DebugView {
+    /// Draws the timeline at the bottom of the screen, returning the time the user dragged it to, if any.
+    pub fn timeline(&mut self, ui: &mut Ui, range: (f32, f32), time: f32, beat: f32) -> Option<f32> {
+        let y = ui.top - 0.08;
+        let (start, end) = range;
+        let len = (end - start).max(1e-3);
+        let r = Rect::new(-TIMELINE_WIDTH, y - TIMELINE_HEIGHT / 2., TIMELINE_WIDTH * 2., TIMELINE_HEIGHT);
+        ui.fill_rect(r, Color::new(1., 1., 1., 0.3));
+        let cur = -TIMELINE_WIDTH + (time - start).clamp(0., len) / len * TIMELINE_WIDTH * 2.;
+        ui.fill_rect(Rect::new(-TIMELINE_WIDTH, r.y, cur + TIMELINE_WIDTH, r.h), WHITE);
+        ui.fill_circle(cur, y, 0.015, WHITE);
+        ui.text(format!("{:.3}s · beat {:.2}", time, beat))
+            .pos(0., r.y - 0.01)
+            .anchor(0.5, 1.)
+            .size(0.4)
+            .draw();
+
+        if self.press.is_none() {
+            let r = ui.rect_to_global(r.feather(0.02));
+            self.press = Judge::get_touches()
+                .iter()
+                .find(|it| it.phase == TouchPhase::Started && r.contains(it.position))
+                .map(|it| it.id);
+        }
+        let id = self.press?;
+        let touch = Judge::get_touches().into_iter().rfind(|it| it.id == id)?;
+        if matches!(touch.phase, TouchPhase::Cancelled | TouchPhase::Ended) {
+            self.press = None;
+        }
+        let x = ui.to_local((touch.position.x, touch.position.y)).0;
+        Some(start + ((x + TIMELINE_WIDTH) / (TIMELINE_WIDTH * 2.)).clamp(0., 1.) * len)
+    }
+
+    /// Labels every line where it is and lists the current values of their objects.
+    pub fn lines(&self, ui: &mut Ui, res: &Resource, chart: &Chart) {
+        if !self.show_lines {
+            return;
+        }
+        for (index, line) in chart.lines.iter().enumerate() {
+            let p = line.now_transform(res, &chart.lines).transform_point(&Point::origin());
+            ui.fill_circle(p.x, -p.y, 0.006, YELLOW);
+            ui.text(index.to_string())
+                .pos(p.x + 0.01, -p.y)
+                .anchor(0., 1.)
+                .size(0.35)
+                .color(YELLOW)
+                .draw();
+        }
+        let size = 0.32;
+        let x = -0.98;
+        let mut y = -ui.top + 0.12;
+        let bottom = ui.top - 0.15;
+        let r = ui.text("line parent z alpha rotation translation").pos(x, y).size(size).draw();
+        y += r.h + 0.005;
+        for (index, line) in chart.lines.iter().enumerate() {
+            if y > bottom {
+                ui.text(format!("… {} more", chart.lines.len() - index)).pos(x, y).size(size).draw();
+                break;
+            }
+            let object = &line.object;
+            let translation = object.translation.now();
+            let text = format!(
+                "#{index} {} {} {:.2} {:.1}° ({:.3}, {:.3})",
+                line.parent.map_or_else(|| "-".to_owned(), |it| format!("#{it}")),
+                line.z_index,
+                object.alpha.now_opt().unwrap_or(1.),
+                object.rotation.now(),
+                translation.x,
+                translation.y
+            );
+            let r = ui.text(text).pos(x, y).size(size).draw();
+            y += r.h + 0.005;
+        }
+    }
+}
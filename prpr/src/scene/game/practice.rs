@@ -0,0 +1,89 @@
+use crate::judge::Judge;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// A named A–B section of a chart, in beats.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PracticeSection {
+    pub name: String,
+    pub start: f32,
+    pub end: f32,
+}
+
+/// Saved sections offered in [super::GameMode::Exercise]. The game pops with the edited list as its result.
+pub static PRACTICE_SECTIONS: Mutex<Vec<PracticeSection>> = Mutex::new(Vec::new());
+
+pub(super) const DEFAULT_LOOPS: u32 = 4;
+
+/// Looping state of [super::GameMode::Exercise].
+pub(super) struct Practice {
+    pub sections: Vec<PracticeSection>,
+    /// The saved section being practiced, if the range came from one
+    pub current: Option<usize>,
+    /// Number of loops, stepping the speed from `base_speed` up to 1
+    pub loops: u32,
+    pub base_speed: f32,
+    pub loop_index: u32,
+    // counts right after the first judge update of a loop, when the notes before the section have been missed
+    pub base_counts: Option<[u32; 4]>,
+    /// (speed, accuracy) of each finished loop
+    pub history: Vec<(f32, Option<f64>)>,
+}
+
+impl Practice {
+    pub fn new(sections: Vec<PracticeSection>, base_speed: f32) -> Self {
+        Self {
+            sections,
+            current: None,
+            loops: DEFAULT_LOOPS,
+            base_speed,
+            loop_index: 0,
+            base_counts: None,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        if self.loops <= 1 || self.base_speed >= 1. {
+            return self.base_speed;
+        }
+        self.base_speed + (1. - self.base_speed) * self.loop_index.min(self.loops - 1) as f32 / (self.loops - 1) as f32
+    }
+
+    /// Accuracy of the notes judged since the loop started, if any.
+    pub fn loop_accuracy(&self, judge: &Judge) -> Option<f64> {
+        let base = self.base_counts.unwrap_or_default();
+        let counts: Vec<_> = judge.counts.iter().zip(base).map(|(now, base)| now.saturating_sub(base)).collect();
+        let judged: u32 = counts.iter().sum();
+        if judged == 0 {
+            return None;
+        }
+        Some((counts[0] as f64 + counts[1] as f64 * judge.rules.good_accuracy) / judged as f64)
+    }
+
+    /// Records the loop that just ended and moves on. Returns whether that was the last one, in which case it starts over.
+    pub fn finish_loop(&mut self, judge: &Judge) -> bool {
+        self.history.push((self.speed(), self.loop_accuracy(judge)));
+        self.loop_index += 1;
+        if self.loop_index >= self.loops {
+            self.loop_index = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Starts counting loops over, e.g. after the range changed.
+    pub fn restart(&mut self) {
+        self.loop_index = 0;
+        self.base_counts = None;
+    }
+}
+
+pub(super) fn fmt_beat(beat: f32) -> String {
+    if (beat - beat.round()).abs() < 1e-3 {
+        format!("{:.0}", beat.round())
+    } else {
+        format!("{beat:.2}")
+    }
+}
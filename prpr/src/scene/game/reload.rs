@@ -0,0 +1,79 @@
+use crate::{
+    core::Chart,
+    ext::{poll_future, LocalTask},
+    fs::{load_info, ExternalFileSystem, FileSystem},
+    info::ChartInfo,
+    parse::load_chart,
+};
+use anyhow::Result;
+use std::{ops::DerefMut, path::PathBuf, sync::atomic::AtomicBool, time::SystemTime};
+
+/// If set, games loaded from a directory reload the chart whenever the chart file, `extra.json` or `info.yml` changes.
+pub static HOT_RELOAD: AtomicBool = AtomicBool::new(false);
+
+const CHECK_INTERVAL: f64 = 0.5;
+
+pub(super) struct ChartWatcher {
+    fs: Box<dyn FileSystem>,
+    dir: PathBuf,
+    files: Vec<String>,
+    stamps: Vec<Option<SystemTime>>,
+    last_check: f64,
+    task: LocalTask<Result<(ChartInfo, Chart)>>,
+}
+
+impl ChartWatcher {
+    /// Watches the chart in `fs`, or returns none if it doesn't come from a directory.
+    pub fn new(fs: &mut Box<dyn FileSystem>, info: &ChartInfo) -> Option<Self> {
+        let dir = fs.as_any().downcast_mut::<ExternalFileSystem>()?.0.clone();
+        let mut res = Self {
+            fs: fs.clone_box(),
+            dir,
+            files: Vec::new(),
+            stamps: Vec::new(),
+            last_check: 0.,
+            task: None,
+        };
+        res.watch(info);
+        Some(res)
+    }
+
+    fn watch(&mut self, info: &ChartInfo) {
+        self.files = vec![info.chart.clone(), "extra.json".to_owned(), "info.yml".to_owned()];
+        self.stamps = self.stamps();
+    }
+
+    fn stamps(&self) -> Vec<Option<SystemTime>> {
+        self.files
+            .iter()
+            .map(|file| std::fs::metadata(self.dir.join(file)).and_then(|it| it.modified()).ok())
+            .collect()
+    }
+
+    /// Checks the files every now and then, returning the reloaded chart once it's ready.
+    pub fn poll(&mut self, now: f64) -> Option<Result<(ChartInfo, Chart)>> {
+        if let Some(task) = &mut self.task {
+            let result = poll_future(task.as_mut())?;
+            self.task = None;
+            if let Ok((info, _)) = &result {
+                self.watch(info);
+            }
+            return Some(result);
+        }
+        if now - self.last_check < CHECK_INTERVAL {
+            return None;
+        }
+        self.last_check = now;
+        let stamps = self.stamps();
+        if stamps != self.stamps {
+            self.stamps = stamps;
+            let mut fs = self.fs.clone_box();
+            self.task = Some(Box::pin(async move {
+                let info = load_info(fs.deref_mut()).await?;
+                let chart = load_chart(&mut fs, &info).await?;
+                Ok((info, chart))
+            }));
+        }
+        None
+    }
+}
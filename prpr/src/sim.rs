@@ -0,0 +1,127 @@
+use crate::{
+    config::Config,
+    core::{BadNote, Chart, HeadlessResource},
+    info::ChartInfo,
+    judge::{FrameInput, Judge, PlayResult},
+};
+
+/// Steps a chart and its judge through time without a window, GPU or audio device.
+///
+/// Only the judge lines and notes are updated; effects and videos are left alone.
+pub struct Simulation {
+    pub res: HeadlessResource,
+    pub chart: Chart,
+    pub judge: Judge,
+    bad_notes: Vec<BadNote>,
+}
+
+impl Simulation {
+    pub fn new(chart: Chart, config: Config, info: &ChartInfo) -> Self {
+        Self {
+            judge: Judge::new(&chart, config.judge_rules.rules()),
+            res: HeadlessResource::new(config, info),
+            chart,
+            bad_notes: Vec::new(),
+        }
+    }
+
+    /// Advances to `time` (in chart time) and judges the given input.
+    pub fn step(&mut self, time: f32, input: FrameInput) {
+        self.res.time = time;
+        self.judge.update_with(&mut self.res, &mut self.chart, &mut self.bad_notes, input);
+        self.chart.update_lines(&mut self.res);
+        self.bad_notes.clear();
+    }
+
+    /// Runs from the current time until `end_time` at `fps` frames per second without any input.
+    ///
+    /// With `config.autoplay` set, every note is hit perfectly.
+    pub fn run(&mut self, end_time: f32, fps: u32) -> PlayResult {
+        let start = self.res.time;
+        let mut frame = 0;
+        loop {
+            let time = start + frame as f32 / fps as f32;
+            if time > end_time {
+                break;
+            }
+            self.step(time, FrameInput::default());
+            frame += 1;
+        }
+        self.result()
+    }
+
+    pub fn result(&self) -> PlayResult {
+        self.judge.result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::ChartExtra, judge::JudgePreset, parse::parse_pec};
+    use macroquad::prelude::{vec2, Touch, TouchPhase};
+
+    // taps the middle of the screen at `time`, releasing it a frame later
+    fn tap(sim: &mut Simulation, time: f32) {
+        for (time, phase) in [(time, TouchPhase::Started), (time + 1. / 60., TouchPhase::Ended)] {
+            let touch = Touch {
+                id: 0,
+                phase,
+                position: vec2(0., 0.),
+            };
+            sim.step(
+                time,
+                FrameInput {
+                    touches: vec![touch.clone()],
+                    events: vec![touch],
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn autoplay_full_combo() {
+        let chart = parse_pec(include_str!("../tests/fixtures/simple.pec"), ChartExtra::default()).unwrap();
+        let config = Config {
+            autoplay: true,
+            ..Default::default()
+        };
+        let mut sim = Simulation::new(chart, config, &ChartInfo::default());
+        let result = sim.run(6., 60);
+        // the fake note is not counted
+        assert_eq!(result.num_of_notes, 6);
+        assert_eq!(result.counts, [6, 0, 0, 0]);
+        assert_eq!(result.max_combo, 6);
+        assert_eq!(result.score, 1000000);
+        assert_eq!(sim.res.played_sfx.len(), 6);
+    }
+
+    #[test]
+    fn judge_presets() {
+        // the first two clicks are at 0.5s and 1s, hit 0.12s and 0.06s late
+        let play = |judge_rules| {
+            let chart = parse_pec(include_str!("../tests/fixtures/simple.pec"), ChartExtra::default()).unwrap();
+            let config = Config {
+                judge_rules,
+                ..Default::default()
+            };
+            let mut sim = Simulation::new(chart, config, &ChartInfo::default());
+            tap(&mut sim, 0.62);
+            tap(&mut sim, 1.06);
+            sim.result()
+        };
+
+        let official = play(JudgePreset::Official);
+        assert_eq!(official.rules, JudgePreset::Official);
+        assert_eq!(official.counts, [1, 1, 0, 0]);
+        // a good is worth 65% of a perfect, and max combo gives 10% of the score
+        assert_eq!(official.score, ((0.9 * 1.65 + 0.1 * 2.) / 6. * 1e6f64).round() as u32);
+
+        let strict = play(JudgePreset::Strict);
+        assert_eq!(strict.rules, JudgePreset::Strict);
+        assert_eq!(strict.counts, [0, 1, 1, 0]);
+        // a good is worth half a perfect, and the score is given by accuracy alone
+        assert_eq!(strict.score, (0.5 / 6. * 1e6f64).round() as u32);
+    }
+}
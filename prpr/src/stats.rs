@@ -0,0 +1,175 @@
+use crate::{
+    core::{Chart, NoteKind},
+    fs::{self, FileSystem},
+    parse::load_chart,
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::ops::DerefMut;
+
+/// Width of the windows the notes per second are measured over, in seconds.
+const NPS_WINDOW: f32 = 1.;
+/// The nps curve stops here, so that a stray note long after the rest of the chart can't make it huge.
+const NPS_MAX_TIME: f32 = 3600.;
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteCounts {
+    pub click: u32,
+    pub drag: u32,
+    pub hold: u32,
+    pub flick: u32,
+}
+
+impl NoteCounts {
+    pub fn total(&self) -> u32 {
+        self.click + self.drag + self.hold + self.flick
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartStats {
+    /// Real notes by kind
+    pub notes: NoteCounts,
+    pub fake: u32,
+    /// Real notes hit at the same time as another one, i.e. those with a multi-hint
+    pub chords: u32,
+    /// Time from the first note to the end of the last one, in seconds
+    pub duration: f32,
+    pub average_nps: f32,
+    /// The most notes hit within any window of one second
+    pub peak_nps: f32,
+    /// Notes per second in consecutive windows, the first of which starts at time 0
+    pub nps: Vec<f32>,
+    /// The part of the duration during which at least one hold is being held
+    pub hold_coverage: f32,
+    /// A rough difficulty estimate, meant to be on the same scale as the official levels
+    pub difficulty: f32,
+}
+
+// how much a note weighs in the difficulty estimate compared to a click
+fn weight(kind: &NoteKind, chord: bool) -> f32 {
+    let weight = match kind {
+        NoteKind::Click => 1.,
+        NoteKind::Hold { .. } => 1.1,
+        NoteKind::Flick => 0.7,
+        NoteKind::Drag => 0.4,
+    };
+    if chord {
+        weight * 1.3
+    } else {
+        weight
+    }
+}
+
+/// The largest sum of weights of `notes` (sorted by time) within any window of `NPS_WINDOW`.
+fn peak(notes: &[(f32, f32)]) -> f32 {
+    let (mut sum, mut result, mut start) = (0., 0f32, 0);
+    for &(time, weight) in notes {
+        sum += weight;
+        while notes[start].0 <= time - NPS_WINDOW {
+            sum -= notes[start].1;
+            start += 1;
+        }
+        result = result.max(sum);
+    }
+    result
+}
+
+/// Computes the statistics of a loaded chart.
+pub fn chart_stats(chart: &Chart) -> ChartStats {
+    let mut stats = ChartStats::default();
+    // (time, weight) of every real note
+    let mut notes = Vec::new();
+    let mut holds = Vec::new();
+    let (mut start, mut end) = (f32::INFINITY, f32::NEG_INFINITY);
+    for note in chart.lines.iter().flat_map(|it| it.notes.iter()) {
+        if note.fake {
+            stats.fake += 1;
+            continue;
+        }
+        let counter = match note.kind {
+            NoteKind::Click => &mut stats.notes.click,
+            NoteKind::Drag => &mut stats.notes.drag,
+            NoteKind::Flick => &mut stats.notes.flick,
+            NoteKind::Hold { end_time, .. } => {
+                holds.push((note.time, end_time.max(note.time)));
+                &mut stats.notes.hold
+            }
+        };
+        *counter += 1;
+        if note.multiple_hint {
+            stats.chords += 1;
+        }
+        notes.push((note.time, weight(&note.kind, note.multiple_hint)));
+        start = start.min(note.time);
+        end = end.max(match note.kind {
+            NoteKind::Hold { end_time, .. } => end_time.max(note.time),
+            _ => note.time,
+        });
+    }
+    if notes.is_empty() {
+        return stats;
+    }
+    notes.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let total = notes.len() as f32;
+    stats.duration = end - start;
+    // a chart whose notes are all hit at once spans a single window
+    let span = stats.duration.max(NPS_WINDOW);
+    stats.average_nps = total / span;
+    stats.peak_nps = peak(&notes.iter().map(|it| (it.0, 1.)).collect::<Vec<_>>()) / NPS_WINDOW;
+    stats.nps = vec![0.; (notes.last().unwrap().0.clamp(0., NPS_MAX_TIME) / NPS_WINDOW) as usize + 1];
+    for (time, _) in notes.iter().take_while(|it| it.0 <= NPS_MAX_TIME) {
+        stats.nps[(time.max(0.) / NPS_WINDOW) as usize] += 1. / NPS_WINDOW;
+    }
+
+    holds.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (mut covered, mut last) = (0., f32::NEG_INFINITY);
+    for (hold_start, hold_end) in holds {
+        if hold_end > last {
+            covered += hold_end - hold_start.max(last);
+            last = hold_end;
+        }
+    }
+    stats.hold_coverage = if stats.duration > 0. { covered / stats.duration } else { 0. };
+
+    // weighs the densest part of the chart against the overall density
+    let average = notes.iter().map(|it| it.1).sum::<f32>() / span;
+    let peak = peak(&notes) / NPS_WINDOW;
+    stats.difficulty = 1.1 * (0.35 * peak + 0.65 * average);
+    stats
+}
+
+/// Loads the chart in `fs` and computes its statistics.
+pub async fn load_stats(fs: &mut Box<dyn FileSystem>) -> Result<ChartStats> {
+    let info = fs::load_info(fs.deref_mut()).await?;
+    let chart = load_chart(fs, &info).await?;
+    Ok(chart_stats(&chart))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::ChartExtra, parse::parse_pec, testing::fixture_str};
+
+    #[test]
+    fn simple_chart() {
+        let stats = chart_stats(&parse_pec(&fixture_str("simple.pec"), ChartExtra::default()).unwrap());
+        assert_eq!((stats.notes.click, stats.notes.drag, stats.notes.hold, stats.notes.flick, stats.fake), (3, 1, 1, 1, 1));
+        // the last note is hit at 3.5s
+        assert_eq!(stats.nps.len(), 4);
+        assert_eq!(stats.nps.iter().sum::<f32>(), 6.);
+    }
+
+    #[test]
+    fn outliers_are_left_out_of_the_curve() {
+        // a note at beat 10^7, i.e. about two months in
+        let text = fixture_str("simple.pec") + "n1 0 10000000.00 0.00 1 0\n# 1.00\n& 1.00\n";
+        let stats = chart_stats(&parse_pec(&text, ChartExtra::default()).unwrap());
+        assert_eq!(stats.notes.click, 4);
+        assert_eq!(stats.nps.len(), (NPS_MAX_TIME / NPS_WINDOW) as usize + 1);
+        assert_eq!(stats.nps.iter().sum::<f32>(), 6.);
+    }
+}
@@ -0,0 +1,34 @@
+//! Helpers shared by the unit tests. Fixtures live in `tests/fixtures`.
+
+use crate::fs::{FileSystem, ZipFileSystem};
+use std::{
+    future::Future,
+    io::{Cursor, Write},
+};
+use zip::{write::FileOptions, ZipWriter};
+
+pub fn fixture(name: &str) -> Vec<u8> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/");
+    std::fs::read(format!("{path}{name}")).unwrap_or_else(|err| panic!("Failed to read fixture {name}: {err}"))
+}
+
+pub fn fixture_str(name: &str) -> String {
+    String::from_utf8(fixture(name)).unwrap()
+}
+
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+}
+
+/// An in-memory chart folder holding `files`.
+pub fn zip_fs(files: &[(&str, Vec<u8>)]) -> Box<dyn FileSystem> {
+    let mut buffer = Vec::new();
+    let mut w = ZipWriter::new(Cursor::new(&mut buffer));
+    for (path, data) in files {
+        w.start_file(*path, FileOptions::default()).unwrap();
+        w.write_all(data).unwrap();
+    }
+    w.finish().unwrap();
+    drop(w);
+    Box::new(ZipFileSystem::new(buffer).unwrap())
+}